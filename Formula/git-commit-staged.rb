@@ -30,8 +30,10 @@ class GitCommitStaged < Formula
       system "cargo", "build", "--release"
       bin.install "../target/release/git-commit-staged"
       bin.install "../target/release/git-commit-files"
+      bin.install "../target/release/git-stage-area"
       man1.install "man/git-commit-staged.1"
       man1.install "man/git-commit-files.1"
+      man1.install "man/git-stage-area.1"
     end
   end
 
@@ -3,12 +3,21 @@ use std::fs;
 use std::path::Path;
 use std::process::Command;
 
+// The `--each` values, as `crate::Each` for the staged CLI
+#[allow(dead_code)]
+#[path = "src/series/each.rs"]
+mod each;
+use each::Each;
+
 #[path = "src/staged/cli.rs"]
 mod cli_staged;
 
 #[path = "src/files/cli.rs"]
 mod cli_files;
 
+#[path = "src/stage_area/cli.rs"]
+mod cli_stage_area;
+
 fn main() {
     // Embed git commit hash in version
     let git_hash = Command::new("git")
@@ -35,4 +44,11 @@ fn main() {
     let mut buffer = Vec::new();
     man.render(&mut buffer).expect("failed to render man page");
     fs::write(out_dir.join("git-commit-files.1"), buffer).expect("failed to write man page");
+
+    // Generate man page for git-stage-area
+    let cmd = cli_stage_area::Args::command();
+    let man = clap_mangen::Man::new(cmd);
+    let mut buffer = Vec::new();
+    man.render(&mut buffer).expect("failed to render man page");
+    fs::write(out_dir.join("git-stage-area.1"), buffer).expect("failed to write man page");
 }
@@ -0,0 +1,219 @@
+//! Cleanup of run artifacts on signals and early exits.
+//!
+//! Every file a run creates in `.git` (the index lock and its owner sidecar,
+//! temp indexes, lock queue tickets), and any scratch directory, is
+//! registered here until it is removed or consumed. Index backups are
+//! registered with their git dir instead, and are installed back as its
+//! index through the held lock rather than removed.
+//!
+//! On SIGINT, SIGTERM or SIGHUP, a handler thread forwards the signal to the
+//! running `git commit`, waits briefly for it to exit, removes (or restores)
+//! every registered file, and re-raises the signal so the exit status still
+//! reports it. [`remove_all`] performs the same cleanup on ordinary exits.
+//!
+//! Nothing can be done about SIGKILL; see `git commit-staged unlock --stale`.
+
+use anyhow::{Context, Result};
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use crate::lock::install_index;
+
+/// Signals that trigger cleanup.
+const SIGNALS: [i32; 3] = [SIGINT, SIGTERM, SIGHUP];
+
+/// How long to wait for the child to exit before removing files under it.
+const CHILD_GRACE: Duration = Duration::from_secs(5);
+
+/// A file to clean up.
+struct Artifact {
+    path: PathBuf,
+    /// Install `path` as an index instead of removing it
+    restore: Option<Restore>,
+}
+
+/// Where an index backup goes back to.
+struct Restore {
+    git_dir: PathBuf,
+    /// Per `core.fsync`, read when the backup was taken
+    fsync: bool,
+}
+
+/// Files to clean up, in creation order.
+static ARTIFACTS: Mutex<Vec<Artifact>> = Mutex::new(Vec::new());
+
+/// PID of the running `git commit`, or 0.
+static CHILD: AtomicU32 = AtomicU32::new(0);
+
+/// Install handlers for SIGINT, SIGTERM and SIGHUP.
+///
+/// Call once, at the start of `main`.
+///
+/// # Errors
+/// Returns an error if the handlers or their thread cannot be set up.
+pub fn install_signal_handlers() -> Result<()> {
+    let mut signals = Signals::new(SIGNALS).context("failed to install signal handlers")?;
+    std::thread::Builder::new()
+        .name("signal-cleanup".to_owned())
+        .spawn(move || {
+            if let Some(signal) = signals.forever().next() {
+                terminate(signal);
+            }
+        })
+        .context("failed to start signal handler thread")?;
+    Ok(())
+}
+
+/// Register a file this run created, to be removed if the run is interrupted.
+pub fn register(path: &Path) {
+    artifacts().push(Artifact {
+        path: path.to_path_buf(),
+        restore: None,
+    });
+}
+
+/// Register a backup of `git_dir`'s index, to be installed back as the index
+/// (fsynced if `fsync`) if the run is interrupted.
+pub fn register_backup(backup: &Path, git_dir: &Path, fsync: bool) {
+    artifacts().push(Artifact {
+        path: backup.to_path_buf(),
+        restore: Some(Restore {
+            git_dir: git_dir.to_path_buf(),
+            fsync,
+        }),
+    });
+}
+
+/// Stop tracking a file that has been consumed (e.g. renamed into place).
+pub fn forget(path: &Path) {
+    let mut artifacts = artifacts();
+    if let Some(i) = artifacts.iter().rposition(|a| a.path == path) {
+        artifacts.remove(i);
+    }
+}
+
+/// Remove a registered file (or directory tree) and stop tracking it.
+///
+/// # Errors
+/// Returns the error from removing the file; it is untracked regardless.
+pub fn remove(path: &Path) -> std::io::Result<()> {
+    forget(path);
+    remove_path(path)
+}
+
+/// Remove (or restore) every file still registered, newest first.
+///
+/// Call on the way out of `main`, after the run's guards have dropped.
+pub fn remove_all() {
+    let mut artifacts = artifacts();
+    remove_files(&artifacts);
+    artifacts.clear();
+}
+
+/// Marks a child process as the target for forwarded signals while alive.
+pub struct ChildGuard(());
+
+/// Forward termination signals to `pid` until the guard is dropped.
+#[must_use]
+pub fn track_child(pid: u32) -> ChildGuard {
+    CHILD.store(pid, Ordering::SeqCst);
+    ChildGuard(())
+}
+
+impl Drop for ChildGuard {
+    fn drop(&mut self) {
+        CHILD.store(0, Ordering::SeqCst);
+    }
+}
+
+fn artifacts() -> MutexGuard<'static, Vec<Artifact>> {
+    ARTIFACTS.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn remove_files(artifacts: &[Artifact]) {
+    // Newest first: an owner sidecar goes before its lock, and a backup is
+    // restored while the lock protecting it is still held
+    for artifact in artifacts.iter().rev() {
+        // A backup that can't be installed stays for `doctor` to report
+        let _ = artifact.restore.as_ref().map_or_else(
+            || remove_path(&artifact.path).map_err(anyhow::Error::from),
+            |restore| install_index(&restore.git_dir, &artifact.path, restore.fsync),
+        );
+    }
+}
+
+fn remove_path(path: &Path) -> std::io::Result<()> {
+    if path.is_dir() {
+        std::fs::remove_dir_all(path)
+    } else {
+        std::fs::remove_file(path)
+    }
+}
+
+/// Whether our child `pid` has exited, without reaping it.
+///
+/// The main thread may not reap it promptly: it also waits for EOF on the
+/// child's output, which hook processes outliving `git commit` can hold open.
+fn child_exited(pid: libc::pid_t) -> bool {
+    // SAFETY: siginfo_t is plain data; waitid only writes into it.
+    unsafe {
+        let mut info: libc::siginfo_t = std::mem::zeroed();
+        let flags = libc::WEXITED | libc::WNOWAIT | libc::WNOHANG;
+        let Ok(id) = libc::id_t::try_from(pid) else {
+            return true;
+        };
+        // Fails with ECHILD once the main thread has reaped it
+        libc::waitid(libc::P_PID, id, &raw mut info, flags) != 0 || info.si_pid() != 0
+    }
+}
+
+/// Handle a termination signal: forward, clean up, re-raise.
+fn terminate(signal: i32) -> ! {
+    // Held until exit, so the main thread can't race us to its own exit path
+    // (with a status that hides the signal) once the child is gone.
+    let artifacts = artifacts();
+
+    let child = CHILD.load(Ordering::SeqCst);
+    if let Ok(pid @ 1..) = libc::pid_t::try_from(child) {
+        // SAFETY: kill has no memory-safety preconditions.
+        unsafe { libc::kill(pid, signal) };
+        let start = Instant::now();
+        while !child_exited(pid) && start.elapsed() < CHILD_GRACE {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    remove_files(&artifacts);
+
+    let _ = signal_hook::low_level::emulate_default_handler(signal);
+    // Not reached for the signals we handle; exit as a shell would report it
+    std::process::exit(128 + signal);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn remove_untracks_and_deletes() {
+        let dir = TempDir::new().unwrap();
+        let kept = dir.path().join("kept");
+        let removed = dir.path().join("removed");
+        std::fs::write(&kept, "").unwrap();
+        std::fs::write(&removed, "").unwrap();
+
+        register(&kept);
+        register(&removed);
+        forget(&kept);
+        remove(&removed).unwrap();
+
+        assert!(kept.exists());
+        assert!(!removed.exists());
+        assert!(!artifacts().iter().any(|a| a.path.starts_with(dir.path())));
+    }
+}
@@ -0,0 +1,258 @@
+//! CODEOWNERS files (`--split-by-owners`).
+//!
+//! Both GitHub and GitLab syntax are read. Each rule is a pattern followed
+//! by owners (`@user`, `@org/team`, or an email); the last rule matching a
+//! path wins, and a rule without owners leaves the path unowned. Patterns
+//! follow `.gitignore`: a `/` anywhere but at the end anchors the pattern at
+//! the top of the repository, a trailing `/` matches directories only, `*`
+//! and `?` stop at `/`, and `**` crosses it. As on GitHub, `dir/*` matches
+//! only the files directly in `dir`.
+//!
+//! GitLab `[Section]` headers (also `^[Section][2] @default-owners`) start
+//! a new section: a path's owners are those of the last matching rule in
+//! each section, together, and rules without owners take their section's
+//! default owners.
+
+use anyhow::{Context, Result};
+use git2::Repository;
+use std::path::{Path, PathBuf};
+
+/// Where CODEOWNERS is looked for, in order.
+pub const LOCATIONS: [&str; 4] = [
+    ".github/CODEOWNERS",
+    "CODEOWNERS",
+    "docs/CODEOWNERS",
+    ".gitlab/CODEOWNERS",
+];
+
+/// The rules of a CODEOWNERS file.
+#[derive(Debug, Default)]
+pub struct CodeOwners {
+    /// Rules by section; rules before the first header form a section
+    sections: Vec<Vec<Rule>>,
+}
+
+#[derive(Debug)]
+struct Rule {
+    /// Pattern, without leading and trailing `/`
+    glob: String,
+    /// Matched from the top of the repository only
+    anchored: bool,
+    /// Matches directories (and so everything below them) only
+    dir_only: bool,
+    owners: Vec<String>,
+}
+
+impl CodeOwners {
+    /// Parse CODEOWNERS text.
+    #[must_use]
+    pub fn parse(text: &str) -> Self {
+        let mut sections = Vec::new();
+        let mut rules = Vec::new();
+        let mut defaults: Vec<String> = Vec::new();
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(owners) = section_header(line) {
+                sections.push(std::mem::take(&mut rules));
+                defaults = owners;
+                continue;
+            }
+
+            let mut tokens = tokens(line).into_iter();
+            let Some(pattern) = tokens.next() else {
+                continue;
+            };
+            let mut owners: Vec<String> = tokens.take_while(|t| !t.starts_with('#')).collect();
+            if owners.is_empty() {
+                owners.clone_from(&defaults);
+            }
+            let dir_only = pattern.ends_with('/');
+            let pattern = pattern.trim_end_matches('/');
+            let anchored = pattern.contains('/');
+            let rule = Rule {
+                glob: pattern.trim_start_matches('/').to_owned(),
+                anchored,
+                dir_only,
+                owners,
+            };
+            rules.push(rule);
+        }
+        sections.push(rules);
+        Self { sections }
+    }
+
+    /// Read and parse a CODEOWNERS file.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be read.
+    pub fn read(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        Ok(Self::parse(&text))
+    }
+
+    /// Find the repository's CODEOWNERS file in the working tree (see
+    /// [`LOCATIONS`]) and read it.
+    ///
+    /// # Errors
+    /// Returns an error if there is none, or it cannot be read.
+    pub fn find(repo: &Repository) -> Result<(PathBuf, Self)> {
+        let workdir = repo.workdir().context("bare repository has no CODEOWNERS")?;
+        let path = LOCATIONS
+            .iter()
+            .map(|location| workdir.join(location))
+            .find(|path| path.is_file())
+            .with_context(|| format!("no CODEOWNERS file (looked in {})", LOCATIONS.join(", ")))?;
+        let owners = Self::read(&path)?;
+        Ok((path, owners))
+    }
+
+    /// Owners of a repo-relative path, sorted; empty if unowned.
+    #[must_use]
+    pub fn owners(&self, path: &str) -> Vec<String> {
+        let mut owners: Vec<String> = self
+            .sections
+            .iter()
+            .filter_map(|rules| rules.iter().rev().find(|rule| rule.matches(path)))
+            .flat_map(|rule| rule.owners.iter().cloned())
+            .collect();
+        owners.sort();
+        owners.dedup();
+        owners
+    }
+}
+
+impl Rule {
+    fn matches(&self, path: &str) -> bool {
+        // The path itself, and (unless the pattern ends in `/*`) each of its
+        // directories, so that matching a directory matches what is below it
+        let files_only = self.glob == "*" || self.glob.ends_with("/*");
+        let dirs = path
+            .match_indices('/')
+            .map(|(end, _)| end)
+            .filter(|_| !files_only);
+        let file = (!self.dir_only).then_some(path.len());
+        dirs.chain(file).any(|end| self.matches_exactly(&path[..end]))
+    }
+
+    fn matches_exactly(&self, path: &str) -> bool {
+        let glob = self.glob.as_bytes();
+        if self.anchored {
+            return glob_match(glob, path.as_bytes());
+        }
+        // Unanchored: may start at any directory
+        std::iter::once(0)
+            .chain(path.match_indices('/').map(|(i, _)| i + 1))
+            .any(|start| glob_match(glob, &path.as_bytes()[start..]))
+    }
+}
+
+/// Owners of a GitLab section header line, or `None` if it isn't one.
+fn section_header(line: &str) -> Option<Vec<String>> {
+    let rest = line.strip_prefix('^').unwrap_or(line).strip_prefix('[')?;
+    let (_, rest) = rest.split_once(']')?;
+    // Optional number of required approvals
+    let rest = match rest.strip_prefix('[') {
+        Some(count) => count.split_once(']')?.1,
+        None => rest,
+    };
+    Some(
+        rest.split_whitespace()
+            .take_while(|t| !t.starts_with('#'))
+            .map(str::to_owned)
+            .collect(),
+    )
+}
+
+/// Whitespace-separated tokens, where `\` escapes the next character (kept
+/// for the pattern matcher).
+fn tokens(line: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut token = String::new();
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                token.push(c);
+                token.extend(chars.next());
+            }
+            c if c.is_whitespace() => {
+                if !token.is_empty() {
+                    tokens.push(std::mem::take(&mut token));
+                }
+            }
+            c => token.push(c),
+        }
+    }
+    if !token.is_empty() {
+        tokens.push(token);
+    }
+    tokens
+}
+
+/// Match a glob: `*` and `?` within a path component, `**` across them.
+fn glob_match(glob: &[u8], text: &[u8]) -> bool {
+    match glob.split_first() {
+        None => text.is_empty(),
+        // `**/` also matches no directory at all
+        Some((b'*', [b'*', rest @ ..])) => {
+            rest.strip_prefix(b"/").is_some_and(|after| glob_match(after, text))
+                || (0..=text.len()).any(|i| glob_match(rest, &text[i..]))
+        }
+        Some((b'*', rest)) => (0..=text.len())
+            .take_while(|&i| i == 0 || text[i - 1] != b'/')
+            .any(|i| glob_match(rest, &text[i..])),
+        Some((b'?', rest)) => {
+            text.first().is_some_and(|&c| c != b'/') && glob_match(rest, &text[1..])
+        }
+        Some((b'\\', [escaped, rest @ ..])) => {
+            text.first() == Some(escaped) && glob_match(rest, &text[1..])
+        }
+        Some((c, rest)) => text.first() == Some(c) && glob_match(rest, &text[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn last_matching_rule_wins() {
+        let owners = CodeOwners::parse(
+            "# Default\n\
+             *                @org/core\n\
+             *.md             @org/docs  # prose\n\
+             /build/          @org/build\n\
+             docs/*           @org/docs @alice\n\
+             apps/**/test     @org/qa\n\
+             /vendor/\n",
+        );
+        assert_eq!(owners.owners("src/main.rs"), ["@org/core"]);
+        assert_eq!(owners.owners("src/README.md"), ["@org/docs"]);
+        assert_eq!(owners.owners("build/out/x"), ["@org/build"]);
+        assert_eq!(owners.owners("src/build/x"), ["@org/core"], "anchored");
+        assert_eq!(owners.owners("docs/index.html"), ["@alice", "@org/docs"]);
+        assert_eq!(owners.owners("docs/api/index.html"), ["@org/core"], "not nested");
+        assert_eq!(owners.owners("apps/web/test/a.js"), ["@org/qa"]);
+        assert_eq!(owners.owners("apps/test/a.js"), ["@org/qa"]);
+        assert!(owners.owners("vendor/lib.c").is_empty(), "unowned");
+    }
+
+    #[test]
+    fn gitlab_sections_combine() {
+        let owners = CodeOwners::parse(
+            "* @core\n\
+             \n\
+             [Docs] @docs-team\n\
+             *.md\n\
+             ^[Security][2] @security\n\
+             /auth/ @auth-team\n",
+        );
+        assert_eq!(owners.owners("README.md"), ["@core", "@docs-team"]);
+        assert_eq!(owners.owners("auth/login.rs"), ["@auth-team", "@core"]);
+        assert_eq!(owners.owners("src/lib.rs"), ["@core"]);
+    }
+}
@@ -4,13 +4,42 @@
 //! preserving full git commit compatibility (--amend, --fixup, -C, etc.).
 
 use anyhow::{bail, Context, Result};
-use std::path::Path;
-use std::process::Command;
+use git2::{Oid, Repository};
+use std::path::{Path, PathBuf};
+use std::process::{Output, Stdio};
+use std::time::SystemTime;
 
-/// Result of a successful commit operation.
+use crate::cleanup;
+use crate::common_dir;
+use crate::error::Error;
+use crate::session::{git_commit_command, Session};
+use crate::{hook_changes, plumbing, verify, StagedEntry};
+
+/// Result of a successful commit operation, read back from the repository.
+#[derive(Debug, Clone)]
 pub struct CommitOutput {
-    /// The new commit's SHA (short form from git output).
-    pub commit_sha: String,
+    /// The new commit
+    pub commit: Oid,
+    /// The new commit's tree
+    pub tree: Oid,
+    /// HEAD before the commit (`None` on an unborn branch)
+    pub old_head: Option<Oid>,
+    /// Ref the commit updated: a full branch name, or `HEAD` when detached
+    pub reference: String,
+    /// Short branch name (`None` when detached)
+    pub branch: Option<String>,
+    /// Whether the commit replaced the old HEAD instead of building on it (`--amend`)
+    pub amend: bool,
+}
+
+impl CommitOutput {
+    /// Abbreviated commit ID (7 hex digits).
+    #[must_use]
+    pub fn short_id(&self) -> String {
+        let mut id = self.commit.to_string();
+        id.truncate(7);
+        id
+    }
 }
 
 /// Commit from a temporary index file while caller holds index.lock.
@@ -18,57 +47,222 @@ pub struct CommitOutput {
 /// Spawns `git commit` as a subprocess with `GIT_INDEX_FILE` set to the temp index.
 /// Git will lock the temp index (not the main one), so no conflict with our lock.
 ///
+/// If `$GIT_PARTIAL_SESSION` is set, the session's author identity and
+/// trailers are applied (see [`crate::session`]).
+///
+/// Signals received meanwhile are forwarded to `git commit` (see
+/// [`crate::cleanup`]).
+///
+/// The result is read back from the repository (HEAD before and after), not
+/// scraped from git's output, which varies with `-q`, config and locale.
+///
 /// **Caller must hold index.lock** for the entire operation.
 ///
 /// # Errors
-/// - Git commit fails (hooks reject, no message, etc.)
+/// - Repository or HEAD cannot be read
+/// - Session config cannot be read
+/// - Git commit fails: [`Error::HookRejected`] or [`Error::CommitFailed`]
+/// - Git commit exits 0 without committing: [`Error::CommitFailed`]
 pub fn do_commit(
     temp_index_path: &Path,
     passthrough_args: &[String],
 ) -> Result<CommitOutput> {
-    let output = Command::new("git")
-        .arg("commit")
+    let repo = Repository::open_from_env().context("failed to open repository")?;
+    let session = Session::from_env(&repo)?;
+    let (reference, old_head) = read_head(&repo)?;
+    let editmsg_before = editmsg_stamp(&repo);
+
+    let child = git_commit_command(session.as_ref())
         .args(passthrough_args)
         .env("GIT_INDEX_FILE", temp_index_path)
-        .output()
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
         .context("failed to run git commit")?;
 
+    // Termination signals reach git commit before we clean up under it
+    let tracked = cleanup::track_child(child.id());
+    let output = child.wait_with_output();
+    drop(tracked);
+    let output = output.context("failed to wait for git commit")?;
+
     // Clean up temp index regardless of outcome
-    let _ = std::fs::remove_file(temp_index_path);
+    let _ = cleanup::remove(temp_index_path);
 
     if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        bail!("git commit failed: {stderr}");
+        return Err(commit_failure(&repo, passthrough_args, &output, editmsg_before)?.into());
+    }
+
+    commit_output(&repo, reference, old_head)
+}
+
+/// Checks on the tree the commit hooks leave, made with `--plumbing` before
+/// the branch moves.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TreeChecks<'a> {
+    /// `--fold-hook-changes`: the tree before the hooks, and the entries
+    /// they may change
+    pub scope: Option<(Oid, &'a [StagedEntry])>,
+    /// `--verify-cmd`, and whether to keep its scratch directory
+    pub verify: Option<(&'a str, bool)>,
+}
+
+/// Commit the temp index with `git commit`, or with `plumbing` through git2,
+/// making `checks` once the hooks have run.
+///
+/// `git commit` can't be stopped between its hooks and the branch update,
+/// so `checks.verify` needs `plumbing`; without it, `checks.scope` is left
+/// to [`crate::hook_changes::fold`] to report.
+///
+/// **Caller must hold index.lock** for the entire operation.
+///
+/// # Errors
+/// As for [`do_commit`] or [`crate::plumbing::commit_checked`], or
+/// [`Error::VerifyFailed`] or an error from a check, with nothing committed.
+pub fn commit_temp_index(
+    repo: &Repository,
+    temp_index_path: &Path,
+    passthrough_args: &[String],
+    plumbing: bool,
+    checks: TreeChecks,
+) -> Result<CommitOutput> {
+    if !plumbing {
+        if checks.verify.is_some() {
+            bail!("--verify-cmd needs --plumbing, to check the tree after the commit hooks");
+        }
+        return do_commit(temp_index_path, passthrough_args);
     }
+    let check = |tree| {
+        if let Some((intended, entries)) = checks.scope {
+            hook_changes::check_scope(repo, intended, tree, entries)?;
+        }
+        if let Some((command, keep)) = checks.verify {
+            verify::verify(repo, tree, command, keep)?;
+        }
+        Ok(())
+    };
+    plumbing::commit_checked(temp_index_path, passthrough_args, check)
+}
+
+/// When `COMMIT_EDITMSG` was last written.
+///
+/// `git commit` writes it right after the `pre-commit` hook passes, so
+/// comparing stamps taken before and after tells which phase failed.
+pub(crate) fn editmsg_stamp(repo: &Repository) -> Option<SystemTime> {
+    std::fs::metadata(repo.path().join("COMMIT_EDITMSG"))
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Classify a `git commit` failure as [`Error::HookRejected`] or
+/// [`Error::CommitFailed`], by the phase it failed in.
+///
+/// If `COMMIT_EDITMSG` wasn't written since `editmsg_before` (see
+/// [`editmsg_stamp`]), git stopped at or before `pre-commit`: the hook
+/// rejected the commit if it is enabled and git exited 1 (its own fatal
+/// errors exit 128 or 129). Otherwise only `prepare-commit-msg` and
+/// `commit-msg` can have rejected it, and as git exits 1 for its own failures
+/// at that point too, the commit counts as rejected if one of them is enabled
+/// and git printed none of its own failure messages. When unsure, the
+/// failure is reported as [`Error::CommitFailed`]; neither is retryable.
+pub(crate) fn commit_failure(
+    repo: &Repository,
+    passthrough_args: &[String],
+    output: &Output,
+    editmsg_before: Option<SystemTime>,
+) -> Result<Error> {
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+    let no_verify = passthrough_args.iter().any(|arg| arg == "--no-verify" || arg == "-n");
+    let hooks_dir = hooks_dir(repo)?;
+    let enabled = |hook: &str| {
+        (!no_verify || hook == "prepare-commit-msg") && is_executable(&hooks_dir.join(hook))
+    };
+
+    if editmsg_stamp(repo) == editmsg_before {
+        return Ok(if enabled("pre-commit") && output.status.code() == Some(1) {
+            Error::HookRejected {
+                hooks: vec!["pre-commit".to_owned()],
+                stderr,
+            }
+        } else {
+            Error::CommitFailed { stderr }
+        });
+    }
+
+    let hooks: Vec<String> = ["prepare-commit-msg", "commit-msg"]
+        .into_iter()
+        .filter(|hook| enabled(hook))
+        .map(str::to_owned)
+        .collect();
 
-    // Parse commit SHA from output (e.g., "[main abc1234] message")
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let commit_sha = parse_commit_sha(&stdout).unwrap_or_else(|| "unknown".to_string());
+    let git_failed = stderr.lines().any(|line| {
+        ["fatal: ", "error: ", "Aborting commit"]
+            .iter()
+            .any(|prefix| line.starts_with(prefix))
+    });
 
-    Ok(CommitOutput { commit_sha })
+    Ok(if hooks.is_empty() || git_failed {
+        Error::CommitFailed { stderr }
+    } else {
+        Error::HookRejected { hooks, stderr }
+    })
 }
 
-/// Extract short SHA from git commit output like "[main abc1234] message"
-fn parse_commit_sha(output: &str) -> Option<String> {
-    let line = output.lines().next()?;
-    let start = line.find(' ')? + 1;
-    let end = line[start..].find(']').map(|i| start + i)?;
-    Some(line[start..end].to_string())
+/// Directory git runs hooks from: `core.hooksPath`, or the common git dir's `hooks/`.
+///
+/// # Errors
+/// Returns an error if the config cannot be read.
+pub(crate) fn hooks_dir(repo: &Repository) -> Result<PathBuf> {
+    let config = repo.config().context("failed to read git config")?;
+    Ok(match config.get_path("core.hooksPath") {
+        Ok(path) if path.is_relative() => repo.workdir().unwrap_or_else(|| repo.path()).join(path),
+        Ok(path) => path,
+        Err(_) => common_dir(repo.path()).join("hooks"),
+    })
+}
+
+pub(crate) fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::metadata(path).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+/// HEAD's ref name (`HEAD` if detached) and target (`None` if unborn).
+pub(crate) fn read_head(repo: &Repository) -> Result<(String, Option<Oid>)> {
+    let head = repo.find_reference("HEAD").context("failed to read HEAD")?;
+    let reference = head.symbolic_target().unwrap_or("HEAD").to_owned();
+    let oid = head.resolve().ok().and_then(|r| r.target());
+    Ok((reference, oid))
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn parse_sha_from_commit_output() {
-        assert_eq!(
-            parse_commit_sha("[main abc1234] Add feature"),
-            Some("abc1234".to_string())
-        );
-        assert_eq!(
-            parse_commit_sha("[detached HEAD def5678] Fix bug"),
-            Some("HEAD def5678".to_string())
-        );
+/// Describe the commit `git commit` just made, from the repository state.
+///
+/// # Errors
+/// [`Error::CommitFailed`] if the ref still points at `old_head`: `git
+/// commit` exited 0 without committing (e.g. `--dry-run` after `--`).
+pub(crate) fn commit_output(
+    repo: &Repository,
+    reference: String,
+    old_head: Option<Oid>,
+) -> Result<CommitOutput> {
+    let commit = repo
+        .refname_to_id(&reference)
+        .with_context(|| format!("git commit succeeded but {reference} cannot be read"))?;
+    if old_head == Some(commit) {
+        let stderr = format!("it exited 0 but made no commit; {reference} is still {commit}");
+        return Err(Error::CommitFailed { stderr }.into());
     }
+    let commit = repo.find_commit(commit).context("failed to read new commit")?;
+
+    let amend = old_head.is_some_and(|old| !commit.parent_ids().any(|parent| parent == old));
+    let branch = reference.strip_prefix("refs/heads/").map(str::to_owned);
+
+    Ok(CommitOutput {
+        commit: commit.id(),
+        tree: commit.tree_id(),
+        old_head,
+        reference,
+        branch,
+        amend,
+    })
 }
@@ -0,0 +1,476 @@
+//! Repository health checks for `git commit-staged doctor`.
+//!
+//! Finds files left behind by killed runs (index locks, owner sidecars and
+//! temp indexes) in the git dir and every worktree's git dir, decides
+//! whether their owners are gone, and optionally removes the orphans. Also
+//! flags repository setups this tool cannot handle safely.
+
+use anyhow::{bail, Context, Result};
+use git2::{Repository, RepositoryState};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::commit::hooks_dir;
+use crate::common_dir;
+use crate::lock::pid_alive;
+use crate::lock_owner::{remove_stale_lock, LockOwner};
+
+/// Findings of a health check.
+#[derive(Debug, Default, Serialize)]
+pub struct Report {
+    /// Leftover files, in each git dir
+    pub artifacts: Vec<Artifact>,
+    /// Repository settings or states this tool can't handle safely
+    pub warnings: Vec<Warning>,
+}
+
+/// A file a run may have left behind.
+#[derive(Debug, Serialize)]
+pub struct Artifact {
+    /// Path to the file
+    pub path: PathBuf,
+    /// What the file is
+    pub kind: ArtifactKind,
+    /// Size in bytes
+    pub size: u64,
+    /// Seconds since last modification
+    pub age_secs: u64,
+    /// PID of the process that created it, if recorded
+    pub owner_pid: Option<u32>,
+    /// Whether the owner is gone
+    pub state: ArtifactState,
+    /// Why the file is in this state
+    pub reason: String,
+    /// Whether `--fix` removed it
+    pub removed: bool,
+}
+
+/// Kind of leftover file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ArtifactKind {
+    /// `index.lock`
+    Lock,
+    /// `index.lock.owner` (or its temp file)
+    LockOwner,
+    /// `index.stage.<pid>` (git-commit-files, git-stage-area)
+    StageIndex,
+    /// `index.commit-staged.<pid>`
+    CommitIndex,
+    /// `index.backup.<pid>` (git-commit-files)
+    IndexBackup,
+    /// `index.partial-*`
+    PartialIndex,
+}
+
+/// Whether a leftover file's owner is gone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ArtifactState {
+    /// Owner is provably gone; safe to remove
+    Orphaned,
+    /// Owner may still be running
+    InUse,
+    /// Owner cannot be determined; left alone
+    Unknown,
+    /// Owner is gone or unknown, but the file may hold the only copy of
+    /// something; never removed
+    Recoverable,
+}
+
+/// A repository setting or state this tool can't handle safely.
+#[derive(Debug, Serialize)]
+pub struct Warning {
+    /// What was found
+    pub kind: WarningKind,
+    /// Human-readable details
+    pub message: String,
+}
+
+/// Kind of warning.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WarningKind {
+    /// Split index is enabled; temp indexes don't carry the shared index
+    SplitIndex,
+    /// A hook appears to run `git add`, changing the index mid-commit
+    HookRunsGitAdd,
+    /// A rebase, merge or similar operation is in progress
+    OperationInProgress,
+}
+
+/// Inspect `repo` and all its worktrees.
+///
+/// # Errors
+/// Returns an error if a git dir or the repository config cannot be read.
+pub fn diagnose(repo: &Repository) -> Result<Report> {
+    let mut report = Report::default();
+
+    for git_dir in git_dirs(repo.path()) {
+        scan_git_dir(&git_dir, &mut report.artifacts)?;
+    }
+
+    check_split_index(repo, &mut report.warnings)?;
+    check_hooks(repo, &mut report.warnings)?;
+    check_state(repo, &mut report.warnings);
+
+    Ok(report)
+}
+
+/// Remove every orphaned artifact in `report`, marking it removed.
+///
+/// Recoverable artifacts such as index backups are never removed. Locks are
+/// re-verified before removal (see [`remove_stale_lock`]). An orphan that
+/// cannot be removed doesn't stop the others from being removed.
+///
+/// # Errors
+/// Returns an error listing the orphans that could not be removed.
+pub fn fix(report: &mut Report) -> Result<()> {
+    let mut errors = Vec::new();
+    for artifact in &mut report.artifacts {
+        if artifact.state != ArtifactState::Orphaned {
+            continue;
+        }
+        let removed = if artifact.kind == ArtifactKind::Lock {
+            remove_stale_lock(&artifact.path).map(drop)
+        } else {
+            std::fs::remove_file(&artifact.path)
+                .with_context(|| format!("failed to remove {}", artifact.path.display()))
+        };
+        match removed {
+            Ok(()) => artifact.removed = true,
+            Err(err) => errors.push(format!("{err:#}")),
+        }
+    }
+    if !errors.is_empty() {
+        bail!("{}", errors.join("\n"));
+    }
+    Ok(())
+}
+
+/// Remove the temp indexes (and owner sidecar temp file) of `pid`, a dead
+/// lock owner whose lock `unlock --stale` just removed.
+///
+/// They are only written under the lock, which that owner held until now,
+/// so no one else can be using them. Index backups are left alone: only the
+/// user can tell whether they are still needed.
+///
+/// # Errors
+/// Returns an error if a file exists but cannot be removed.
+pub fn remove_temp_indexes(git_dir: &Path, pid: u32) -> Result<Vec<PathBuf>> {
+    let mut removed = Vec::new();
+    for prefix in ["index.stage.", "index.commit-staged.", "index.lock.owner."] {
+        let path = git_dir.join(format!("{prefix}{pid}"));
+        match std::fs::remove_file(&path) {
+            Ok(()) => removed.push(path),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => {
+                return Err(err).with_context(|| format!("failed to remove {}", path.display()));
+            }
+        }
+    }
+    Ok(removed)
+}
+
+/// The common git dir plus each linked worktree's git dir.
+fn git_dirs(git_dir: &Path) -> Vec<PathBuf> {
+    let common = common_dir(git_dir);
+
+    let mut dirs = vec![common.clone()];
+    if let Ok(entries) = std::fs::read_dir(common.join("worktrees")) {
+        let mut worktrees: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.is_dir())
+            .collect();
+        worktrees.sort();
+        dirs.extend(worktrees);
+    }
+    dirs
+}
+
+fn scan_git_dir(git_dir: &Path, artifacts: &mut Vec<Artifact>) -> Result<()> {
+    let entries = std::fs::read_dir(git_dir)
+        .with_context(|| format!("failed to read {}", git_dir.display()))?;
+
+    let mut names: Vec<String> = entries
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .filter(|name| name.starts_with("index."))
+        .collect();
+    names.sort();
+
+    let lock_owner = verified_owner(&git_dir.join("index.lock"));
+    for name in names {
+        let path = git_dir.join(&name);
+        let Some((kind, owner_pid, state, reason)) = classify(git_dir, &name, lock_owner.as_ref())
+        else {
+            continue;
+        };
+        let Ok(metadata) = std::fs::metadata(&path) else {
+            // Removed while we looked
+            continue;
+        };
+        let age_secs = metadata
+            .modified()
+            .ok()
+            .and_then(|mtime| SystemTime::now().duration_since(mtime).ok())
+            .map_or(0, |age| age.as_secs());
+
+        artifacts.push(Artifact {
+            path,
+            kind,
+            size: metadata.len(),
+            age_secs,
+            owner_pid,
+            state,
+            reason,
+            removed: false,
+        });
+    }
+    Ok(())
+}
+
+/// Identify a file named `index.*`, or `None` if it isn't ours to judge.
+///
+/// `lock_owner` is the verified owner of the git dir's `index.lock`, if any.
+fn classify(
+    git_dir: &Path,
+    name: &str,
+    lock_owner: Option<&LockOwner>,
+) -> Option<(ArtifactKind, Option<u32>, ArtifactState, String)> {
+    if name == "index.lock" {
+        let (pid, state, reason) = lock_state(&git_dir.join(name), lock_owner);
+        return Some((ArtifactKind::Lock, pid, state, reason));
+    }
+    if name == "index.lock.owner" {
+        // Reported with its lock, unless the lock is gone
+        if git_dir.join("index.lock").exists() {
+            return None;
+        }
+        let pid = LockOwner::read(&git_dir.join("index.lock")).map(|owner| owner.pid);
+        return Some((
+            ArtifactKind::LockOwner,
+            pid,
+            ArtifactState::Orphaned,
+            "its lock is gone".to_owned(),
+        ));
+    }
+
+    let (kind, pid) = if let Some(pid) = name.strip_prefix("index.lock.owner.") {
+        (ArtifactKind::LockOwner, pid.parse().ok())
+    } else if let Some(pid) = name.strip_prefix("index.stage.") {
+        (ArtifactKind::StageIndex, pid.parse().ok())
+    } else if let Some(pid) = name.strip_prefix("index.commit-staged.") {
+        (ArtifactKind::CommitIndex, pid.parse().ok())
+    } else if let Some(pid) = name.strip_prefix("index.backup.") {
+        (ArtifactKind::IndexBackup, pid.parse().ok())
+    } else if let Some(rest) = name.strip_prefix("index.partial-") {
+        let pid = rest.rsplit(['-', '.']).next().and_then(|pid| pid.parse().ok());
+        (ArtifactKind::PartialIndex, pid)
+    } else {
+        return None;
+    };
+
+    let (state, reason) = pid.map_or_else(
+        || (ArtifactState::Unknown, "no owner PID in file name".to_owned()),
+        |pid| pid_state(pid, lock_owner),
+    );
+    if kind == ArtifactKind::IndexBackup && state != ArtifactState::InUse {
+        // The index as it was before an interrupted run; only the user can
+        // tell whether it is still needed
+        let reason = format!(
+            "{reason}; it is .git/index from before that run: restore it with \
+             `mv {} {}` or delete it by hand",
+            git_dir.join(name).display(),
+            git_dir.join("index").display()
+        );
+        return Some((kind, pid, ArtifactState::Recoverable, reason));
+    }
+    Some((kind, pid, state, reason))
+}
+
+/// Judge a file named after the process that created it.
+///
+/// The name records a PID but not the host or PID namespace, so a PID that
+/// isn't running here proves nothing by itself. The file is orphaned only if
+/// that PID also holds the git dir's `index.lock` and the lock's owner record
+/// proves it dead.
+fn pid_state(pid: u32, lock_owner: Option<&LockOwner>) -> (ArtifactState, String) {
+    if let Some(owner) = lock_owner.filter(|owner| owner.pid == pid) {
+        return owner.death_reason().map_or_else(
+            || (ArtifactState::InUse, format!("index.lock is held by {owner}")),
+            |reason| (ArtifactState::Orphaned, format!("{reason} (it holds index.lock)")),
+        );
+    }
+    if pid_alive(pid) {
+        (ArtifactState::InUse, format!("process {pid} is running"))
+    } else {
+        (
+            ArtifactState::Unknown,
+            format!(
+                "process {pid} isn't running here, but may be on another host \
+                 or in another PID namespace"
+            ),
+        )
+    }
+}
+
+fn lock_state(
+    lock_path: &Path,
+    lock_owner: Option<&LockOwner>,
+) -> (Option<u32>, ArtifactState, String) {
+    let Some(owner) = lock_owner else {
+        let reason = if LockOwner::read(lock_path).is_some() {
+            "owner record belongs to an earlier lock file"
+        } else {
+            "no owner record; may belong to another git process"
+        };
+        return (None, ArtifactState::Unknown, reason.to_owned());
+    };
+    owner.death_reason().map_or_else(
+        || (Some(owner.pid), ArtifactState::InUse, format!("held by {owner}")),
+        |reason| (Some(owner.pid), ArtifactState::Orphaned, reason),
+    )
+}
+
+/// The owner recorded for `lock_path`, if the record belongs to the lock file
+/// there now.
+fn verified_owner(lock_path: &Path) -> Option<LockOwner> {
+    use std::os::unix::fs::MetadataExt;
+
+    let owner = LockOwner::read(lock_path)?;
+    let inode = std::fs::metadata(lock_path).ok()?.ino();
+    (inode == owner.lock_inode).then_some(owner)
+}
+
+fn check_split_index(repo: &Repository, warnings: &mut Vec<Warning>) -> Result<()> {
+    let config = repo.config().context("failed to read git config")?;
+    let enabled = config.get_bool("core.splitIndex").unwrap_or(false);
+    let shared = std::fs::read_dir(repo.path())
+        .context("failed to read git dir")?
+        .filter_map(Result::ok)
+        .any(|entry| entry.file_name().to_string_lossy().starts_with("sharedindex."));
+
+    if enabled || shared {
+        warnings.push(Warning {
+            kind: WarningKind::SplitIndex,
+            message: "split index is in use (core.splitIndex); temp indexes are written \
+                      whole, and committing from them may drop the shared index"
+                .to_owned(),
+        });
+    }
+    Ok(())
+}
+
+fn check_hooks(repo: &Repository, warnings: &mut Vec<Warning>) -> Result<()> {
+    let hooks_dir = hooks_dir(repo)?;
+    let Ok(entries) = std::fs::read_dir(&hooks_dir) else {
+        return Ok(());
+    };
+
+    let mut hooks: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.is_file() && path.extension().is_none_or(|ext| ext != "sample"))
+        .collect();
+    hooks.sort();
+
+    for hook in hooks {
+        let Ok(script) = std::fs::read_to_string(&hook) else {
+            continue;
+        };
+        if script.lines().any(runs_git_add) {
+            warnings.push(Warning {
+                kind: WarningKind::HookRunsGitAdd,
+                message: format!(
+                    "hook {} appears to run `git add`; index changes it makes during \
+                     a commit land in the temp index, not .git/index",
+                    hook.display()
+                ),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Whether a shell line appears to stage files.
+fn runs_git_add(line: &str) -> bool {
+    let line = line.trim_start();
+    if line.starts_with('#') {
+        return false;
+    }
+    let words: Vec<&str> = line.split_whitespace().collect();
+    words
+        .windows(2)
+        .any(|w| w[0] == "git" && matches!(w[1], "add" | "stage" | "update-index"))
+}
+
+fn check_state(repo: &Repository, warnings: &mut Vec<Warning>) {
+    let operation = match repo.state() {
+        RepositoryState::Clean => return,
+        RepositoryState::Rebase
+        | RepositoryState::RebaseInteractive
+        | RepositoryState::RebaseMerge
+        | RepositoryState::ApplyMailboxOrRebase => "a rebase",
+        RepositoryState::Merge => "a merge",
+        RepositoryState::Revert | RepositoryState::RevertSequence => "a revert",
+        RepositoryState::CherryPick | RepositoryState::CherryPickSequence => "a cherry-pick",
+        RepositoryState::Bisect => "a bisect",
+        RepositoryState::ApplyMailbox => "git am",
+    };
+    warnings.push(Warning {
+        kind: WarningKind::OperationInProgress,
+        message: format!("{operation} is in progress; commits would land in the middle of it"),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_git_add_in_hooks() {
+        assert!(runs_git_add("git add -u"));
+        assert!(runs_git_add("  cargo fmt && git add src/"));
+        assert!(runs_git_add("exec git update-index --refresh"));
+        assert!(!runs_git_add("# git add -u"));
+        assert!(!runs_git_add("git diff --cached"));
+    }
+
+    #[test]
+    fn classifies_temp_indexes_by_pid() {
+        let dir = Path::new("/nonexistent");
+        let ours = format!("index.stage.{}", std::process::id());
+        let (kind, pid, state, _) = classify(dir, &ours, None).unwrap();
+        assert_eq!(kind, ArtifactKind::StageIndex);
+        assert_eq!(pid, Some(std::process::id()));
+        assert_eq!(state, ArtifactState::InUse);
+
+        // Not running here doesn't prove it's gone
+        let dead = format!("index.commit-staged.{}", u32::MAX);
+        let (kind, _, state, _) = classify(dir, &dead, None).unwrap();
+        assert_eq!(kind, ArtifactKind::CommitIndex);
+        assert_eq!(state, ArtifactState::Unknown);
+
+        // Unless it held the lock, and its owner record proves it dead
+        let lock = tempfile::tempfile().unwrap();
+        let owner = LockOwner {
+            pid: u32::MAX,
+            process_start: None,
+            ..LockOwner::current(&lock).unwrap()
+        };
+        let (_, _, state, _) = classify(dir, &dead, Some(&owner)).unwrap();
+        assert_eq!(state, ArtifactState::Orphaned);
+
+        // Backups are never orphaned, whatever happened to their owner
+        let backup = format!("index.backup.{}", u32::MAX);
+        let (kind, _, state, _) = classify(dir, &backup, Some(&owner)).unwrap();
+        assert_eq!(kind, ArtifactKind::IndexBackup);
+        assert_eq!(state, ArtifactState::Recoverable);
+
+        let (kind, _, state, _) = classify(dir, "index.partial-abc", None).unwrap();
+        assert_eq!(kind, ArtifactKind::PartialIndex);
+        assert_eq!(state, ArtifactState::Unknown);
+
+        assert!(classify(dir, "index.bak", None).is_none());
+    }
+}
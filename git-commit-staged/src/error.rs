@@ -0,0 +1,388 @@
+//! Failure modes callers can tell apart.
+//!
+//! Library functions return `anyhow::Result`. Failures a caller may want to
+//! act on carry an [`Error`] in their chain, recovered with [`Error::find`].
+//! Each variant has a stable code (used in `--json` and `--porcelain`
+//! output) and a process exit code:
+//!
+//! | Exit | Code                     | Meaning                                   |
+//! |------|--------------------------|-------------------------------------------|
+//! | 1    | `failed`                 | Any other failure                         |
+//! | 2    | -                        | Invalid command line                      |
+//! | 3    | `no-changes`             | Nothing to commit at the given paths      |
+//! | 4    | `path-escapes-scope`     | A path is outside the scope or repository |
+//! | 5    | `empty-repo`             | The repository has no commits yet         |
+//! | 6    | `would-overwrite-staged` | Staged changes would be overwritten       |
+//! | 7    | `hook-rejected`          | A commit hook rejected the commit         |
+//! | 8    | `commit-failed`          | `git commit` failed for another reason    |
+//! | 9    | `head-moved`             | HEAD is not the expected commit           |
+//! | 10   | `blob-mismatch`          | Content to commit differs from expected   |
+//! | 11   | `conflict`               | Paths changed on both sides of a rebase   |
+//! | 12   | `verify-failed`          | The `--verify-cmd` command failed         |
+//! | 13   | `unexpected-mutation`    | `--paranoid` found other changes          |
+//! | 75   | `lock-held`              | The index lock is held (retry later)      |
+//! | 75   | `lock-timeout`           | Timed out waiting for the index lock      |
+//!
+//! Only exit code 75 is worth retrying unchanged.
+
+use git2::Oid;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::expect::Blob;
+use crate::lock_owner::LockOwner;
+
+/// Exit code for any failure without a more specific code.
+pub const EXIT_FAILURE: u8 = 1;
+/// Exit code for [`Error::NoChanges`].
+pub const EXIT_NO_CHANGES: u8 = 3;
+/// Exit code for [`Error::PathEscapesScope`].
+pub const EXIT_PATH_ESCAPES_SCOPE: u8 = 4;
+/// Exit code for [`Error::EmptyRepo`].
+pub const EXIT_EMPTY_REPO: u8 = 5;
+/// Exit code for [`Error::WouldOverwriteStaged`].
+pub const EXIT_WOULD_OVERWRITE_STAGED: u8 = 6;
+/// Exit code for [`Error::HookRejected`].
+pub const EXIT_HOOK_REJECTED: u8 = 7;
+/// Exit code for [`Error::CommitFailed`].
+pub const EXIT_COMMIT_FAILED: u8 = 8;
+/// Exit code for [`Error::HeadMoved`].
+pub const EXIT_HEAD_MOVED: u8 = 9;
+/// Exit code for [`Error::BlobMismatch`].
+pub const EXIT_BLOB_MISMATCH: u8 = 10;
+/// Exit code for [`Error::Conflict`].
+pub const EXIT_CONFLICT: u8 = 11;
+/// Exit code for [`Error::VerifyFailed`].
+pub const EXIT_VERIFY_FAILED: u8 = 12;
+/// Exit code for [`Error::UnexpectedMutation`].
+pub const EXIT_UNEXPECTED_MUTATION: u8 = 13;
+/// Exit code for lock contention: [`Error::LockHeld`] and
+/// [`Error::LockTimeout`] (`EX_TEMPFAIL`).
+pub const EXIT_LOCK_CONTENTION: u8 = 75;
+
+/// A failure callers can act on.
+#[derive(Debug)]
+pub enum Error {
+    /// The index lock is held by another process
+    LockHeld {
+        /// The contended lock file
+        path: PathBuf,
+        /// Its recorded owner, if it was taken by us
+        owner: Option<LockOwner>,
+    },
+    /// Timed out waiting for the index lock
+    LockTimeout {
+        /// The contended lock file
+        path: PathBuf,
+        /// How long we waited
+        waited: Duration,
+    },
+    /// Nothing to commit at the given paths
+    NoChanges(String),
+    /// A path is outside the scope directory or the repository
+    PathEscapesScope {
+        /// The path as given
+        path: PathBuf,
+        /// The directory it had to stay within
+        scope: PathBuf,
+    },
+    /// The repository has no commits yet
+    EmptyRepo,
+    /// Staging from the working tree would overwrite staged changes
+    WouldOverwriteStaged {
+        /// Repo-relative paths with staged changes that differ from the working tree
+        paths: Vec<String>,
+    },
+    /// A commit hook rejected the commit
+    HookRejected {
+        /// Hooks that may have rejected it (those that ran)
+        hooks: Vec<String>,
+        /// `git commit`'s stderr, including the hook's output
+        stderr: String,
+    },
+    /// `git commit` failed for another reason
+    CommitFailed {
+        /// `git commit`'s stderr
+        stderr: String,
+    },
+    /// HEAD is not the commit the caller expected (`--expect-head`)
+    HeadMoved {
+        /// The expected commit
+        expected: Oid,
+        /// HEAD now
+        actual: Oid,
+    },
+    /// The content to commit is not what the caller expected
+    /// (`--expect-blob`, `--expect-plan`)
+    BlobMismatch {
+        /// Paths that differ, sorted
+        mismatches: Vec<BlobMismatch>,
+    },
+    /// Paths to commit also changed in commits HEAD gained since the base
+    Conflict {
+        /// Repo-relative paths changed on both sides
+        paths: Vec<String>,
+    },
+    /// The `--verify-cmd` command failed on the tree to commit
+    VerifyFailed {
+        /// The command
+        command: String,
+        /// Its exit code (`None` if killed by a signal)
+        status: Option<i32>,
+    },
+    /// The main index or working tree changed beyond the paths committed
+    /// (`--paranoid`)
+    UnexpectedMutation {
+        /// The commit made anyway, if any
+        commit: Option<Oid>,
+        /// What changed, sorted by path
+        mutations: Vec<Mutation>,
+    },
+}
+
+/// A path whose content to commit differs from what was expected.
+///
+/// `None` means the path has no change to commit; the zero OID means it is
+/// deleted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlobMismatch {
+    /// Repo-relative path
+    pub path: String,
+    /// The expected blob and mode (`None`: not to be committed; no mode:
+    /// any)
+    pub expected: Option<Blob>,
+    /// The blob and mode that would be committed (`None`: no change)
+    pub actual: Option<Blob>,
+}
+
+/// Where an unexpected change was found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Area {
+    /// The main index
+    Index,
+    /// The working tree
+    WorkTree,
+}
+
+/// A main index entry or working tree file that changed unexpectedly.
+///
+/// `None` means the entry or file does not exist.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mutation {
+    /// Repo-relative path
+    pub path: String,
+    /// Where it changed
+    pub area: Area,
+    /// Blob before
+    pub old: Option<Oid>,
+    /// Blob after
+    pub new: Option<Oid>,
+}
+
+impl Error {
+    /// Find an [`Error`] in `err`'s chain.
+    #[must_use]
+    pub fn find(err: &anyhow::Error) -> Option<&Self> {
+        err.chain().find_map(|cause| cause.downcast_ref::<Self>())
+    }
+
+    /// Stable error code.
+    #[must_use]
+    pub const fn code(&self) -> &'static str {
+        match self {
+            Self::LockHeld { .. } => "lock-held",
+            Self::LockTimeout { .. } => "lock-timeout",
+            Self::NoChanges(_) => "no-changes",
+            Self::PathEscapesScope { .. } => "path-escapes-scope",
+            Self::EmptyRepo => "empty-repo",
+            Self::WouldOverwriteStaged { .. } => "would-overwrite-staged",
+            Self::HookRejected { .. } => "hook-rejected",
+            Self::CommitFailed { .. } => "commit-failed",
+            Self::HeadMoved { .. } => "head-moved",
+            Self::BlobMismatch { .. } => "blob-mismatch",
+            Self::Conflict { .. } => "conflict",
+            Self::VerifyFailed { .. } => "verify-failed",
+            Self::UnexpectedMutation { .. } => "unexpected-mutation",
+        }
+    }
+
+    /// Process exit code.
+    #[must_use]
+    pub const fn exit_code(&self) -> u8 {
+        match self {
+            Self::LockHeld { .. } | Self::LockTimeout { .. } => EXIT_LOCK_CONTENTION,
+            Self::NoChanges(_) => EXIT_NO_CHANGES,
+            Self::PathEscapesScope { .. } => EXIT_PATH_ESCAPES_SCOPE,
+            Self::EmptyRepo => EXIT_EMPTY_REPO,
+            Self::WouldOverwriteStaged { .. } => EXIT_WOULD_OVERWRITE_STAGED,
+            Self::HookRejected { .. } => EXIT_HOOK_REJECTED,
+            Self::CommitFailed { .. } => EXIT_COMMIT_FAILED,
+            Self::HeadMoved { .. } => EXIT_HEAD_MOVED,
+            Self::BlobMismatch { .. } => EXIT_BLOB_MISMATCH,
+            Self::Conflict { .. } => EXIT_CONFLICT,
+            Self::VerifyFailed { .. } => EXIT_VERIFY_FAILED,
+            Self::UnexpectedMutation { .. } => EXIT_UNEXPECTED_MUTATION,
+        }
+    }
+
+    /// Whether retrying the same operation later may succeed.
+    #[must_use]
+    pub const fn is_retryable(&self) -> bool {
+        matches!(self, Self::LockHeld { .. } | Self::LockTimeout { .. })
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::LockHeld { path, owner: None } => write!(
+                f,
+                "failed to acquire index lock at {}\n\
+                 Another git process may be running. If not, remove the stale lock file.",
+                path.display()
+            ),
+            Self::LockHeld {
+                path,
+                owner: Some(owner),
+            } => write!(
+                f,
+                "failed to acquire index lock at {}\n\
+                 Held by {owner}\n\
+                 If that process is gone, remove the stale lock file with:\n  \
+                   git commit-staged unlock --stale",
+                path.display()
+            ),
+            Self::LockTimeout { path, waited } => write!(
+                f,
+                "timed out after {}s waiting for index lock at {}",
+                waited.as_secs(),
+                path.display()
+            ),
+            Self::NoChanges(message) => f.write_str(message),
+            Self::PathEscapesScope { path, scope } => {
+                write!(f, "{} escapes scope {}", path.display(), scope.display())
+            }
+            Self::EmptyRepo => f.write_str("repository has no commits yet (HEAD is unborn)"),
+            Self::WouldOverwriteStaged { paths } => {
+                writeln!(f, "staged changes at these paths differ from working tree:")?;
+                for path in paths {
+                    writeln!(f, "  {path}")?;
+                }
+                write!(
+                    f,
+                    "\nThese would be overwritten. Either:\n  \
+                       git commit-staged <paths>   # commit staged changes first\n  \
+                       git reset <paths>           # discard staged changes\n\
+                     Then retry."
+                )
+            }
+            Self::HookRejected { hooks, stderr } => write!(
+                f,
+                "git commit rejected by hook ({}): {stderr}",
+                hooks.join(", ")
+            ),
+            Self::CommitFailed { stderr } => write!(f, "git commit failed: {stderr}"),
+            Self::HeadMoved { expected, actual } => {
+                write!(f, "HEAD moved: expected {expected}, found {actual}")
+            }
+            Self::BlobMismatch { mismatches } => {
+                write!(f, "content to commit differs from what was expected:")?;
+                for BlobMismatch {
+                    path,
+                    expected,
+                    actual,
+                } in mismatches
+                {
+                    write!(
+                        f,
+                        "\n  {path}: expected {}, found {}",
+                        describe(*expected),
+                        describe(*actual)
+                    )?;
+                }
+                Ok(())
+            }
+            Self::Conflict { paths } => {
+                write!(f, "these paths also changed since the base commit:")?;
+                for path in paths {
+                    write!(f, "\n  {path}")?;
+                }
+                Ok(())
+            }
+            Self::VerifyFailed { command, status } => {
+                let status =
+                    status.map_or_else(|| "killed".to_owned(), |code| format!("exit {code}"));
+                write!(f, "verify command failed ({status}), not committing: {command}")
+            }
+            Self::UnexpectedMutation { commit, mutations } => {
+                write!(f, "main index or working tree changed unexpectedly")?;
+                if let Some(commit) = commit {
+                    write!(f, " (committed {commit})")?;
+                }
+                write!(f, ":")?;
+                for mutation in mutations {
+                    write!(f, "\n  {mutation}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Describe an expected or actual blob.
+fn describe(blob: Option<Blob>) -> String {
+    blob.map_or_else(|| "no change".to_owned(), describe_blob)
+}
+
+/// Describe a blob and its mode, if known; the zero OID is a deletion.
+pub(crate) fn describe_blob((oid, mode): Blob) -> String {
+    match mode {
+        _ if oid.is_zero() => "deleted".to_owned(),
+        Some(mode) => format!("{mode:06o} {oid}"),
+        None => oid.to_string(),
+    }
+}
+
+impl std::fmt::Display for Mutation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let area = match self.area {
+            Area::Index => "index",
+            Area::WorkTree => "work tree",
+        };
+        let describe =
+            |oid: Option<Oid>| oid.map_or_else(|| "missing".to_owned(), |oid| oid.to_string());
+        write!(f, "{area}: {}: {} -> {}", self.path, describe(self.old), describe(self.new))
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn found_through_context() {
+        let err = Err::<(), _>(Error::EmptyRepo)
+            .context("failed to prepare commit")
+            .unwrap_err();
+        let found = Error::find(&err).expect("typed error in chain");
+        assert_eq!(found.code(), "empty-repo");
+        assert_eq!(found.exit_code(), EXIT_EMPTY_REPO);
+    }
+
+    #[test]
+    fn only_lock_contention_is_retryable() {
+        let timeout = Error::LockTimeout {
+            path: PathBuf::from(".git/index.lock"),
+            waited: Duration::from_secs(1),
+        };
+        let hook = Error::HookRejected {
+            hooks: vec!["pre-commit".to_owned()],
+            stderr: String::new(),
+        };
+        assert!(timeout.is_retryable());
+        assert!(!hook.is_retryable());
+    }
+}
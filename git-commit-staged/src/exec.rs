@@ -1,13 +1,24 @@
 //! CLI execution helpers shared between git-commit-staged and git-commit-files.
 
 use anyhow::{bail, Context, Result};
-use git2::{Index, IndexAddOption, Repository};
+use git2::{Index, IndexAddOption, Oid, Repository};
+use std::fs::File;
 use std::os::unix::process::CommandExt;
 use std::path::{Path, PathBuf};
-use std::process::Command;
-
+use std::process::ExitCode;
+
+use crate::cleanup;
+use crate::commit::CommitOutput;
+use crate::doctor::{ArtifactState, Report};
+use crate::index::write_index_copy;
+use crate::journal::Undone;
+use crate::lock::{fsync_index, replace_index, sync_dir};
+use crate::error::{Error, EXIT_FAILURE};
+use crate::series::SeriesCommit;
+use crate::session::{git_commit_command, Session};
+use crate::status::{staged_and_unstaged, Change, Status};
 use crate::unglobbed_path::UnglobbedPath;
-use crate::StagedEntry;
+use crate::{head_tree, StagedEntry};
 
 /// Print dry-run output showing files that would be committed.
 pub fn print_dry_run(staged_entries: &[StagedEntry]) {
@@ -18,16 +29,77 @@ pub fn print_dry_run(staged_entries: &[StagedEntry]) {
     }
 }
 
+/// Print dry-run output for a series: each commit's label and files.
+pub fn print_series_dry_run(commits: &[SeriesCommit]) {
+    println!("Commits to make:");
+    for commit in commits {
+        println!("  {}:", commit.label);
+        for (path, data) in &commit.entries {
+            let status = if data.is_some() { "M" } else { "D" };
+            println!("    {status} {path}");
+        }
+    }
+}
+
+/// Print warnings on stderr.
+pub fn print_warnings(warnings: &[String]) {
+    for warning in warnings {
+        eprintln!("warning: {warning}");
+    }
+}
+
+/// Warnings for committed paths whose working tree differs from what is
+/// committed (the commit won't include those changes).
+///
+/// # Errors
+/// Returns an error if HEAD or the index cannot be read.
+pub fn unstaged_warnings(repo: &Repository, entries: &[StagedEntry]) -> Result<Vec<String>> {
+    let paths: Vec<&str> = entries.iter().map(|(path, _)| path.as_str()).collect();
+    Ok(staged_and_unstaged(repo, &paths)?
+        .into_iter()
+        .map(|path| format!("{path} has unstaged changes that are not committed"))
+        .collect())
+}
+
+/// Print a commit result as `[<tool> <branch> <id>]`.
+///
+/// The branch is `detached HEAD` when detached; `(root-commit)` or `(amend)`
+/// precedes the abbreviated ID when applicable.
+pub fn print_commit(tool: &str, output: &CommitOutput) {
+    let branch = output.branch.as_deref().unwrap_or("detached HEAD");
+    let kind = if output.old_head.is_none() {
+        " (root-commit)"
+    } else if output.amend {
+        " (amend)"
+    } else {
+        ""
+    };
+    println!("[{tool} {branch}{kind} {}]", output.short_id());
+}
+
+/// Report a fatal error on stderr and map it to the process exit code.
+///
+/// Typed failures exit with [`Error::exit_code`] (see [`crate::error`]);
+/// everything else with 1.
+#[must_use]
+pub fn report_error(err: &anyhow::Error) -> ExitCode {
+    eprintln!("Error: {err:?}");
+    ExitCode::from(Error::find(err).map_or(EXIT_FAILURE, Error::exit_code))
+}
+
 /// Execute git commit with a temporary index file.
 ///
 /// This function does not return on success - it replaces the current process
 /// with `git commit`. On error, it cleans up the temp index and returns the error.
 ///
 /// # Errors
-/// Returns an error if `exec()` fails (e.g., git not found).
+/// Returns an error if the session config cannot be read or `exec()` fails
+/// (e.g., git not found).
 pub fn exec_git_commit(temp_index_path: &Path, passthrough_args: &[String]) -> Result<()> {
-    let mut cmd = Command::new("git");
-    cmd.arg("commit");
+    let repo = Repository::open_from_env().context("failed to open repository")?;
+    let session = Session::from_env(&repo)?;
+
+    let mut cmd = git_commit_command(session.as_ref());
     cmd.args(passthrough_args);
     cmd.env("GIT_INDEX_FILE", temp_index_path);
 
@@ -35,7 +107,7 @@ pub fn exec_git_commit(temp_index_path: &Path, passthrough_args: &[String]) -> R
     let err = cmd.exec();
 
     // exec() only returns on error
-    let _ = std::fs::remove_file(temp_index_path);
+    let _ = cleanup::remove(temp_index_path);
     Err(err).context("failed to exec git commit")
 }
 
@@ -46,71 +118,162 @@ pub fn exec_git_commit(temp_index_path: &Path, passthrough_args: &[String]) -> R
 /// (already staged what we're committing), we're fine.
 ///
 /// # Errors
-/// Returns an error if staged changes would be lost, or if git operations fail.
+/// Returns [`Error::WouldOverwriteStaged`] if staged changes would be lost,
+/// or an error if git operations fail.
 pub fn check_no_staged_changes(paths: &[UnglobbedPath]) -> Result<()> {
-    use std::collections::HashSet;
-
     let repo = Repository::open_from_env().context("failed to open repository")?;
-    let index = repo.index().context("failed to get index")?;
-    let head = repo.head()?.peel_to_tree()?;
-
-    let matches = |p: &Path| paths.iter().any(|t| p.starts_with(t.as_ref()));
-
-    let staged: HashSet<PathBuf> = repo
-        .diff_tree_to_index(Some(&head), Some(&index), None)?
-        .deltas()
-        .filter_map(|d| d.new_file().path())
-        .filter(|p| matches(p))
-        .map(Path::to_path_buf)
-        .collect();
-
-    let unstaged: HashSet<PathBuf> = repo
-        .diff_index_to_workdir(Some(&index), None)?
-        .deltas()
-        .filter_map(|d| d.new_file().path())
-        .filter(|p| matches(p))
-        .map(Path::to_path_buf)
-        .collect();
-
-    let conflicts: Vec<_> = staged.intersection(&unstaged).collect();
+
+    let conflicts = staged_and_unstaged(&repo, paths)?;
     if !conflicts.is_empty() {
-        let list = conflicts.iter().map(|p| format!("  {}", p.display())).collect::<Vec<_>>().join("\n");
-        bail!(
-            "staged changes at these paths differ from working tree:\n{list}\n\n\
-             These would be overwritten. Either:\n  \
-               git commit-staged <paths>   # commit staged changes first\n  \
-               git reset <paths>           # discard staged changes\n\
-             Then retry."
-        );
+        return Err(Error::WouldOverwriteStaged { paths: conflicts }.into());
     }
 
     Ok(())
 }
 
+/// Print status grouped by claimant, as for `git commit-staged status`.
+pub fn print_status(status: &Status) {
+    let print_changes = |heading: &str, changes: &[Change]| {
+        if !changes.is_empty() {
+            println!("{heading}:");
+            for change in changes {
+                println!("  {} {}", change.status.letter(), change.path);
+            }
+        }
+    };
+
+    for (area, changes) in &status.claimed {
+        print_changes(&format!("Staged by {area}"), changes);
+    }
+    print_changes("Staged (unattributed)", &status.unattributed);
+    print_changes("Not staged", &status.unstaged);
+
+    if !status.staged_and_unstaged.is_empty() {
+        println!("Staged and modified since:");
+        for path in &status.staged_and_unstaged {
+            println!("  {path}");
+        }
+    }
+
+    if !status.conflicts.is_empty() {
+        println!("Conflicting claims:");
+        for conflict in &status.conflicts {
+            println!("  {} ({})", conflict.path, conflict.claimants.join(", "));
+        }
+    }
+}
+
+/// Print the result of undoing one commit, as for `git commit-staged undo`.
+pub fn print_undone(undone: &Undone) {
+    print_warnings(&undone.warnings);
+    let short = |oid: Oid| oid.to_string()[..7].to_owned();
+    match undone.revert {
+        Some(revert) => println!("Reverted {} with {}", short(undone.commit), short(revert)),
+        None => println!("Undid {}", short(undone.commit)),
+    }
+    for path in &undone.restored {
+        println!("  restored index entry for {path}");
+    }
+}
+
+/// Print a health check report, as for `git commit-staged doctor`.
+pub fn print_doctor(report: &Report, fixed: bool) {
+    if report.artifacts.is_empty() && report.warnings.is_empty() {
+        println!("No problems found.");
+        return;
+    }
+
+    if !report.artifacts.is_empty() {
+        println!("Leftover files:");
+        for artifact in &report.artifacts {
+            let state = match artifact.state {
+                ArtifactState::Orphaned if artifact.removed => "removed",
+                ArtifactState::Orphaned => "orphaned",
+                ArtifactState::InUse => "in use",
+                ArtifactState::Unknown => "unknown",
+                ArtifactState::Recoverable => "recoverable",
+            };
+            println!(
+                "  {state:<11} {} ({} bytes, {} old): {}",
+                artifact.path.display(),
+                artifact.size,
+                format_age(artifact.age_secs),
+                artifact.reason
+            );
+        }
+        let orphans = report
+            .artifacts
+            .iter()
+            .filter(|a| a.state == ArtifactState::Orphaned && !a.removed)
+            .count();
+        if orphans > 0 && !fixed {
+            println!("Run `git commit-staged doctor --fix` to remove orphaned files.");
+        }
+    }
+
+    if !report.warnings.is_empty() {
+        println!("Warnings:");
+        for warning in &report.warnings {
+            println!("  {}", warning.message);
+        }
+    }
+}
+
+fn format_age(secs: u64) -> String {
+    match secs {
+        0..60 => format!("{secs}s"),
+        60..3600 => format!("{}m", secs / 60),
+        3600..86400 => format!("{}h", secs / 3600),
+        _ => format!("{}d", secs / 86400),
+    }
+}
+
 /// Result of staging paths to a temp index.
 pub struct StageResult {
     /// Path to temp index with staged content
     pub temp_index_path: PathBuf,
-    /// Path to real index (for rename)
-    pub real_index_path: PathBuf,
     /// Entries that were staged (diff from HEAD)
     pub staged_entries: Vec<StagedEntry>,
 }
 
-/// Stage paths from working tree to a temp index.
+/// Paths staged from the working tree into an in-memory copy of the index.
+pub struct StagedPaths {
+    index: Index,
+    git_dir: PathBuf,
+    /// Entries that were staged (diff from HEAD)
+    pub staged_entries: Vec<StagedEntry>,
+}
+
+impl StagedPaths {
+    /// Write the staged index to a temp index (`index.stage.<pid>`).
+    ///
+    /// # Errors
+    /// Returns an error if the temp index cannot be written.
+    pub fn write_temp(self) -> Result<StageResult> {
+        // Write to temp path (avoids conflict with index.lock we hold)
+        let temp_path = self.git_dir.join(format!("index.stage.{}", std::process::id()));
+        cleanup::register(&temp_path);
+
+        write_index_copy(&self.index, &temp_path)
+            .context("failed to write temp index for staging")?;
+
+        Ok(StageResult {
+            temp_index_path: temp_path,
+            staged_entries: self.staged_entries,
+        })
+    }
+}
+
+/// Stage paths from working tree into a copy of the index, in memory.
 ///
 /// Uses `update_all` for tracked files (handles modifications and deletions)
-/// plus `add_all` for new untracked files.
-///
-/// Returns the temp index path and staged entries. Caller decides whether to:
-/// - Rename temp → real (commit path)
-/// - Delete temp (dry-run path)
+/// plus `add_all` for new untracked files. Nothing is written: a dry run can
+/// stop here, and a real run checks the entries before writing them with
+/// [`StagedPaths::write_temp`].
 ///
 /// # Errors
 /// Returns an error if paths is empty or staging fails.
-pub fn stage_paths_to_temp(paths: &[UnglobbedPath]) -> Result<StageResult> {
-    use std::borrow::Cow;
-
+pub fn stage_paths(paths: &[UnglobbedPath]) -> Result<StagedPaths> {
     if paths.is_empty() {
         bail!("no paths specified");
     }
@@ -118,25 +281,7 @@ pub fn stage_paths_to_temp(paths: &[UnglobbedPath]) -> Result<StageResult> {
     let repo = Repository::open_from_env().context("failed to open repository")?;
     let mut index = repo.index().context("failed to get index")?;
 
-    // Resolve user paths to repo-relative paths
-    let cwd = std::env::current_dir().context("failed to get current directory")?;
-    let repo_root = repo
-        .workdir()
-        .context("repository has no workdir")?;
-    let repo_root = std::fs::canonicalize(repo_root).context("failed to canonicalize repo root")?;
-
-    let repo_relative_paths: Vec<PathBuf> = paths
-        .iter()
-        .map(|p| {
-            let absolute = cwd.join(p.as_ref());
-            let normalized = gix_path::normalize(Cow::Owned(absolute), &cwd)
-                .context("path normalization failed")?;
-            normalized
-                .strip_prefix(&repo_root)
-                .map(Path::to_path_buf)
-                .with_context(|| format!("{} is outside repository", p.as_ref().display()))
-        })
-        .collect::<Result<Vec<_>>>()?;
+    let repo_relative_paths = resolve_cwd_paths(&repo, paths)?;
 
     // update_all: sync index with working tree for tracked files (modifications + deletions)
     index
@@ -148,42 +293,62 @@ pub fn stage_paths_to_temp(paths: &[UnglobbedPath]) -> Result<StageResult> {
         .add_all(&repo_relative_paths, IndexAddOption::DEFAULT, None)
         .context("failed to add paths to index")?;
 
-    // Write to temp path (avoids conflict with index.lock we hold)
-    let git_dir = repo.path();
-    let temp_path = git_dir.join(format!("index.stage.{}", std::process::id()));
+    // Find what was staged (diff HEAD to staged index)
+    let head_tree = head_tree(&repo)?;
 
-    let mut temp_index =
-        Index::open(&temp_path).context("failed to create temp index for staging")?;
+    let staged_entries = find_staged_in_index(&repo, &index, &head_tree, &repo_relative_paths)?;
 
-    for entry in index.iter() {
-        temp_index.add(&entry).with_context(|| {
-            format!(
-                "failed to copy entry {}",
-                String::from_utf8_lossy(&entry.path)
-            )
-        })?;
-    }
+    Ok(StagedPaths {
+        index,
+        git_dir: repo.path().to_path_buf(),
+        staged_entries,
+    })
+}
 
-    temp_index.write().context("failed to write temp index")?;
+/// Stage paths from working tree to a temp index.
+///
+/// Returns the temp index path and staged entries. Caller decides whether to:
+/// - Rename temp → real (commit path)
+/// - Delete temp (dry-run path)
+///
+/// # Errors
+/// Returns an error if paths is empty or staging fails.
+pub fn stage_paths_to_temp(paths: &[UnglobbedPath]) -> Result<StageResult> {
+    stage_paths(paths)?.write_temp()
+}
 
-    // Find what was staged (diff HEAD to temp index)
-    let head_tree = repo
-        .head()
-        .context("failed to get HEAD")?
-        .peel_to_tree()
-        .context("failed to peel HEAD to tree")?;
+/// Resolve user paths (relative to the current directory) to repo-relative paths.
+///
+/// # Errors
+/// Returns an error if a path is outside the repository or the repo has no workdir.
+pub fn resolve_cwd_paths<P: AsRef<Path>>(
+    repo: &Repository,
+    paths: &[P],
+) -> Result<Vec<PathBuf>> {
+    use std::borrow::Cow;
 
-    let staged_entries = find_staged_in_index(&repo, &temp_index, &head_tree, &repo_relative_paths)?;
+    let cwd = std::env::current_dir().context("failed to get current directory")?;
+    let repo_root = repo
+        .workdir()
+        .context("repository has no workdir")?;
+    let repo_root = std::fs::canonicalize(repo_root).context("failed to canonicalize repo root")?;
 
-    Ok(StageResult {
-        temp_index_path: temp_path,
-        real_index_path: git_dir.join("index"),
-        staged_entries,
-    })
+    paths
+        .iter()
+        .map(|p| {
+            let absolute = cwd.join(p.as_ref());
+            let normalized = gix_path::normalize(Cow::Owned(absolute), &cwd)
+                .context("path normalization failed")?;
+            normalized
+                .strip_prefix(&repo_root)
+                .map(Path::to_path_buf)
+                .with_context(|| format!("{} is outside repository", p.as_ref().display()))
+        })
+        .collect()
 }
 
-/// Find entries in an index that differ from HEAD at the given paths.
-fn find_staged_in_index(
+/// Find entries in an index that differ from a tree at the given paths.
+pub(crate) fn find_staged_in_index(
     repo: &Repository,
     index: &Index,
     head_tree: &git2::Tree,
@@ -222,13 +387,90 @@ fn find_staged_in_index(
     Ok(staged)
 }
 
-/// Commit the temp index by renaming it to the real index.
+/// Commit the temp index by moving it into place through `index.lock`.
+///
+/// **Caller must hold index.lock.**
 ///
 /// # Errors
-/// Returns an error if the rename fails.
+/// Returns an error if the lock is not held or the index cannot be replaced.
 pub fn commit_staged_index(stage_result: &StageResult) -> Result<()> {
-    std::fs::rename(&stage_result.temp_index_path, &stage_result.real_index_path)
-        .context("failed to rename temp index to real index")
+    let repo = Repository::open_from_env().context("failed to open repository")?;
+    replace_index(&repo, &stage_result.temp_index_path)
+}
+
+/// Backup of the main index, restored on drop unless discarded.
+///
+/// Lets a sequence of index and commit operations leave `.git/index` exactly
+/// as it was if any step fails. The backup is also restored if the process
+/// is interrupted by a signal (see [`crate::cleanup`]).
+///
+/// **Caller must hold index.lock** while the backup is alive.
+pub struct IndexBackup {
+    backup_path: PathBuf,
+    git_dir: PathBuf,
+    discarded: bool,
+}
+
+impl IndexBackup {
+    /// Copy `repo`'s index to `index.backup.<pid>` beside it, fsynced unless
+    /// `core.fsync` excludes the index.
+    ///
+    /// # Errors
+    /// Returns an error if the backup cannot be written.
+    pub fn take(repo: &Repository) -> Result<Self> {
+        let git_dir = repo.path();
+        let index_path = git_dir.join("index");
+        let backup_path = git_dir.join(format!("index.backup.{}", std::process::id()));
+        let fsync = fsync_index(repo);
+        cleanup::register_backup(&backup_path, git_dir, fsync);
+
+        if index_path.exists() {
+            std::fs::copy(&index_path, &backup_path).context("failed to back up index")?;
+        } else {
+            // No index is equivalent to an empty one
+            Index::open(&backup_path)
+                .and_then(|mut index| index.write())
+                .context("failed to back up index")?;
+        }
+        if fsync {
+            File::open(&backup_path)
+                .and_then(|backup| backup.sync_all())
+                .context("failed to fsync index backup")?;
+            sync_dir(git_dir)?;
+        }
+
+        Ok(Self {
+            backup_path,
+            git_dir: git_dir.to_path_buf(),
+            discarded: false,
+        })
+    }
+
+    /// The operation succeeded: keep the current index and remove the backup.
+    pub fn discard(mut self) {
+        self.discarded = true;
+        if let Err(e) = cleanup::remove(&self.backup_path) {
+            eprintln!("warning: failed to remove index backup: {e}");
+        }
+    }
+}
+
+impl Drop for IndexBackup {
+    fn drop(&mut self) {
+        if self.discarded {
+            return;
+        }
+        // Through the held lock, like any other index write
+        let restored = Repository::open(&self.git_dir)
+            .context("failed to open repository")
+            .and_then(|repo| replace_index(&repo, &self.backup_path));
+        if let Err(e) = restored {
+            eprintln!(
+                "warning: failed to restore index from {}: {e:#}",
+                self.backup_path.display()
+            );
+        }
+    }
 }
 
 /// Discard the temp index (for dry-run).
@@ -237,8 +479,9 @@ pub fn commit_staged_index(stage_result: &StageResult) -> Result<()> {
 /// Returns an error if the temp index exists but cannot be removed.
 pub fn discard_staged_index(stage_result: &StageResult) -> Result<()> {
     if stage_result.temp_index_path.exists() {
-        std::fs::remove_file(&stage_result.temp_index_path)
-            .context("failed to remove temp index")?;
+        cleanup::remove(&stage_result.temp_index_path).context("failed to remove temp index")?;
+    } else {
+        cleanup::forget(&stage_result.temp_index_path);
     }
     Ok(())
 }
@@ -0,0 +1,253 @@
+//! Compare-and-swap guards: `--expect-head`, `--expect-blob` and `--expect-plan`.
+//!
+//! A dry run shows what would be committed, but HEAD can move and paths can
+//! be restaged before the real run. Expectations pin down what was reviewed;
+//! they are checked once the index lock is held, before any temp index is
+//! written, and the run aborts with [`Error::HeadMoved`] or
+//! [`Error::BlobMismatch`] if they no longer hold.
+//!
+//! Blob expectations refer to the content and mode that would be committed
+//! at a repo-relative path. The all-zeros OID expects a deletion, as in
+//! `git diff --raw`. A plan always pins the mode; `--expect-blob` does when
+//! given as `PATH=MODE,OID`.
+
+use anyhow::{bail, Context, Result};
+use git2::{Oid, Repository};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::error::{describe_blob, BlobMismatch, Error};
+use crate::{head_commit, StagedEntry};
+
+/// A blob and its mode (`None`: any mode).
+pub type Blob = (Oid, Option<u32>);
+
+/// What the caller expects to commit.
+#[derive(Debug, Default)]
+pub struct Expectations {
+    /// Expected HEAD commit
+    pub head: Option<Oid>,
+    /// Expected blob and mode per repo-relative path (zero OID: deleted;
+    /// no mode: any)
+    pub blobs: BTreeMap<String, Blob>,
+    /// Whether `blobs` lists every path to be committed (`--expect-plan`)
+    pub exact: bool,
+}
+
+/// The parts of a `--json` report a plan needs.
+#[derive(Deserialize)]
+struct Plan {
+    entries: Vec<PlanEntry>,
+}
+
+#[derive(Deserialize)]
+struct PlanEntry {
+    path: String,
+    new_mode: Option<String>,
+    new_oid: Option<String>,
+}
+
+impl Expectations {
+    /// Build from the command-line flags.
+    ///
+    /// `blobs` are `PATH=OID` or `PATH=MODE,OID` pairs (octal mode); `plan` is
+    /// the `--json` output of a dry run.
+    ///
+    /// # Errors
+    /// Returns an error if an OID or pair is malformed, the plan can't be
+    /// read, or the plan and a `PATH=OID` pair disagree.
+    pub fn from_args(head: Option<&str>, blobs: &[String], plan: Option<&Path>) -> Result<Self> {
+        let mut expectations = Self {
+            head: head.map(parse_oid).transpose()?,
+            ..Self::default()
+        };
+        if let Some(plan) = plan {
+            expectations.exact = true;
+            for (path, blob) in read_plan(plan)? {
+                expectations.expect_blob(&path, blob)?;
+            }
+        }
+        for pair in blobs {
+            let (path, blob) = pair
+                .rsplit_once('=')
+                .with_context(|| format!("expected PATH=OID, got {pair:?}"))?;
+            let blob = match blob.split_once(',') {
+                Some((mode, oid)) => (parse_oid(oid)?, Some(parse_mode(mode)?)),
+                None => (parse_oid(blob)?, None),
+            };
+            expectations.expect_blob(path, blob)?;
+        }
+        Ok(expectations)
+    }
+
+    /// Check HEAD and the entries about to be committed.
+    ///
+    /// # Errors
+    /// Returns [`Error::HeadMoved`] or [`Error::BlobMismatch`] if an
+    /// expectation fails, or an error if HEAD cannot be read.
+    pub fn check(&self, repo: &Repository, staged: &[StagedEntry]) -> Result<()> {
+        if let Some(expected) = self.head {
+            let actual = head_commit(repo)?.id();
+            if actual != expected {
+                return Err(Error::HeadMoved { expected, actual }.into());
+            }
+        }
+
+        let actual: BTreeMap<&str, Blob> = staged
+            .iter()
+            .map(|(path, entry)| {
+                let blob = entry.map_or((Oid::zero(), None), |(oid, mode)| (oid, Some(mode)));
+                (path.as_str(), blob)
+            })
+            .collect();
+
+        let mut mismatches: Vec<BlobMismatch> = self
+            .blobs
+            .iter()
+            .filter_map(|(path, &(oid, mode))| {
+                let found = actual.get(path.as_str()).copied();
+                let matches = found.is_some_and(|(actual_oid, actual_mode)| {
+                    actual_oid == oid && mode.is_none_or(|_| actual_mode == mode)
+                });
+                (!matches).then(|| BlobMismatch {
+                    path: path.clone(),
+                    expected: Some((oid, mode)),
+                    actual: found,
+                })
+            })
+            .collect();
+        if self.exact {
+            mismatches.extend(
+                actual
+                    .iter()
+                    .filter(|&(path, _)| !self.blobs.contains_key(*path))
+                    .map(|(path, blob)| BlobMismatch {
+                        path: (*path).to_owned(),
+                        expected: None,
+                        actual: Some(*blob),
+                    }),
+            );
+        }
+
+        if mismatches.is_empty() {
+            Ok(())
+        } else {
+            mismatches.sort_by(|a, b| a.path.cmp(&b.path));
+            Err(Error::BlobMismatch { mismatches }.into())
+        }
+    }
+
+    fn expect_blob(&mut self, path: &str, (oid, mode): Blob) -> Result<()> {
+        let merged = match self.blobs.get(path) {
+            None => (oid, mode),
+            // A mode-less expectation agrees with any mode
+            Some(&(previous, previous_mode))
+                if previous == oid && previous_mode.zip(mode).is_none_or(|(a, b)| a == b) =>
+            {
+                (oid, previous_mode.or(mode))
+            }
+            Some(&previous) => bail!(
+                "conflicting expectations for {path}: {} and {}",
+                describe_blob(previous),
+                describe_blob((oid, mode))
+            ),
+        };
+        self.blobs.insert(path.to_owned(), merged);
+        Ok(())
+    }
+}
+
+/// Read the entries of a `--json` dry-run report.
+fn read_plan(path: &Path) -> Result<Vec<(String, Blob)>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read plan {}", path.display()))?;
+    let plan: Plan = serde_json::from_str(&text)
+        .with_context(|| format!("failed to parse plan {}", path.display()))?;
+    plan.entries
+        .into_iter()
+        .map(|entry| {
+            let oid = entry.new_oid.as_deref().map_or_else(|| Ok(Oid::zero()), parse_oid)?;
+            let mode = entry.new_mode.as_deref().map(parse_mode).transpose()?;
+            Ok((entry.path, (oid, mode)))
+        })
+        .collect()
+}
+
+/// Parse an octal file mode, such as `100644`.
+fn parse_mode(s: &str) -> Result<u32> {
+    u32::from_str_radix(s, 8).with_context(|| format!("invalid file mode {s:?}"))
+}
+
+/// Parse a full hex OID.
+pub(crate) fn parse_oid(s: &str) -> Result<Oid> {
+    if s.len() != 40 {
+        bail!("expected a full 40-character object ID, got {s:?}");
+    }
+    Oid::from_str(s).with_context(|| format!("invalid object ID {s:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn oid(c: char) -> Oid {
+        Oid::from_str(&c.to_string().repeat(40)).unwrap()
+    }
+
+    #[test]
+    fn blob_pairs_parse_and_conflicts_fail() {
+        let pairs = [format!("a.txt={}", oid('a')), format!("b=c=100755,{}", oid('b'))];
+        let expectations = Expectations::from_args(None, &pairs, None).unwrap();
+        assert_eq!(expectations.blobs["a.txt"], (oid('a'), None));
+        assert_eq!(expectations.blobs["b=c"], (oid('b'), Some(0o100_755)));
+
+        let conflicting = [format!("a.txt={}", oid('a')), format!("a.txt={}", oid('b'))];
+        assert!(Expectations::from_args(None, &conflicting, None).is_err());
+        assert!(Expectations::from_args(Some("abc123"), &[], None).is_err());
+    }
+
+    #[test]
+    fn exact_plan_reports_unplanned_paths() {
+        let repo_dir = tempfile::TempDir::new().unwrap();
+        let repo = Repository::init(repo_dir.path()).unwrap();
+        let mut expectations = Expectations {
+            exact: true,
+            ..Expectations::default()
+        };
+        expectations.blobs.insert("a".to_owned(), (oid('a'), Some(0o100_644)));
+        expectations.blobs.insert("gone".to_owned(), (Oid::zero(), None));
+
+        let staged = vec![
+            ("a".to_owned(), Some((oid('a'), 0o100_644))),
+            ("extra".to_owned(), Some((oid('e'), 0o100_644))),
+            ("gone".to_owned(), None),
+        ];
+        let err = expectations.check(&repo, &staged).unwrap_err();
+        let Some(Error::BlobMismatch { mismatches }) = Error::find(&err) else {
+            panic!("expected blob mismatch: {err:#}");
+        };
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(mismatches[0].path, "extra");
+        assert_eq!(mismatches[0].expected, None);
+    }
+
+    #[test]
+    fn modes_must_match_when_given() {
+        let repo_dir = tempfile::TempDir::new().unwrap();
+        let repo = Repository::init(repo_dir.path()).unwrap();
+        let staged = vec![("run.sh".to_owned(), Some((oid('a'), 0o100_755)))];
+
+        let any_mode = [format!("run.sh={}", oid('a'))];
+        let expectations = Expectations::from_args(None, &any_mode, None).unwrap();
+        expectations.check(&repo, &staged).unwrap();
+
+        let regular = [format!("run.sh=100644,{}", oid('a'))];
+        let expectations = Expectations::from_args(None, &regular, None).unwrap();
+        let err = expectations.check(&repo, &staged).unwrap_err();
+        let Some(Error::BlobMismatch { mismatches }) = Error::find(&err) else {
+            panic!("expected blob mismatch: {err:#}");
+        };
+        assert_eq!(mismatches[0].actual, Some((oid('a'), Some(0o100_755))));
+    }
+}
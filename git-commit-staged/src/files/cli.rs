@@ -1,4 +1,4 @@
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use std::path::PathBuf;
 
 #[derive(Parser, Debug)]
@@ -15,6 +15,7 @@ use std::path::PathBuf;
                   \x20 git commit-files src/ tests/ -- --amend\n\
                   \x20 git commit-files . -- --fixup HEAD~1"
 )]
+#[allow(clippy::struct_excessive_bools)]
 pub struct Args {
     /// Paths to stage and commit
     #[arg(required = true)]
@@ -24,8 +25,86 @@ pub struct Args {
     #[arg(short = 'n', long)]
     pub dry_run: bool,
 
+    /// Wait for a held index lock: indefinitely, or up to SECONDS
+    /// [default: config commitStaged.wait]
+    #[arg(long, value_name = "SECONDS", num_args = 0..=1, require_equals = true)]
+    #[allow(clippy::option_option)]
+    pub wait: Option<Option<u64>>,
+
+    /// While waiting, queue for the index lock in FIFO order
+    /// [default: config commitStaged.queue]
+    #[arg(long)]
+    pub queue: bool,
+
+    /// Commit with libgit2 instead of running `git commit`. Supports -m, -F,
+    /// --amend, --no-verify, --trailer and -S after --; runs the pre-commit,
+    /// commit-msg and post-commit hooks
+    #[arg(long)]
+    pub plumbing: bool,
+
+    /// Fold index changes made by commit hooks (e.g. `git add` from an
+    /// autofixer) to the committed paths into the main index. Changes to
+    /// other paths abort with --plumbing, and are warned about otherwise
+    #[arg(long)]
+    pub fold_hook_changes: bool,
+
+    /// Check out the exact tree to commit, as the commit hooks leave it, into
+    /// a scratch directory and run CMD there (with `sh -c`); commit only if
+    /// it exits 0. Requires --plumbing, which can stop between the hooks and
+    /// the branch update
+    #[arg(long, value_name = "CMD", requires = "plumbing")]
+    pub verify_cmd: Option<String>,
+
+    /// Keep the --verify-cmd scratch directory, for debugging
+    #[arg(long, requires = "verify_cmd")]
+    pub keep_scratch: bool,
+
+    /// Fingerprint the main index and working tree first, and fail if
+    /// anything but the committed paths' index entries changed
+    #[arg(long)]
+    pub paranoid: bool,
+
+    /// Abort unless HEAD is this commit (full OID)
+    #[arg(long, value_name = "OID")]
+    pub expect_head: Option<String>,
+
+    /// Abort unless the content to commit at PATH (repo-relative) is this
+    /// blob, with this octal mode if given; the all-zeros OID expects a
+    /// deletion. Repeatable
+    #[arg(long, value_name = "PATH=[MODE,]OID")]
+    pub expect_blob: Vec<String>,
+
+    /// Abort unless exactly the entries in this `--json` dry-run report
+    /// would be committed
+    #[arg(long, value_name = "FILE")]
+    pub expect_plan: Option<PathBuf>,
+
+    /// Print results as JSON
+    #[arg(long, conflicts_with = "porcelain")]
+    pub json: bool,
+
+    /// Print results in a stable, line-oriented format
+    #[arg(
+        long,
+        value_name = "VERSION",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "v1"
+    )]
+    pub porcelain: Option<PorcelainVersion>,
+
+    /// With --porcelain, terminate records with NUL instead of newline
+    #[arg(short = 'z', requires = "porcelain")]
+    pub nul: bool,
+
     /// Arguments to pass through to git commit
     #[arg(last = true)]
     #[allow(clippy::struct_field_names)]
     pub passthrough_args: Vec<String>,
 }
+
+/// Versions of the `--porcelain` format.
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum PorcelainVersion {
+    V1,
+}
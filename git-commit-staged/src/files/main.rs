@@ -1,61 +1,138 @@
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
 use clap::{CommandFactory, FromArgMatches};
+use git2::Repository;
+use std::process::ExitCode;
 
 mod cli;
 use cli::Args;
-use git_commit_staged::commit::do_commit;
+use git_commit_staged::cleanup;
+use git_commit_staged::commit::{commit_temp_index, TreeChecks};
 use git_commit_staged::exec::{
-    check_no_staged_changes, commit_staged_index, discard_staged_index, print_dry_run,
-    stage_paths_to_temp,
+    check_no_staged_changes, commit_staged_index, print_commit, print_dry_run, print_warnings,
+    report_error, stage_paths, IndexBackup,
 };
+use git_commit_staged::expect::Expectations;
+use git_commit_staged::hook_changes;
 use git_commit_staged::index::write_temp_index_for_paths;
+use git_commit_staged::journal;
 use git_commit_staged::lock::IndexLock;
+use git_commit_staged::output::{self, Format, Report};
+use git_commit_staged::paranoid::Snapshot;
 use git_commit_staged::unglobbed_path::UnglobbedPath;
+use git_commit_staged::Error;
 
 const VERSION: &str = concat!(env!("CARGO_PKG_VERSION"), " (", env!("GIT_HASH"), ")");
 
-fn main() -> Result<()> {
-    let args = Args::from_arg_matches(&Args::command().version(VERSION).get_matches())?;
+fn main() -> ExitCode {
+    let args = Args::from_arg_matches(&Args::command().version(VERSION).get_matches())
+        .unwrap_or_else(|err| err.exit());
+    let format = Format::from_flags(args.json, args.porcelain.is_some(), args.nul);
+    let dry_run = args.dry_run;
 
+    let code = cleanup::install_signal_handlers()
+        .and_then(|()| run(&args, format))
+        .map_or_else(
+            |err| {
+                Report::failure(dry_run, &err).print(format);
+                report_error(&err)
+            },
+            |()| ExitCode::SUCCESS,
+        );
+    // Anything a failed run left behind
+    cleanup::remove_all();
+    code
+}
+
+fn run(args: &Args, format: Format) -> Result<()> {
     // Expand directories to files
     let files = UnglobbedPath::from_paths(&args.paths);
     if files.is_empty() {
         bail!("no files found at specified paths");
     }
 
+    let expectations = Expectations::from_args(
+        args.expect_head.as_deref(),
+        &args.expect_blob,
+        args.expect_plan.as_deref(),
+    )?;
+
     // Acquire lock before reading any state (skip for dry-run)
     let _lock = if args.dry_run {
         None
     } else {
-        Some(IndexLock::acquire()?)
+        Some(IndexLock::acquire_waiting(args.wait, args.queue)?)
     };
 
+    let repo = Repository::open_from_env().context("failed to open repository")?;
+    let snapshot = args.paranoid.then(|| Snapshot::take(&repo)).transpose()?;
+
     // Bail if staging would destroy existing staged changes
     check_no_staged_changes(&files)?;
 
-    // Stage working tree to temp index (same code path for dry-run and real)
-    let stage_result = stage_paths_to_temp(&files)?;
+    // Stage working tree in memory (same code path for dry-run and real)
+    let staged = stage_paths(&files)?;
 
-    if stage_result.staged_entries.is_empty() {
-        discard_staged_index(&stage_result)?;
-        bail!("no changes to commit at specified paths");
+    if staged.staged_entries.is_empty() {
+        return Err(Error::NoChanges("no changes to commit at specified paths".to_owned()).into());
     }
 
+    // Before anything is written
+    expectations.check(&repo, &staged.staged_entries)?;
+
+    let mut report = Report::new(args.dry_run);
+    report.entries = output::entries(&repo, &staged.staged_entries)?;
+
     if args.dry_run {
-        print_dry_run(&stage_result.staged_entries);
-        discard_staged_index(&stage_result)?;
+        if format == Format::Human {
+            print_dry_run(&staged.staged_entries);
+        }
+        if let Some(snapshot) = &snapshot {
+            snapshot.check(&repo, &[], None)?;
+        }
+        report.print(format);
         return Ok(());
     }
 
+    let stage_result = staged.write_temp()?;
+    let index_before = journal::index_entries(&repo, &stage_result.staged_entries)?;
+
+    // Any failure from here on restores the original index
+    let backup = IndexBackup::take(&repo)?;
+
     // Commit path: rename temp → real index
     commit_staged_index(&stage_result)?;
 
     // Create temp index for commit (HEAD + staged entries)
     let commit_index_path = write_temp_index_for_paths(&stage_result.staged_entries)?;
+    let intended = hook_changes::intended_tree(&repo, &commit_index_path)?;
 
     // Lock held throughout - do_commit expects caller to hold it
-    let output = do_commit(&commit_index_path, &args.passthrough_args)?;
-    println!("[commit-files {}]", &output.commit_sha[..7]);
+    let checks = TreeChecks {
+        scope: args.fold_hook_changes.then_some((intended, stage_result.staged_entries.as_slice())),
+        verify: args.verify_cmd.as_deref().map(|command| (command, args.keep_scratch)),
+    };
+    let passthrough = &args.passthrough_args;
+    let output = commit_temp_index(&repo, &commit_index_path, passthrough, args.plumbing, checks)?;
+    backup.discard();
+
+    let entries = &stage_result.staged_entries;
+    if args.fold_hook_changes {
+        report.warnings = hook_changes::fold(&repo, intended, output.tree, entries)?;
+    }
+    if let Err(err) = journal::record(&repo, "commit-files", &output, entries, &index_before) {
+        report.warnings.push(format!("failed to journal the commit: {err:#}"));
+    }
+
+    if let Some(snapshot) = &snapshot {
+        snapshot.check(&repo, entries, Some(output.commit))?;
+    }
+
+    if format == Format::Human {
+        print_warnings(&report.warnings);
+        print_commit("commit-files", &output);
+    }
+    report.commit = Some((&output).into());
+    report.print(format);
 
     Ok(())
 }
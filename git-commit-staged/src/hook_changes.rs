@@ -0,0 +1,124 @@
+//! Index changes made by commit hooks (`--fold-hook-changes`).
+//!
+//! Hooks like lint-staged or pre-commit autofixers run `git add`. During
+//! our commit `GIT_INDEX_FILE` names the temp index, so their changes land
+//! there, and `git commit` (or `--plumbing`) commits them after the hook
+//! returns. The main index never sees them, and would then show the old
+//! content as a staged revert.
+//!
+//! This compares the tree we meant to commit with the one committed, and
+//! folds the difference into the main index. A path-scoped commit should
+//! not change other paths: with `--plumbing`, hook changes outside the
+//! commit abort it before the branch moves (see [`check_scope`]). `git
+//! commit` can't be stopped after its hooks, so there they are committed,
+//! reported, and left out of the main index.
+
+use anyhow::{bail, Context, Result};
+use git2::{Diff, Index, Oid, Repository};
+use std::collections::HashSet;
+use std::path::Path;
+
+use crate::staging::sync_main_index;
+use crate::StagedEntry;
+
+/// Tree of the temp index, as we mean to commit it.
+///
+/// # Errors
+/// Returns an error if the index cannot be read or its tree written.
+pub fn intended_tree(repo: &Repository, temp_index_path: &Path) -> Result<Oid> {
+    Index::open(temp_index_path)
+        .and_then(|mut index| index.write_tree_to(repo))
+        .context("failed to write tree from temp index")
+}
+
+/// Fail if hooks changed paths outside `entries`, the paths being committed
+/// (`committed` tree vs `intended`).
+///
+/// # Errors
+/// Returns an error naming those paths, or if the trees cannot be compared.
+pub fn check_scope(
+    repo: &Repository,
+    intended: Oid,
+    committed: Oid,
+    entries: &[StagedEntry],
+) -> Result<()> {
+    let (_, outside) = hook_changes(repo, intended, committed, entries)?;
+    if !outside.is_empty() {
+        let paths: Vec<&str> = outside.iter().map(|(path, _)| path.as_str()).collect();
+        bail!(
+            "a hook changed paths outside the ones being committed: {}",
+            paths.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// Fold hook changes (`committed` tree vs `intended`) to `entries`, the
+/// paths being committed, into the main index.
+///
+/// Changes to other paths are not folded; a warning is returned for each.
+///
+/// **Caller must hold index.lock.**
+///
+/// # Errors
+/// Returns an error if the trees cannot be compared or the main index
+/// cannot be updated.
+pub fn fold(
+    repo: &Repository,
+    intended: Oid,
+    committed: Oid,
+    entries: &[StagedEntry],
+) -> Result<Vec<String>> {
+    let (inside, outside) = hook_changes(repo, intended, committed, entries)?;
+    let warnings = outside
+        .iter()
+        .map(|(path, _)| {
+            format!(
+                "a hook changed {path}, which is outside the paths being committed; \
+                 committed, but not staged"
+            )
+        })
+        .collect();
+
+    if !inside.is_empty() {
+        let intended = repo.find_tree(intended).context("failed to read intended tree")?;
+        sync_main_index(repo, &intended, &inside)?;
+    }
+    Ok(warnings)
+}
+
+/// Hook changes (`committed` tree vs `intended`), split into those to
+/// `entries` and those to other paths.
+fn hook_changes(
+    repo: &Repository,
+    intended: Oid,
+    committed: Oid,
+    entries: &[StagedEntry],
+) -> Result<(Vec<StagedEntry>, Vec<StagedEntry>)> {
+    if intended == committed {
+        return Ok((Vec::new(), Vec::new()));
+    }
+    let intended = repo.find_tree(intended).context("failed to read intended tree")?;
+    let committed = repo.find_tree(committed).context("failed to read committed tree")?;
+    let diff = repo
+        .diff_tree_to_tree(Some(&intended), Some(&committed), None)
+        .context("failed to diff intended and committed trees")?;
+
+    let scope: HashSet<&str> = entries.iter().map(|(path, _)| path.as_str()).collect();
+    Ok(changed_entries(&diff)?
+        .into_iter()
+        .partition(|(path, _)| scope.contains(path.as_str())))
+}
+
+/// New side of each delta, as staged entries.
+fn changed_entries(diff: &Diff) -> Result<Vec<StagedEntry>> {
+    diff.deltas()
+        .map(|delta| {
+            let path = delta.new_file().path().context("diff delta has no path")?;
+            let path = path.to_str().context("path is not valid UTF-8")?.to_owned();
+            let data = (delta.status() != git2::Delta::Deleted)
+                .then(|| (delta.new_file().id(), u32::from(delta.new_file().mode())));
+            Ok((path, data))
+        })
+        .collect()
+}
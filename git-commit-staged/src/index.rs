@@ -1,10 +1,12 @@
 //! Temporary index file creation for path-scoped commits.
 
 use anyhow::{Context, Result};
-use git2::{Index, Repository};
+use git2::{Index, IndexEntry, IndexTime, Oid, Repository, Tree};
 use std::path::{Path, PathBuf};
 
-use crate::StagedEntry;
+use crate::cleanup;
+use crate::lock::replace_index;
+use crate::{head_tree, StagedEntry};
 
 /// Write a temporary index file containing HEAD + specified staged entries.
 ///
@@ -15,44 +17,53 @@ use crate::StagedEntry;
 /// Returns an error if HEAD cannot be resolved, index file cannot be created,
 /// or index operations fail.
 pub fn write_temp_index(repo: &Repository, entries: &[StagedEntry]) -> Result<PathBuf> {
-    let head = repo.head().context("failed to get HEAD")?;
-    let head_commit = head.peel_to_commit().context("failed to get HEAD commit")?;
-    let head_tree = head_commit.tree().context("failed to get HEAD tree")?;
+    let head_tree = head_tree(repo)?;
 
     // Determine temp index path: .git/index.commit-staged.<pid>
     let git_dir = repo.path(); // .git directory
     let pid = std::process::id();
     let temp_index_path = git_dir.join(format!("index.commit-staged.{pid}"));
+    cleanup::register(&temp_index_path);
 
-    // Create the temp index file and open it
+    write_index(&temp_index_path, &head_tree, entries)?;
+
+    Ok(temp_index_path)
+}
+
+/// Write an index file at `path` containing `base` tree + specified entries.
+///
+/// Any existing content at `path` is replaced.
+///
+/// # Errors
+/// Returns an error if the index file cannot be created or index operations fail.
+pub fn write_index(path: &Path, base: &Tree, entries: &[StagedEntry]) -> Result<()> {
     // Index::open creates a new file if it doesn't exist
-    let mut index = Index::open(&temp_index_path).context("failed to create temp index file")?;
+    let mut index = Index::open(path).context("failed to create index file")?;
 
-    // Read HEAD tree as the base
+    // Read base tree (replaces any existing entries)
     index
-        .read_tree(&head_tree)
-        .context("failed to read HEAD tree into index")?;
+        .read_tree(base)
+        .context("failed to read base tree into index")?;
 
     // Apply our staged entries
+    apply_entries(&mut index, entries)?;
+
+    // Write the index to disk
+    index.write().context("failed to write index")?;
+
+    Ok(())
+}
+
+/// Add (or, for deletions, remove) `entries` in `index`.
+///
+/// # Errors
+/// Returns an error if an entry cannot be added or removed.
+pub(crate) fn apply_entries(index: &mut Index, entries: &[StagedEntry]) -> Result<()> {
     for (path, data) in entries {
         match data {
             Some((oid, mode)) => {
-                let entry = git2::IndexEntry {
-                    ctime: git2::IndexTime::new(0, 0),
-                    mtime: git2::IndexTime::new(0, 0),
-                    dev: 0,
-                    ino: 0,
-                    mode: *mode,
-                    uid: 0,
-                    gid: 0,
-                    file_size: 0,
-                    id: *oid,
-                    flags: 0,
-                    flags_extended: 0,
-                    path: path.as_bytes().to_vec(),
-                };
                 index
-                    .add(&entry)
+                    .add(&unstatted_entry(path, *oid, *mode))
                     .with_context(|| format!("failed to add {path} to index"))?;
             }
             None => {
@@ -62,11 +73,71 @@ pub fn write_temp_index(repo: &Repository, entries: &[StagedEntry]) -> Result<Pa
             }
         }
     }
+    Ok(())
+}
 
-    // Write the index to disk
-    index.write().context("failed to write temp index")?;
+/// Blob and mode at `path` in `tree`, if any.
+pub(crate) fn tree_entry(tree: &Tree, path: &str) -> Option<(Oid, u32)> {
+    tree.get_path(Path::new(path))
+        .ok()
+        .map(|entry| (entry.id(), entry.filemode().cast_unsigned()))
+}
 
-    Ok(temp_index_path)
+/// Write a copy of `index`'s entries to a new index file at `path`.
+///
+/// Used to replace an index whose lock we already hold: `Index::write()`
+/// would try to take the lock itself.
+///
+/// # Errors
+/// Returns an error if the index file cannot be created or written.
+pub fn write_index_copy(index: &Index, path: &Path) -> Result<()> {
+    let mut copy = Index::open(path).context("failed to create index file")?;
+    copy.clear().context("failed to clear index")?;
+
+    for entry in index.iter() {
+        copy.add(&entry).with_context(|| {
+            format!(
+                "failed to copy entry {}",
+                String::from_utf8_lossy(&entry.path)
+            )
+        })?;
+    }
+
+    copy.write().context("failed to write index")
+}
+
+/// Replace `.git/index` with a copy of `index`.
+///
+/// **Caller must hold index.lock.**
+///
+/// # Errors
+/// Returns an error if the new index cannot be written or renamed into place.
+pub(crate) fn replace_main_index(repo: &Repository, index: &Index) -> Result<()> {
+    // Write beside the real index, then through index.lock (which we hold)
+    let temp_path = repo
+        .path()
+        .join(format!("index.stage.{}", std::process::id()));
+    cleanup::register(&temp_path);
+    write_index_copy(index, &temp_path)?;
+    replace_index(repo, &temp_path)
+}
+
+/// Index entry without stat data, so git re-hashes the working copy file.
+pub(crate) fn unstatted_entry(path: &str, oid: Oid, mode: u32) -> IndexEntry {
+    IndexEntry {
+        ctime: IndexTime::new(0, 0),
+        mtime: IndexTime::new(0, 0),
+        dev: 0,
+        ino: 0,
+        mode,
+        uid: 0,
+        gid: 0,
+        file_size: 0,
+        id: oid,
+        flags: 0,
+        flags_extended: 0,
+        path: path.as_bytes().to_vec(),
+    }
 }
 
 /// Convenience wrapper that opens repo from environment.
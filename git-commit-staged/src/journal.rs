@@ -0,0 +1,428 @@
+//! Operation journal and `undo`.
+//!
+//! Every commit made by `git commit-staged`, `git commit-files` or
+//! `git stage-area commit` appends a record to
+//! `.git/commit-staged/journal.jsonl`: when, by which session and command
+//! line, the ref's old and new targets, the entries committed, and the main
+//! index entries those paths had beforehand.
+//!
+//! `git commit-staged undo` uses a record to take the commit back, scoped
+//! to its paths: the ref moves back if it still points at the commit (a
+//! root commit's branch is deleted), a revert is committed on top if the
+//! ref has moved on from it, and the committed paths' main index entries
+//! return to what they were before. Other paths are left alone. Nothing is
+//! undone unless HEAD is the ref the commit was made on, and contains it.
+//! Undos are journaled too, and never undone twice.
+
+use anyhow::{bail, Context, Result};
+use git2::{Oid, Repository, Tree};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::commit::{read_head, CommitOutput};
+use crate::error::Error;
+use crate::expect::parse_oid;
+use crate::index::{apply_entries, replace_main_index};
+use crate::plan::PlanFileEntry;
+use crate::plumbing::{add_trailers, identity, rewind_ref, update_ref};
+use crate::rebase::conflict_paths;
+use crate::session::{Session, SESSION_ENV};
+use crate::StagedEntry;
+
+/// Operation name of journaled undos.
+pub const UNDO: &str = "undo";
+
+/// One journaled operation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Record {
+    /// Seconds since the Unix epoch
+    pub time: u64,
+    /// `commit-staged`, `commit-files`, `stage-area` or `undo`
+    pub operation: String,
+    /// `$GIT_PARTIAL_SESSION`, if set
+    pub session: Option<String>,
+    /// Command line
+    pub command: Vec<String>,
+    /// The ref updated (`HEAD` when detached)
+    pub reference: String,
+    /// Its target before (`None` if unborn)
+    pub old_head: Option<String>,
+    /// Its target after (all zeros if an undo deleted it)
+    pub new_head: String,
+    /// Entries committed (for an undo, the index entries restored)
+    entries: Vec<PlanFileEntry>,
+    /// Main index entries of the same paths before the operation
+    index_before: Vec<PlanFileEntry>,
+    /// For an undo, the commit undone
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub undoes: Option<String>,
+}
+
+/// The result of undoing one operation.
+#[derive(Debug)]
+pub struct Undone {
+    /// The commit taken back
+    pub commit: Oid,
+    /// The revert committed, if the ref had moved on
+    pub revert: Option<Oid>,
+    /// Paths whose main index entries were restored
+    pub restored: Vec<String>,
+    /// Paths left alone, and why
+    pub warnings: Vec<String>,
+}
+
+/// Path of the journal.
+#[must_use]
+pub fn journal_path(repo: &Repository) -> PathBuf {
+    repo.path().join("commit-staged").join("journal.jsonl")
+}
+
+/// Main index entries (stage 0) of each of `entries`' paths.
+///
+/// # Errors
+/// Returns an error if the index cannot be read.
+pub fn index_entries(repo: &Repository, entries: &[StagedEntry]) -> Result<Vec<StagedEntry>> {
+    let mut index = repo.index().context("failed to open index")?;
+    index.read(false).context("failed to read index")?;
+    Ok(entries
+        .iter()
+        .map(|(path, _)| {
+            let data = index
+                .get_path(Path::new(path), 0)
+                .map(|entry| (entry.id, entry.mode));
+            (path.clone(), data)
+        })
+        .collect())
+}
+
+/// Journal a commit of `entries`, whose main index entries were
+/// `index_before`.
+///
+/// # Errors
+/// Returns an error if the journal cannot be written.
+pub fn record(
+    repo: &Repository,
+    operation: &str,
+    output: &CommitOutput,
+    entries: &[StagedEntry],
+    index_before: &[StagedEntry],
+) -> Result<()> {
+    append(
+        repo,
+        &Record::new(operation, output.reference.clone(), output.old_head, output.commit)
+            .with_entries(entries, index_before),
+    )
+}
+
+/// Every journaled operation, oldest first.
+///
+/// # Errors
+/// Returns an error if the journal exists but cannot be read or parsed.
+pub fn read(repo: &Repository) -> Result<Vec<Record>> {
+    let path = journal_path(repo);
+    let text = match std::fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err).with_context(|| format!("failed to read {}", path.display())),
+    };
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(number, line)| {
+            serde_json::from_str(line)
+                .with_context(|| format!("{}:{}: invalid record", path.display(), number + 1))
+        })
+        .collect()
+}
+
+/// The last `count` journaled commits that haven't been undone, newest
+/// first.
+///
+/// # Errors
+/// Returns an error if there are fewer than `count`, or the journal cannot
+/// be read.
+pub fn undoable(repo: &Repository, count: usize) -> Result<Vec<Record>> {
+    let records = read(repo)?;
+    let undone: HashSet<&str> = records.iter().filter_map(|r| r.undoes.as_deref()).collect();
+    let pending: Vec<Record> = records
+        .iter()
+        .rev()
+        .filter(|r| r.operation != UNDO && !undone.contains(r.new_head.as_str()))
+        .take(count)
+        .cloned()
+        .collect();
+    if pending.len() < count {
+        bail!(
+            "only {} journaled commit(s) left to undo in {}",
+            pending.len(),
+            journal_path(repo).display()
+        );
+    }
+    Ok(pending)
+}
+
+/// Undo a journaled commit, and journal the undo.
+///
+/// **Caller must hold index.lock.**
+///
+/// # Errors
+/// Returns [`Error::Conflict`] if a revert conflicts with later changes,
+/// [`Error::HeadMoved`] if the ref moved during the undo, an error if HEAD
+/// isn't the ref the commit was made on or doesn't contain it, or an error
+/// if the repository, index or journal cannot be read or written.
+pub fn undo(repo: &Repository, record: &Record) -> Result<Undone> {
+    let commit = parse_oid(&record.new_head)?;
+    let old_head = record.old_head.as_deref().map(parse_oid).transpose()?;
+    let (reference, head) = read_head(repo)?;
+
+    let (new_head, revert) = match step(repo, record, &reference, head, commit, true)? {
+        Step::Rewind => {
+            let message = format!("commit-staged: undo {commit}");
+            rewind_ref(repo, &reference, commit, old_head, &message)?;
+            // A root commit's branch is deleted, which git logs as all zeros
+            (old_head.unwrap_or_else(Oid::zero), None)
+        }
+        Step::Revert(head) => {
+            let revert = revert(repo, &reference, head, commit, old_head)?;
+            (revert, Some(revert))
+        }
+    };
+
+    let committed = parse_entries(&record.entries)?;
+    let before = parse_entries(&record.index_before)?;
+    let (restore, warnings) = entries_to_restore(repo, &committed, &before)?;
+    let current = index_entries(repo, &restore)?;
+    if !restore.is_empty() {
+        let mut index = repo.index().context("failed to open index")?;
+        index.read(false).context("failed to read index")?;
+        apply_entries(&mut index, &restore)?;
+        replace_main_index(repo, &index)?;
+    }
+
+    let mut record = Record::new(UNDO, reference, head, new_head).with_entries(&restore, &current);
+    record.undoes = Some(commit.to_string());
+    append(repo, &record)?;
+
+    Ok(Undone {
+        commit,
+        revert,
+        restored: restore.into_iter().map(|(path, _)| path).collect(),
+        warnings,
+    })
+}
+
+/// Check that `records`, newest first as from [`undoable`], can all be
+/// undone in turn, without changing anything.
+///
+/// Each must be on HEAD's ref and contained in it, and each revert must
+/// merge onto the ones before it.
+///
+/// # Errors
+/// Returns [`Error::Conflict`] if a revert would conflict, or an error if a
+/// commit can't be undone or the repository cannot be read.
+pub fn check_undo(repo: &Repository, records: &[Record]) -> Result<()> {
+    let (reference, mut head) = read_head(repo)?;
+    // The tree of the reverts that would be committed on top of `head`
+    let mut reverted: Option<Tree> = None;
+    for record in records {
+        let commit = parse_oid(&record.new_head)?;
+        let old_head = record.old_head.as_deref().map(parse_oid).transpose()?;
+        match step(repo, record, &reference, head, commit, reverted.is_none())? {
+            Step::Rewind => head = old_head,
+            Step::Revert(head) => {
+                let tree = match reverted {
+                    Some(tree) => tree,
+                    None => repo
+                        .find_commit(head)
+                        .and_then(|head| head.tree())
+                        .context("failed to read HEAD tree")?,
+                };
+                reverted = Some(revert_tree(repo, &tree, commit, old_head)?);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// How a commit is undone.
+enum Step {
+    /// Move the ref back: it still points at the commit
+    Rewind,
+    /// Commit a revert on top of this descendant
+    Revert(Oid),
+}
+
+/// How to undo `record`'s `commit` with HEAD at `reference` and `head`;
+/// `rewindable` is false once reverts are pending on top of `head`.
+fn step(
+    repo: &Repository,
+    record: &Record,
+    reference: &str,
+    head: Option<Oid>,
+    commit: Oid,
+    rewindable: bool,
+) -> Result<Step> {
+    if reference != record.reference {
+        bail!(
+            "{commit} was committed to {}, not {reference}; check it out to undo the commit",
+            record.reference
+        );
+    }
+    match head {
+        Some(head) if head == commit && rewindable => Ok(Step::Rewind),
+        Some(head) if head == commit || repo.graph_descendant_of(head, commit).unwrap_or(false) => {
+            Ok(Step::Revert(head))
+        }
+        _ => bail!("{reference} no longer contains {commit}; not reverting it"),
+    }
+}
+
+/// Commit the inverse of `commit` (relative to `old_head`) on top of `head`.
+fn revert(
+    repo: &Repository,
+    reference: &str,
+    head: Oid,
+    commit: Oid,
+    old_head: Option<Oid>,
+) -> Result<Oid> {
+    let head_commit = repo.find_commit(head).context("failed to read HEAD")?;
+    let undone = repo
+        .find_commit(commit)
+        .with_context(|| format!("failed to read commit {commit}"))?;
+    let head_tree = head_commit.tree().context("failed to read HEAD tree")?;
+    let tree = revert_tree(repo, &head_tree, commit, old_head)?;
+
+    // The same identity and trailers as any other commit of the session
+    let config = repo.config().context("failed to read git config")?;
+    let session = Session::from_env(repo)?;
+    let committer = identity(&config, "committer", None)?;
+    let author = identity(&config, "author", session.as_ref())?;
+    let mut message = format!(
+        "Revert \"{}\"\n\nThis reverts commit {commit}.\n",
+        undone.summary().unwrap_or_default()
+    );
+    if let Some(session) = &session {
+        message = add_trailers(&message, &session.all_trailers())?;
+    }
+    let revert = repo
+        .commit(None, &author, &committer, &message, &tree, &[&head_commit])
+        .context("failed to create revert commit")?;
+    let log_message = format!("commit-staged: revert {commit}");
+    update_ref(repo, reference, revert, Some(head), &log_message)?;
+    Ok(revert)
+}
+
+/// `tree` with the changes `commit` made since `old_head` taken back.
+///
+/// # Errors
+/// Returns [`Error::Conflict`] if they conflict with changes in `tree`.
+fn revert_tree<'repo>(
+    repo: &'repo Repository,
+    tree: &Tree,
+    commit: Oid,
+    old_head: Option<Oid>,
+) -> Result<Tree<'repo>> {
+    let undone = repo
+        .find_commit(commit)
+        .with_context(|| format!("failed to read commit {commit}"))?;
+    let before = old_head
+        .map_or_else(
+            // Before a root commit: the empty tree
+            || {
+                repo.treebuilder(None)
+                    .and_then(|builder| builder.write())
+                    .and_then(|oid| repo.find_tree(oid))
+            },
+            |old| repo.find_commit(old).and_then(|c| c.tree()),
+        )
+        .with_context(|| format!("failed to read the tree before {commit}"))?;
+
+    let mut merged = repo
+        .merge_trees(
+            &undone.tree().context("failed to read commit tree")?,
+            tree,
+            &before,
+            None,
+        )
+        .context("failed to merge the revert into HEAD")?;
+    if merged.has_conflicts() {
+        return Err(Error::Conflict {
+            paths: conflict_paths(&merged)?,
+        }
+        .into());
+    }
+    merged
+        .write_tree_to(repo)
+        .and_then(|oid| repo.find_tree(oid))
+        .context("failed to write revert tree")
+}
+
+/// The pre-commit index entries to put back: only for paths whose entry is
+/// still what was committed.
+fn entries_to_restore(
+    repo: &Repository,
+    committed: &[StagedEntry],
+    before: &[StagedEntry],
+) -> Result<(Vec<StagedEntry>, Vec<String>)> {
+    let current = index_entries(repo, committed)?;
+    let mut restore = Vec::new();
+    let mut warnings = Vec::new();
+    for ((path, committed), (_, now)) in committed.iter().zip(&current) {
+        if now != committed {
+            warnings.push(format!("left {path} alone: its index entry changed since the commit"));
+            continue;
+        }
+        let data = before
+            .iter()
+            .find(|(before_path, _)| before_path == path)
+            .and_then(|(_, data)| *data);
+        restore.push((path.clone(), data));
+    }
+    Ok((restore, warnings))
+}
+
+fn parse_entries(entries: &[PlanFileEntry]) -> Result<Vec<StagedEntry>> {
+    entries.iter().cloned().map(PlanFileEntry::parse).collect()
+}
+
+fn append(repo: &Repository, record: &Record) -> Result<()> {
+    let path = journal_path(repo);
+    let dir = path.parent().context("journal has no parent directory")?;
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("failed to create {}", dir.display()))?;
+    let line = serde_json::to_string(record).context("failed to serialize journal record")?;
+    std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| file.write_all(format!("{line}\n").as_bytes()))
+        .with_context(|| format!("failed to append to {}", path.display()))
+}
+
+impl Record {
+    fn new(operation: &str, reference: String, old_head: Option<Oid>, new_head: Oid) -> Self {
+        Self {
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_secs()),
+            operation: operation.to_owned(),
+            session: std::env::var(SESSION_ENV).ok().filter(|id| !id.is_empty()),
+            command: std::env::args().collect(),
+            reference,
+            old_head: old_head.map(|oid| oid.to_string()),
+            new_head: new_head.to_string(),
+            entries: Vec::new(),
+            index_before: Vec::new(),
+            undoes: None,
+        }
+    }
+
+    fn with_entries(mut self, entries: &[StagedEntry], index_before: &[StagedEntry]) -> Self {
+        self.entries = entries.iter().map(PlanFileEntry::new).collect();
+        self.index_before = index_before.iter().map(PlanFileEntry::new).collect();
+        self
+    }
+}
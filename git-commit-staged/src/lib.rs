@@ -14,24 +14,62 @@
 //! - [`prepare`] - Core logic for preparing staged commits
 //! - [`index`] - Temporary index file creation
 //! - [`exec`] - CLI execution helpers
+//! - [`error`] - Typed failure modes and exit codes
+//! - [`expect`] - Compare-and-swap guards on HEAD and the content to commit
+//! - [`cleanup`] - Removal of run artifacts on signals and early exits
+//! - [`codeowners`] - CODEOWNERS files, for splitting commits by owner
+//! - [`doctor`] - Leftover-file sweeper and repository health checks
+//! - [`hook_changes`] - Folding index changes made by commit hooks
+//! - [`journal`] - Operation journal and `undo`
+//! - [`lock_owner`] - Lock ownership records and stale-lock recovery
+//! - [`output`] - Machine-readable `--json` / `--porcelain` output
+//! - [`paranoid`] - Checking nothing else was touched (`--paranoid`)
+//! - [`plan`] - Commit plans recorded by a dry run
+//! - [`plumbing`] - Commits made with git2 instead of `git commit`
+//! - [`rebase`] - Re-applying changes when HEAD moved off their base
+//! - [`series`] - Several commits made as one atomic series
+//! - [`session`] - Per-session commit identity and trailers
+//! - [`staging`] - Named persistent staging areas
+//! - [`status`] - Staged/unstaged status grouped by claimant
+//! - [`verify`] - Running a command on the exact tree to commit
 
-use anyhow::{bail, Context, Result};
-use git2::Oid;
+use anyhow::{Context, Result};
+use git2::{Commit, Oid, Repository, Tree};
 use std::path::{Path, PathBuf};
-use std::process::Command;
 
+pub mod cleanup;
+pub mod codeowners;
 pub mod commit;
+pub mod doctor;
+pub mod error;
+pub mod expect;
 pub mod exec;
+pub mod hook_changes;
 pub mod index;
+pub mod journal;
 pub mod lock;
+pub mod lock_owner;
+pub mod output;
+pub mod paranoid;
+pub mod plan;
+pub mod plumbing;
 pub mod prepare;
+pub mod rebase;
+pub mod series;
+pub mod session;
+pub mod staging;
+pub mod status;
 pub mod unglobbed_path;
+pub mod verify;
 
 #[cfg(test)]
 mod tests;
 
 // Re-export main entry points
-pub use prepare::prepare_staged_commit;
+pub use error::Error;
+pub use prepare::{prepare_staged_commit, prepare_staged_commit_from, Source};
+
+use session::{git_commit_command, Session};
 
 /// Result of preparing staged changes for commit
 #[derive(Debug)]
@@ -45,6 +83,34 @@ pub struct PrepareResult {
 /// Entry: (path, `blob_oid`, filemode) - None means deletion
 pub type StagedEntry = (String, Option<(Oid, u32)>);
 
+/// HEAD's commit.
+///
+/// # Errors
+/// Returns [`Error::EmptyRepo`] if HEAD is unborn, or an error if HEAD cannot be read.
+pub(crate) fn head_commit(repo: &Repository) -> Result<Commit<'_>> {
+    let head = match repo.head() {
+        Err(e) if e.code() == git2::ErrorCode::UnbornBranch => return Err(Error::EmptyRepo.into()),
+        head => head.context("failed to get HEAD")?,
+    };
+    head.peel_to_commit().context("failed to get HEAD commit")
+}
+
+/// HEAD's tree.
+///
+/// # Errors
+/// Returns [`Error::EmptyRepo`] if HEAD is unborn, or an error if HEAD cannot be read.
+pub(crate) fn head_tree(repo: &Repository) -> Result<Tree<'_>> {
+    head_commit(repo)?.tree().context("failed to get HEAD tree")
+}
+
+/// The git dir shared by all worktrees, given any worktree's git dir.
+pub(crate) fn common_dir(git_dir: &Path) -> PathBuf {
+    // A worktree's git dir names the common dir in its `commondir` file
+    let common = std::fs::read_to_string(git_dir.join("commondir"))
+        .map_or_else(|_| git_dir.to_path_buf(), |rel| git_dir.join(rel.trim()));
+    std::fs::canonicalize(&common).unwrap_or(common)
+}
+
 /// Result of a commit operation
 #[derive(Debug)]
 pub struct CommitResult {
@@ -67,7 +133,8 @@ pub struct CommitResult {
 /// * `dry_run` - If true, show what would be committed without committing
 ///
 /// # Errors
-/// Returns an error if preparation fails or if `git commit` fails.
+/// Returns an error if the repository or `$GIT_PARTIAL_SESSION`'s config
+/// cannot be read, preparation fails, or `git commit` fails.
 ///
 /// # Panics
 /// Panics if `dry_run` is false but no temp index path is returned (internal invariant).
@@ -77,6 +144,9 @@ pub fn git_commit_staged(
     directory: &Path,
     dry_run: bool,
 ) -> Result<CommitResult> {
+    // Before the temp index exists, so an error leaves nothing behind
+    let repo = Repository::discover(directory).context("failed to open repository")?;
+    let session = Session::from_env(&repo)?;
     let result = prepare_staged_commit(paths, directory, dry_run)?;
 
     if dry_run {
@@ -89,11 +159,11 @@ pub fn git_commit_staged(
         .temp_index_path
         .expect("non-dry-run should have temp index");
 
+    let editmsg_before = commit::editmsg_stamp(&repo);
+
     // Run git commit with the temp index
-    let output = Command::new("git")
-        .arg("-C")
-        .arg(directory)
-        .arg("commit")
+    let output = git_commit_command(session.as_ref())
+        .current_dir(directory)
         .arg("-m")
         .arg(message)
         .env("GIT_INDEX_FILE", &temp_index_path)
@@ -101,11 +171,10 @@ pub fn git_commit_staged(
         .context("failed to run git commit")?;
 
     // Always clean up the temp index
-    let _ = std::fs::remove_file(&temp_index_path);
+    let _ = cleanup::remove(&temp_index_path);
 
     if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        bail!("git commit failed: {stderr}");
+        return Err(commit::commit_failure(&repo, &[], &output, editmsg_before)?.into());
     }
 
     Ok(CommitResult {
@@ -2,11 +2,27 @@
 //!
 //! Provides exclusive locking via `.git/index.lock` to serialize
 //! with other git processes during commit operations.
+//!
+//! By default a held lock fails immediately. A [`WaitPolicy`] makes
+//! acquisition retry with jittered exponential backoff, optionally taking a
+//! ticket in `.git/commit-staged/queue/` so waiters acquire in FIFO order.
 
 use anyhow::{Context, Result};
 use git2::Repository;
 use std::fs::{File, OpenOptions};
-use std::path::PathBuf;
+use std::hash::{BuildHasher, Hasher};
+use std::io::ErrorKind;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::cleanup;
+use crate::error::Error;
+use crate::lock_owner::{sidecar_path, LockOwner};
+
+const INITIAL_BACKOFF: Duration = Duration::from_millis(10);
+const MAX_BACKOFF: Duration = Duration::from_secs(1);
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
 
 /// RAII guard for `.git/index.lock`.
 ///
@@ -18,41 +34,186 @@ pub struct IndexLock {
     file: File,
 }
 
+/// How long to wait for a lock held by another process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Patience {
+    /// Fail immediately
+    #[default]
+    Immediate,
+    /// Retry until this much time has passed
+    Until(Duration),
+    /// Retry indefinitely
+    Forever,
+}
+
+/// Lock acquisition policy.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WaitPolicy {
+    /// How long to wait
+    pub patience: Patience,
+    /// Take a queue ticket so waiters acquire in FIFO order
+    pub queue: bool,
+}
+
+impl WaitPolicy {
+    /// Read defaults from git config: `commitStaged.wait` (seconds; 0 means
+    /// don't wait, negative means wait forever) and `commitStaged.queue`.
+    ///
+    /// # Errors
+    /// Returns an error if the config cannot be read.
+    pub fn from_config(repo: &Repository) -> Result<Self> {
+        let config = repo.config().context("failed to read git config")?;
+        let patience = match config.get_i64("commitStaged.wait") {
+            Ok(secs) if secs < 0 => Patience::Forever,
+            Ok(0) | Err(_) => Patience::Immediate,
+            Ok(secs) => Patience::Until(Duration::from_secs(secs.unsigned_abs())),
+        };
+        let queue = config.get_bool("commitStaged.queue").unwrap_or(false);
+        Ok(Self { patience, queue })
+    }
+
+    /// Apply command-line overrides: `--wait` (`Some(None)`),
+    /// `--wait=SECONDS` (`Some(Some(secs))`) and `--queue`.
+    #[must_use]
+    pub const fn with_overrides(self, wait: Option<Option<u64>>, queue: bool) -> Self {
+        let patience = match wait {
+            None => self.patience,
+            Some(None) => Patience::Forever,
+            Some(Some(0)) => Patience::Immediate,
+            Some(Some(secs)) => Patience::Until(Duration::from_secs(secs)),
+        };
+        Self {
+            patience,
+            queue: self.queue || queue,
+        }
+    }
+}
+
 impl IndexLock {
     /// Acquire `.git/index.lock` exclusively.
     ///
     /// Fails immediately if lock is held by another process (no retry/wait).
     ///
     /// # Errors
-    /// Returns an error if the repository cannot be opened or the lock is already held.
+    /// Returns an error if the repository cannot be opened or the lock is
+    /// already held ([`Error::LockHeld`]).
     pub fn acquire() -> Result<Self> {
         let repo = Repository::open_from_env().context("failed to open repository")?;
         Self::acquire_for_repo(&repo)
     }
 
+    /// Acquire `.git/index.lock`, waiting per config and command-line overrides.
+    ///
+    /// See [`WaitPolicy::from_config`] and [`WaitPolicy::with_overrides`].
+    ///
+    /// # Errors
+    /// Returns an error if the repository cannot be opened, the lock cannot be
+    /// created, or waiting times out ([`Error::LockTimeout`]).
+    pub fn acquire_waiting(wait: Option<Option<u64>>, queue: bool) -> Result<Self> {
+        let repo = Repository::open_from_env().context("failed to open repository")?;
+        let policy = WaitPolicy::from_config(&repo)?.with_overrides(wait, queue);
+        Self::acquire_for_repo_with(&repo, &policy)
+    }
+
     /// Acquire lock for a specific repository.
     ///
     /// # Errors
     /// Returns an error if the lock file cannot be created (already held or permission denied).
     pub fn acquire_for_repo(repo: &Repository) -> Result<Self> {
+        Self::acquire_for_repo_with(repo, &WaitPolicy::default())
+    }
+
+    /// Acquire lock for a specific repository, waiting according to `policy`.
+    ///
+    /// Progress is reported on stderr while waiting.
+    ///
+    /// # Errors
+    /// Returns an error if the lock file cannot be created, or waiting times
+    /// out ([`Error::LockTimeout`]).
+    pub fn acquire_for_repo_with(repo: &Repository, policy: &WaitPolicy) -> Result<Self> {
         let lock_path = repo.path().join("index.lock");
 
-        let file = OpenOptions::new()
+        if policy.patience == Patience::Immediate {
+            return Self::try_acquire(&lock_path)?.ok_or_else(|| held_error(&lock_path));
+        }
+
+        let ticket = if policy.queue {
+            Some(Ticket::take(repo.path())?)
+        } else {
+            None
+        };
+
+        let start = Instant::now();
+        let mut backoff = INITIAL_BACKOFF;
+        let mut next_progress = Duration::ZERO;
+
+        loop {
+            let position = match &ticket {
+                Some(ticket) => ticket.position()?,
+                None => 0,
+            };
+            if position == 0
+                && let Some(lock) = Self::try_acquire(&lock_path)?
+            {
+                return Ok(lock);
+            }
+
+            let waited = start.elapsed();
+            let remaining = match policy.patience {
+                Patience::Until(limit) if waited >= limit => {
+                    return Err(Error::LockTimeout {
+                        path: lock_path,
+                        waited,
+                    }
+                    .into());
+                }
+                Patience::Until(limit) => limit.saturating_sub(waited),
+                Patience::Immediate | Patience::Forever => MAX_BACKOFF,
+            };
+
+            if waited >= next_progress {
+                if position > 0 {
+                    eprintln!(
+                        "waiting for index lock ({}s, {position} ahead in queue)",
+                        waited.as_secs()
+                    );
+                } else {
+                    eprintln!(
+                        "waiting for index lock at {} ({}s)",
+                        lock_path.display(),
+                        waited.as_secs()
+                    );
+                }
+                next_progress = waited + PROGRESS_INTERVAL;
+            }
+
+            std::thread::sleep(jitter(backoff).min(remaining));
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+
+    /// Try once to create the lock file. `Ok(None)` if another process holds it.
+    fn try_acquire(lock_path: &Path) -> Result<Option<Self>> {
+        match OpenOptions::new()
             .write(true)
             .create_new(true)
-            .open(&lock_path)
-            .with_context(|| {
-                format!(
-                    "failed to acquire index lock at {}\n\
-                     Another git process may be running. If not, remove the stale lock file.",
-                    lock_path.display()
-                )
-            })?;
-
-        Ok(Self {
-            path: lock_path,
-            file,
-        })
+            .open(lock_path)
+        {
+            Ok(file) => {
+                cleanup::register(lock_path);
+                let lock = Self {
+                    path: lock_path.to_path_buf(),
+                    file,
+                };
+                cleanup::register(&sidecar_path(lock_path));
+                LockOwner::current(&lock.file)?.write(lock_path)?;
+                Ok(Some(lock))
+            }
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => Ok(None),
+            Err(e) => Err(e).with_context(|| {
+                format!("failed to acquire index lock at {}", lock_path.display())
+            }),
+        }
     }
 
     /// Acquire lock for a repository at a specific path.
@@ -66,9 +227,207 @@ impl IndexLock {
     }
 }
 
+fn held_error(lock_path: &Path) -> anyhow::Error {
+    Error::LockHeld {
+        path: lock_path.to_path_buf(),
+        owner: LockOwner::read(lock_path),
+    }
+    .into()
+}
+
+/// Replace `.git/index` with the index file at `new_index`, keeping the lock.
+///
+/// Follows git's lockfile protocol (see [`install_index`]), then gives the
+/// lock a fresh empty file, as its old one is now the index. `new_index`
+/// (an `index.stage.<pid>` file or an index backup, reused for the link and
+/// the fresh lock) is removed.
+///
+/// **Caller must hold index.lock.**
+///
+/// # Errors
+/// Returns an error if the lock is not held, or any step fails. The old
+/// index is intact unless the final rename succeeded.
+pub(crate) fn replace_index(repo: &Repository, new_index: &Path) -> Result<()> {
+    let lock_path = repo.path().join("index.lock");
+    // Stays registered while it is reused for the link and the fresh lock
+    install_index(repo.path(), new_index, fsync_index(repo))?;
+
+    // A fresh lock file: the old one is the index now
+    let fresh = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(new_index)
+        .context("failed to create fresh index lock")?;
+    std::fs::rename(new_index, &lock_path).context("failed to replace index lock")?;
+    cleanup::forget(new_index);
+    if let Some(mut owner) = LockOwner::read(&lock_path) {
+        owner.lock_inode = fresh.metadata().context("failed to stat lock file")?.ino();
+        owner.write(&lock_path)?;
+    }
+    Ok(())
+}
+
+/// Install the index file at `new_index` as `git_dir`'s index, through the
+/// held `index.lock`.
+///
+/// The new index is written into `index.lock` itself, fsynced if `fsync`,
+/// and renamed into place. The rename goes through a hard link at
+/// `new_index`, so the lock never disappears; afterwards it is another name
+/// for the index. Touches no cleanup registrations, so signal cleanup can
+/// restore a backup with it while holding them.
+///
+/// **Caller must hold index.lock.**
+///
+/// # Errors
+/// Returns an error if the lock is not held, or any step fails. The old
+/// index is intact unless the final rename succeeded.
+pub(crate) fn install_index(git_dir: &Path, new_index: &Path, fsync: bool) -> Result<()> {
+    let lock_path = git_dir.join("index.lock");
+
+    // Into the lock itself, never creating it
+    let mut lock = OpenOptions::new()
+        .write(true)
+        .truncate(true)
+        .open(&lock_path)
+        .with_context(|| format!("index lock {} is not held", lock_path.display()))?;
+    File::open(new_index)
+        .and_then(|mut source| std::io::copy(&mut source, &mut lock))
+        .and_then(|_| if fsync { lock.sync_all() } else { Ok(()) })
+        .context("failed to write new index into index.lock")?;
+    drop(lock);
+    std::fs::remove_file(new_index).context("failed to remove temp index")?;
+
+    // Into place through a link, so the lock stays held
+    std::fs::hard_link(&lock_path, new_index)
+        .and_then(|()| std::fs::rename(new_index, git_dir.join("index")))
+        .context("failed to rename new index into place")?;
+    if fsync {
+        sync_dir(git_dir)?;
+    }
+    Ok(())
+}
+
+/// Fsync a directory, making renames and new entries in it durable.
+///
+/// # Errors
+/// Returns an error if the directory cannot be opened or synced.
+pub(crate) fn sync_dir(dir: &Path) -> Result<()> {
+    File::open(dir)
+        .and_then(|dir| dir.sync_all())
+        .with_context(|| format!("failed to fsync {}", dir.display()))
+}
+
+/// Whether to fsync the index when writing it, per `core.fsync`.
+///
+/// Unlike git, the index is fsynced by default: only `none`, `-index`,
+/// `-added` or `-all` turn it off. (`derived-metadata` only covers pack
+/// metadata and the commit graph.)
+#[must_use]
+pub fn fsync_index(repo: &Repository) -> bool {
+    let Ok(components) = repo.config().and_then(|config| config.get_string("core.fsync")) else {
+        return true;
+    };
+    components
+        .split(',')
+        .map(str::trim)
+        .fold(true, |fsync, component| match component {
+            "none" | "-index" | "-added" | "-all" => false,
+            "all" | "index" | "added" => true,
+            _ => fsync,
+        })
+}
+
+/// Randomize a backoff delay to between 50% and 100% of `delay`.
+fn jitter(delay: Duration) -> Duration {
+    let random = std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish();
+    let fraction = 0.5 + f64::from(u32::try_from(random % 1000).unwrap_or(0)) / 2000.0;
+    delay.mul_f64(fraction)
+}
+
+/// Whether a process with this PID exists on this host.
+pub(crate) fn pid_alive(pid: u32) -> bool {
+    // 0 and out-of-range values would address process groups, not a process
+    let Ok(pid @ 1..) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // SAFETY: signal 0 performs error checking only; nothing is delivered.
+    let ret = unsafe { libc::kill(pid, 0) };
+    ret == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// A place in the FIFO queue of lock waiters.
+///
+/// Tickets are files in `.git/commit-staged/queue/`, named so that they sort
+/// in arrival order and containing the owner's PID. They are written under a
+/// hidden name and renamed into place, so a ticket is never seen empty.
+/// Tickets of dead processes are discarded. The ticket is removed on drop.
+struct Ticket {
+    dir: PathBuf,
+    name: String,
+}
+
+impl Ticket {
+    fn take(git_dir: &Path) -> Result<Self> {
+        let dir = git_dir.join("commit-staged").join("queue");
+        std::fs::create_dir_all(&dir).context("failed to create lock queue directory")?;
+
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let pid = std::process::id();
+        let name = format!("{nanos:024}-{pid}");
+        let temp = dir.join(format!(".{name}"));
+        cleanup::register(&temp);
+        cleanup::register(&dir.join(&name));
+        std::fs::write(&temp, format!("{pid}\n"))
+            .and_then(|()| std::fs::rename(&temp, dir.join(&name)))
+            .context("failed to create lock queue ticket")?;
+        cleanup::forget(&temp);
+
+        Ok(Self { dir, name })
+    }
+
+    /// Number of live tickets ahead of this one.
+    fn position(&self) -> Result<usize> {
+        let mut ahead = 0;
+        for entry in std::fs::read_dir(&self.dir).context("failed to read lock queue")? {
+            let entry = entry.context("failed to read lock queue entry")?;
+            let name = entry.file_name();
+            let Some(name) = name.to_str() else { continue };
+            // Hidden: a ticket still being written
+            if name.starts_with('.') || name >= self.name.as_str() {
+                continue;
+            }
+
+            let owner = std::fs::read_to_string(entry.path())
+                .ok()
+                .and_then(|text| text.trim().parse().ok());
+            match owner {
+                Some(pid) if pid_alive(pid) => ahead += 1,
+                // Abandoned ticket: its owner died without cleaning up
+                _ => {
+                    let _ = std::fs::remove_file(entry.path());
+                }
+            }
+        }
+        Ok(ahead)
+    }
+}
+
+impl Drop for Ticket {
+    fn drop(&mut self) {
+        let _ = cleanup::remove(&self.dir.join(&self.name));
+    }
+}
+
 impl Drop for IndexLock {
     fn drop(&mut self) {
-        if let Err(e) = std::fs::remove_file(&self.path) {
+        // Sidecar first: a lock without a sidecar is never removed as stale
+        let _ = cleanup::remove(&sidecar_path(&self.path));
+        if let Err(e) = cleanup::remove(&self.path) {
             eprintln!("warning: failed to remove index lock: {e}");
         }
     }
@@ -84,7 +443,7 @@ mod tests {
         let repo = Repository::init(dir.path()).unwrap();
 
         // Create initial commit so we have a valid repo state
-        let sig = repo.signature().unwrap();
+        let sig = git2::Signature::now("Test User", "test@test.com").unwrap();
         let tree_id = repo.index().unwrap().write_tree().unwrap();
         let tree = repo.find_tree(tree_id).unwrap();
         repo.commit(Some("HEAD"), &sig, &sig, "initial", &tree, &[]).unwrap();
@@ -107,6 +466,126 @@ mod tests {
         assert!(!lock_path.exists());
     }
 
+    #[test]
+    fn lock_records_owner() {
+        let dir = setup_repo();
+        let lock_path = dir.path().join(".git/index.lock");
+
+        {
+            let _lock = IndexLock::acquire_at(dir.path()).unwrap();
+            let owner = LockOwner::read(&lock_path).expect("owner should be recorded");
+            assert_eq!(owner.pid, std::process::id());
+
+            let err = IndexLock::acquire_at(dir.path()).err().unwrap();
+            assert!(err.to_string().contains(&format!("pid {}", owner.pid)), "{err}");
+        }
+
+        assert!(!sidecar_path(&lock_path).exists());
+    }
+
+    #[test]
+    fn tickets_queue_in_order() {
+        let dir = setup_repo();
+        let git_dir = dir.path().join(".git");
+
+        let first = Ticket::take(&git_dir).unwrap();
+        // Being written by another process: not yet in the queue
+        let writing = first.dir.join(".000000000000000000000000-1");
+        std::fs::write(&writing, "").unwrap();
+        let second = Ticket::take(&git_dir).unwrap();
+
+        assert_eq!(first.position().unwrap(), 0);
+        assert_eq!(second.position().unwrap(), 1);
+        assert!(writing.exists());
+        drop(first);
+        assert_eq!(second.position().unwrap(), 0);
+    }
+
+    #[test]
+    fn replacing_index_keeps_lock_held() {
+        let dir = setup_repo();
+        let repo = Repository::open(dir.path()).unwrap();
+        let git_dir = dir.path().join(".git");
+        let lock_path = git_dir.join("index.lock");
+        let new_index = git_dir.join(format!("index.stage.{}", std::process::id()));
+        let blob = repo.blob(b"new\n").unwrap();
+        let mut index = git2::Index::open(&new_index).unwrap();
+        index
+            .add(&crate::index::unstatted_entry("new.txt", blob, 0o100_644))
+            .unwrap();
+        index.write().unwrap();
+
+        let _lock = IndexLock::acquire_at(dir.path()).unwrap();
+        replace_index(&repo, &new_index).unwrap();
+
+        let replaced = repo.index().unwrap();
+        assert!(replaced.get_path(Path::new("new.txt"), 0).is_some());
+        assert!(!new_index.exists());
+        let lock_inode = std::fs::metadata(&lock_path).unwrap().ino();
+        assert_ne!(lock_inode, std::fs::metadata(git_dir.join("index")).unwrap().ino());
+        assert_eq!(LockOwner::read(&lock_path).unwrap().lock_inode, lock_inode);
+        assert!(IndexLock::acquire_at(dir.path()).is_err(), "lock still held");
+    }
+
+    #[test]
+    fn index_backup_is_restored_through_the_lock() {
+        let dir = setup_repo();
+        let repo = Repository::open(dir.path()).unwrap();
+        let git_dir = dir.path().join(".git");
+        let lock_path = git_dir.join("index.lock");
+
+        let _lock = IndexLock::acquire_at(dir.path()).unwrap();
+        let backup = crate::exec::IndexBackup::take(&repo).unwrap();
+        let blob = repo.blob(b"new\n").unwrap();
+        let mut index = repo.index().unwrap();
+        index
+            .add(&crate::index::unstatted_entry("new.txt", blob, 0o100_644))
+            .unwrap();
+        // As a failing run would leave it, behind the lock's back
+        let changed = git_dir.join("index.changed");
+        crate::index::write_index_copy(&index, &changed).unwrap();
+        std::fs::rename(&changed, git_dir.join("index")).unwrap();
+        let old_lock_inode = std::fs::metadata(&lock_path).unwrap().ino();
+        drop(backup);
+
+        let restored = git2::Index::open(&git_dir.join("index")).unwrap();
+        assert!(restored.get_path(Path::new("new.txt"), 0).is_none());
+        // Written into the lock and renamed into place
+        let index_inode = std::fs::metadata(git_dir.join("index")).unwrap().ino();
+        assert_eq!(index_inode, old_lock_inode);
+        let lock_inode = std::fs::metadata(&lock_path).unwrap().ino();
+        assert_eq!(LockOwner::read(&lock_path).unwrap().lock_inode, lock_inode);
+        assert!(IndexLock::acquire_at(dir.path()).is_err(), "lock still held");
+        let leftovers = std::fs::read_dir(&git_dir)
+            .unwrap()
+            .filter_map(|entry| entry.unwrap().file_name().into_string().ok())
+            .filter(|name| name.starts_with("index.backup."))
+            .count();
+        assert_eq!(leftovers, 0);
+    }
+
+    #[test]
+    fn fsync_follows_core_fsync() {
+        let dir = setup_repo();
+        let repo = Repository::open(dir.path()).unwrap();
+        let mut config = repo.config().unwrap();
+        assert!(fsync_index(&repo));
+        config.set_str("core.fsync", "none").unwrap();
+        assert!(!fsync_index(&repo));
+        config.set_str("core.fsync", "none,index").unwrap();
+        assert!(fsync_index(&repo));
+        config.set_str("core.fsync", "committed,-index").unwrap();
+        assert!(!fsync_index(&repo));
+        config.set_str("core.fsync", "none,added").unwrap();
+        assert!(fsync_index(&repo));
+        config.set_str("core.fsync", "index,-added").unwrap();
+        assert!(!fsync_index(&repo));
+        config.set_str("core.fsync", "none,derived-metadata").unwrap();
+        assert!(!fsync_index(&repo));
+        config.set_str("core.fsync", "-derived-metadata").unwrap();
+        assert!(fsync_index(&repo));
+    }
+
     #[test]
     fn double_lock_fails() {
         let dir = setup_repo();
@@ -116,4 +595,81 @@ mod tests {
 
         assert!(result.is_err());
     }
+
+    #[test]
+    fn wait_times_out() {
+        let dir = setup_repo();
+        let repo = Repository::open(dir.path()).unwrap();
+
+        let _lock1 = IndexLock::acquire_for_repo(&repo).unwrap();
+        let policy = WaitPolicy {
+            patience: Patience::Until(Duration::from_millis(100)),
+            queue: true,
+        };
+        let err = IndexLock::acquire_for_repo_with(&repo, &policy)
+            .err()
+            .expect("should time out");
+
+        assert!(
+            matches!(Error::find(&err), Some(Error::LockTimeout { .. })),
+            "{err:?}"
+        );
+        // Ticket is released with the failed attempt
+        let queue = dir.path().join(".git/commit-staged/queue");
+        assert_eq!(std::fs::read_dir(queue).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn wait_acquires_after_release() {
+        let dir = setup_repo();
+        let repo = Repository::open(dir.path()).unwrap();
+
+        let lock1 = IndexLock::acquire_for_repo(&repo).unwrap();
+        let release = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(100));
+            drop(lock1);
+        });
+
+        let policy = WaitPolicy {
+            patience: Patience::Until(Duration::from_secs(10)),
+            queue: false,
+        };
+        let lock2 = IndexLock::acquire_for_repo_with(&repo, &policy);
+        release.join().unwrap();
+
+        assert!(lock2.is_ok());
+    }
+
+    #[test]
+    fn queue_skips_dead_tickets() {
+        let dir = setup_repo();
+        let git_dir = dir.path().join(".git");
+
+        let first = Ticket::take(&git_dir).unwrap();
+        let second = Ticket::take(&git_dir).unwrap();
+        assert_eq!(first.position().unwrap(), 0);
+        assert_eq!(second.position().unwrap(), 1);
+
+        // A ticket whose owner is gone doesn't hold up the queue
+        let queue = git_dir.join("commit-staged/queue");
+        std::fs::write(queue.join("0-0"), "0\n").unwrap();
+        std::fs::write(queue.join("0-1"), format!("{}\n", u32::MAX)).unwrap();
+        assert_eq!(first.position().unwrap(), 0);
+        assert!(!queue.join("0-1").exists());
+
+        drop(first);
+        assert_eq!(second.position().unwrap(), 0);
+    }
+
+    #[test]
+    fn wait_policy_overrides() {
+        let policy = WaitPolicy::default();
+        assert_eq!(policy.with_overrides(None, false).patience, Patience::Immediate);
+        assert_eq!(policy.with_overrides(Some(None), false).patience, Patience::Forever);
+        assert_eq!(
+            policy.with_overrides(Some(Some(3)), false).patience,
+            Patience::Until(Duration::from_secs(3))
+        );
+        assert!(policy.with_overrides(None, true).queue);
+    }
 }
@@ -0,0 +1,239 @@
+//! Ownership metadata for locks we take.
+//!
+//! Next to each `index.lock` we create, an `index.lock.owner` sidecar records
+//! who holds it. When a holder is killed and the lock is left behind, the
+//! sidecar lets us prove the lock is ours and its owner is gone, and thus
+//! safe to remove.
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::cleanup;
+use crate::lock::pid_alive;
+
+/// Who holds a lock.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockOwner {
+    /// Owner process ID
+    pub pid: u32,
+    /// Host the owner runs on
+    pub hostname: String,
+    /// Kernel boot ID (Linux only)
+    pub boot_id: Option<String>,
+    /// Owner process start time, in clock ticks since boot (Linux only).
+    /// Distinguishes the owner from a later process reusing its PID.
+    pub process_start: Option<u64>,
+    /// When the lock was taken (seconds since the Unix epoch)
+    pub acquired_at: u64,
+    /// Owner's command line
+    pub command: Vec<String>,
+    /// Inode of the lock file, tying this record to one lock file
+    pub lock_inode: u64,
+}
+
+impl LockOwner {
+    /// Describe the current process as owner of `lock_file`.
+    ///
+    /// # Errors
+    /// Returns an error if the lock file's metadata cannot be read.
+    pub fn current(lock_file: &File) -> Result<Self> {
+        let pid = std::process::id();
+        Ok(Self {
+            pid,
+            hostname: hostname(),
+            boot_id: boot_id(),
+            process_start: process_start(pid),
+            acquired_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            command: std::env::args().collect(),
+            lock_inode: lock_file.metadata().context("failed to stat lock file")?.ino(),
+        })
+    }
+
+    /// Read the owner recorded for `lock_path`, if any.
+    #[must_use]
+    pub fn read(lock_path: &Path) -> Option<Self> {
+        let text = std::fs::read_to_string(sidecar_path(lock_path)).ok()?;
+        serde_json::from_str(&text).ok()
+    }
+
+    /// Record this owner next to `lock_path`.
+    ///
+    /// The record is written to a temp file and renamed into place, so it is
+    /// never seen half-written.
+    ///
+    /// # Errors
+    /// Returns an error if the sidecar cannot be written.
+    pub fn write(&self, lock_path: &Path) -> Result<()> {
+        let path = sidecar_path(lock_path);
+        let mut temp_path = path.as_os_str().to_owned();
+        temp_path.push(format!(".{}", std::process::id()));
+        let temp_path = PathBuf::from(temp_path);
+
+        cleanup::register(&temp_path);
+        std::fs::write(&temp_path, serde_json::to_string(self)?)
+            .with_context(|| format!("failed to write {}", temp_path.display()))?;
+        std::fs::rename(&temp_path, &path)
+            .with_context(|| format!("failed to write {}", path.display()))?;
+        cleanup::forget(&temp_path);
+        Ok(())
+    }
+
+    /// Why the owner is provably dead, or `None` if it may still be alive.
+    #[must_use]
+    pub fn death_reason(&self) -> Option<String> {
+        if self.hostname != hostname() {
+            return None;
+        }
+        if let (Some(recorded), Some(current)) = (&self.boot_id, boot_id())
+            && *recorded != current
+        {
+            return Some("host rebooted since the lock was taken".to_owned());
+        }
+        if !pid_alive(self.pid) {
+            return Some(format!("process {} no longer exists", self.pid));
+        }
+        if let (Some(recorded), Some(current)) = (self.process_start, process_start(self.pid))
+            && recorded != current
+        {
+            return Some(format!("process {} has been replaced by another process", self.pid));
+        }
+        None
+    }
+}
+
+impl std::fmt::Display for LockOwner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "pid {} on {}, acquired at {} (unix time), command: {}",
+            self.pid,
+            self.hostname,
+            self.acquired_at,
+            self.command.join(" ")
+        )
+    }
+}
+
+/// Remove `lock_path` if it is provably ours and its owner is dead.
+///
+/// Returns the dead owner.
+///
+/// # Errors
+/// Returns an error, leaving the lock in place, if the lock has no owner
+/// record, the record belongs to a different lock file, or the owner may
+/// still be alive.
+pub fn remove_stale_lock(lock_path: &Path) -> Result<(LockOwner, String)> {
+    let metadata = std::fs::metadata(lock_path)
+        .with_context(|| format!("no lock at {}", lock_path.display()))?;
+
+    let Some(owner) = LockOwner::read(lock_path) else {
+        bail!(
+            "{} has no owner record; it was not taken by git-commit-staged.\n\
+             Make sure no git process is running, then remove it by hand.",
+            lock_path.display()
+        );
+    };
+    if owner.lock_inode != metadata.ino() {
+        bail!(
+            "owner record for {} belongs to an earlier lock file; refusing to remove it",
+            lock_path.display()
+        );
+    }
+    let Some(reason) = owner.death_reason() else {
+        bail!(
+            "{} is held by {owner}\nThe owner may still be running; refusing to remove it.",
+            lock_path.display()
+        );
+    };
+
+    // Remove the lock first: a sidecar without a lock is harmless
+    std::fs::remove_file(lock_path)
+        .with_context(|| format!("failed to remove {}", lock_path.display()))?;
+    let _ = std::fs::remove_file(sidecar_path(lock_path));
+
+    Ok((owner, reason))
+}
+
+/// Path of the owner sidecar for `lock_path`: `<lock>.owner`.
+#[must_use]
+pub fn sidecar_path(lock_path: &Path) -> PathBuf {
+    let mut path = lock_path.as_os_str().to_owned();
+    path.push(".owner");
+    PathBuf::from(path)
+}
+
+fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    // SAFETY: buf is valid for buf.len() bytes; gethostname NUL-terminates on success.
+    let ret = unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) };
+    if ret != 0 {
+        return String::new();
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+fn boot_id() -> Option<String> {
+    std::fs::read_to_string("/proc/sys/kernel/random/boot_id")
+        .ok()
+        .map(|id| id.trim().to_owned())
+}
+
+fn process_start(pid: u32) -> Option<u64> {
+    let stat = std::fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    // Field 2 (comm) may contain spaces; fields after it are space-separated.
+    // starttime is field 22, i.e. the 20th field after comm's closing paren.
+    stat.rsplit_once(')')?
+        .1
+        .split_whitespace()
+        .nth(19)?
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn own_process_is_alive() {
+        let file = tempfile::tempfile().unwrap();
+        let owner = LockOwner::current(&file).unwrap();
+        assert_eq!(owner.death_reason(), None);
+    }
+
+    #[test]
+    fn dead_pid_is_detected() {
+        let file = tempfile::tempfile().unwrap();
+        let owner = LockOwner {
+            pid: u32::MAX,
+            ..LockOwner::current(&file).unwrap()
+        };
+        assert!(owner.death_reason().is_some());
+    }
+
+    #[test]
+    fn other_host_is_never_provably_dead() {
+        let file = tempfile::tempfile().unwrap();
+        let owner = LockOwner {
+            pid: u32::MAX,
+            hostname: "some-other-host".to_owned(),
+            ..LockOwner::current(&file).unwrap()
+        };
+        assert_eq!(owner.death_reason(), None);
+    }
+
+    #[test]
+    fn sidecar_path_appends_suffix() {
+        assert_eq!(
+            sidecar_path(Path::new("/repo/.git/index.lock")),
+            PathBuf::from("/repo/.git/index.lock.owner")
+        );
+    }
+}
@@ -0,0 +1,372 @@
+//! Machine-readable output for `--json` and `--porcelain=v1`.
+//!
+//! Both formats carry the same [`Report`]: the entries committed (or that
+//! would be, for a dry run) with old and new OIDs and modes, the commit made,
+//! warnings, and on failure an error object with a stable code.
+//!
+//! Porcelain v1 is one record per line (NUL-terminated with `-z`):
+//!
+//! ```text
+//! version 1
+//! dry-run 0
+//! entry M 100644 100644 <old-oid> <new-oid> <path>
+//! series-commit <oid> <tree>
+//! commit <oid>
+//! tree <oid>
+//! old-head <oid>
+//! ref refs/heads/main
+//! branch main
+//! amend 0
+//! warning <message>
+//! error <code> <message>
+//! ```
+//!
+//! Missing OIDs and modes (added or deleted paths, unborn HEAD) are all
+//! zeros, as in `git diff --raw`. `branch` is omitted when HEAD is detached.
+//! A series lists each commit it made, oldest first, in `series-commit`
+//! records; `commit` and the records after it describe the last one.
+//! Without `-z`, paths and messages containing special characters are
+//! quoted as git does, honouring `core.quotePath`. New record types may be
+//! added; consumers must ignore ones they don't know.
+
+use anyhow::Result;
+use git2::{Oid, Repository};
+use serde::Serialize;
+use std::fmt::Write as _;
+use std::path::Path;
+
+use crate::commit::CommitOutput;
+use crate::error::Error;
+use crate::status::ChangeStatus;
+use crate::{head_tree, StagedEntry};
+
+/// Porcelain format version.
+pub const PORCELAIN_VERSION: u32 = 1;
+
+/// How results are printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    /// Human-readable text
+    #[default]
+    Human,
+    /// A single JSON object
+    Json,
+    /// Porcelain v1 records, NUL-terminated if `nul`
+    Porcelain {
+        /// Terminate records with NUL instead of newline (`-z`)
+        nul: bool,
+    },
+}
+
+impl Format {
+    /// Choose the format from the `--json`, `--porcelain` and `-z` flags.
+    #[must_use]
+    pub const fn from_flags(json: bool, porcelain: bool, nul: bool) -> Self {
+        if json {
+            Self::Json
+        } else if porcelain {
+            Self::Porcelain { nul }
+        } else {
+            Self::Human
+        }
+    }
+}
+
+/// Result of a run, for machine-readable output.
+#[derive(Debug, Serialize)]
+pub struct Report {
+    /// Output format version
+    pub version: u32,
+    /// Whether this was a dry run
+    pub dry_run: bool,
+    /// Paths committed (or that would be)
+    pub entries: Vec<Entry>,
+    /// The commit made, if any (for a series, the last)
+    pub commit: Option<CommitInfo>,
+    /// Every commit of a series, oldest first
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub series: Vec<CommitInfo>,
+    /// Non-fatal problems
+    pub warnings: Vec<String>,
+    /// Why the run failed, if it did
+    pub error: Option<ErrorInfo>,
+}
+
+/// A committed path.
+#[derive(Debug, Serialize)]
+pub struct Entry {
+    /// Repo-relative path
+    pub path: String,
+    /// Kind of change
+    pub status: ChangeStatus,
+    /// Mode before, in octal (`None` if added)
+    pub old_mode: Option<String>,
+    /// Mode after, in octal (`None` if deleted)
+    pub new_mode: Option<String>,
+    /// Blob before (`None` if added)
+    pub old_oid: Option<String>,
+    /// Blob after (`None` if deleted)
+    pub new_oid: Option<String>,
+}
+
+/// The commit made.
+#[derive(Debug, Serialize)]
+pub struct CommitInfo {
+    /// Commit ID
+    pub oid: String,
+    /// Tree ID
+    pub tree: String,
+    /// HEAD before the commit (`None` on an unborn branch)
+    pub old_head: Option<String>,
+    /// Ref updated: full branch name, or `HEAD` when detached
+    pub reference: String,
+    /// Short branch name (`None` when detached)
+    pub branch: Option<String>,
+    /// Whether the commit replaced the old HEAD (`--amend`)
+    pub amend: bool,
+}
+
+/// A failure, with a code that scripts can match on.
+#[derive(Debug, Serialize)]
+pub struct ErrorInfo {
+    /// Stable error code (see [`error_code`])
+    pub code: &'static str,
+    /// Human-readable message, including causes
+    pub message: String,
+}
+
+impl Report {
+    /// An empty report.
+    #[must_use]
+    pub const fn new(dry_run: bool) -> Self {
+        Self {
+            version: PORCELAIN_VERSION,
+            dry_run,
+            entries: Vec::new(),
+            commit: None,
+            series: Vec::new(),
+            warnings: Vec::new(),
+            error: None,
+        }
+    }
+
+    /// A report of a failed run.
+    #[must_use]
+    pub fn failure(dry_run: bool, err: &anyhow::Error) -> Self {
+        Self {
+            error: Some(ErrorInfo {
+                code: error_code(err),
+                message: format!("{err:#}"),
+            }),
+            ..Self::new(dry_run)
+        }
+    }
+
+    /// Print in a machine-readable `format`. Does nothing for [`Format::Human`].
+    ///
+    /// # Panics
+    /// Never: serializing a report cannot fail.
+    pub fn print(&self, format: Format) {
+        match format {
+            Format::Human => {}
+            Format::Json => {
+                let json = serde_json::to_string_pretty(self).expect("report is serializable");
+                println!("{json}");
+            }
+            Format::Porcelain { nul } => print!("{}", self.porcelain(nul, quote_path())),
+        }
+    }
+
+    /// Render as porcelain v1.
+    #[must_use]
+    pub fn porcelain(&self, nul: bool, quote_path: bool) -> String {
+        let end = if nul { '\0' } else { '\n' };
+        let text = |s: &str| if nul { s.to_owned() } else { quote(s, quote_path) };
+        let zero = Oid::zero().to_string();
+        let or_zero = |oid: &Option<String>| oid.clone().unwrap_or_else(|| zero.clone());
+        let or_zero_mode =
+            |mode: &Option<String>| mode.clone().unwrap_or_else(|| "000000".to_owned());
+
+        let mut out = String::new();
+        let _ = write!(out, "version {}{end}", self.version);
+        let _ = write!(out, "dry-run {}{end}", u8::from(self.dry_run));
+        for entry in &self.entries {
+            let _ = write!(
+                out,
+                "entry {} {} {} {} {} {}{end}",
+                entry.status.letter(),
+                or_zero_mode(&entry.old_mode),
+                or_zero_mode(&entry.new_mode),
+                or_zero(&entry.old_oid),
+                or_zero(&entry.new_oid),
+                text(&entry.path)
+            );
+        }
+        for commit in &self.series {
+            let _ = write!(out, "series-commit {} {}{end}", commit.oid, commit.tree);
+        }
+        if let Some(commit) = &self.commit {
+            let _ = write!(out, "commit {}{end}", commit.oid);
+            let _ = write!(out, "tree {}{end}", commit.tree);
+            let _ = write!(out, "old-head {}{end}", or_zero(&commit.old_head));
+            let _ = write!(out, "ref {}{end}", text(&commit.reference));
+            if let Some(branch) = &commit.branch {
+                let _ = write!(out, "branch {}{end}", text(branch));
+            }
+            let _ = write!(out, "amend {}{end}", u8::from(commit.amend));
+        }
+        for warning in &self.warnings {
+            let _ = write!(out, "warning {}{end}", text(warning));
+        }
+        if let Some(error) = &self.error {
+            let _ = write!(out, "error {} {}{end}", error.code, text(&error.message));
+        }
+        out
+    }
+}
+
+impl From<&CommitOutput> for CommitInfo {
+    fn from(output: &CommitOutput) -> Self {
+        Self {
+            oid: output.commit.to_string(),
+            tree: output.tree.to_string(),
+            old_head: output.old_head.map(|oid| oid.to_string()),
+            reference: output.reference.clone(),
+            branch: output.branch.clone(),
+            amend: output.amend,
+        }
+    }
+}
+
+/// Describe `staged` entries relative to HEAD (the tree they replace).
+///
+/// # Errors
+/// Returns an error if HEAD cannot be read.
+pub fn entries(repo: &Repository, staged: &[StagedEntry]) -> Result<Vec<Entry>> {
+    let base = head_tree(repo)?;
+
+    Ok(staged
+        .iter()
+        .map(|(path, new)| {
+            let old = base
+                .get_path(Path::new(path))
+                .ok()
+                .map(|entry| (entry.id(), entry.filemode().cast_unsigned()));
+            let status = match (old, new) {
+                (None, _) => ChangeStatus::Added,
+                (_, None) => ChangeStatus::Deleted,
+                (Some((_, old_mode)), Some((_, new_mode)))
+                    if old_mode & 0o170_000 != new_mode & 0o170_000 =>
+                {
+                    ChangeStatus::Typechange
+                }
+                _ => ChangeStatus::Modified,
+            };
+            Entry {
+                path: path.clone(),
+                status,
+                old_mode: old.map(|(_, mode)| format!("{mode:06o}")),
+                new_mode: new.map(|(_, mode)| format!("{mode:06o}")),
+                old_oid: old.map(|(oid, _)| oid.to_string()),
+                new_oid: new.map(|(oid, _)| oid.to_string()),
+            }
+        })
+        .collect())
+}
+
+/// Stable code for an error: [`Error::code`] for typed failures (see
+/// [`crate::error`]), `failed` for anything else.
+#[must_use]
+pub fn error_code(err: &anyhow::Error) -> &'static str {
+    Error::find(err).map_or("failed", Error::code)
+}
+
+/// `core.quotePath` of the current repository: whether non-ASCII bytes are
+/// quoted (default true, also outside a repository).
+fn quote_path() -> bool {
+    Repository::open_from_env()
+        .ok()
+        .and_then(|repo| repo.config().ok()?.get_bool("core.quotePath").ok())
+        .unwrap_or(true)
+}
+
+/// Quote `s` as git does for paths with special characters: control
+/// characters, `"` and `\` are escaped, and so is each byte of a non-ASCII
+/// character if `quote_path` (`core.quotePath`).
+fn quote(s: &str, quote_path: bool) -> String {
+    let special = |c: char| c.is_ascii_control() || c == '"' || c == '\\';
+    if !s.chars().any(|c| special(c) || (quote_path && !c.is_ascii())) {
+        return s.to_owned();
+    }
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\u{7}' => quoted.push_str("\\a"),
+            '\u{8}' => quoted.push_str("\\b"),
+            '\t' => quoted.push_str("\\t"),
+            '\n' => quoted.push_str("\\n"),
+            '\u{b}' => quoted.push_str("\\v"),
+            '\u{c}' => quoted.push_str("\\f"),
+            '\r' => quoted.push_str("\\r"),
+            c if c.is_ascii_control() || (quote_path && !c.is_ascii()) => {
+                for byte in c.encode_utf8(&mut [0; 4]).bytes() {
+                    let _ = write!(quoted, "\\{byte:03o}");
+                }
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn porcelain_records() {
+        let mut report = Report::new(true);
+        report.entries.push(Entry {
+            path: "new\nline".to_owned(),
+            status: ChangeStatus::Added,
+            old_mode: None,
+            new_mode: Some("100644".to_owned()),
+            old_oid: None,
+            new_oid: Some("a".repeat(40)),
+        });
+        report.warnings.push("careful".to_owned());
+
+        let zero = "0".repeat(40);
+        let a = "a".repeat(40);
+        assert_eq!(
+            report.porcelain(false, true),
+            format!(
+                "version 1\ndry-run 1\n\
+                 entry A 000000 100644 {zero} {a} \"new\\nline\"\n\
+                 warning careful\n"
+            )
+        );
+        assert_eq!(
+            report.porcelain(true, true),
+            format!(
+                "version 1\0dry-run 1\0\
+                 entry A 000000 100644 {zero} {a} new\nline\0\
+                 warning careful\0"
+            )
+        );
+    }
+
+    #[test]
+    fn quotes_like_git() {
+        assert_eq!(quote("plain name.txt", true), "plain name.txt");
+        assert_eq!(quote("a\"b\\c", true), "\"a\\\"b\\\\c\"");
+        assert_eq!(quote("bell\u{7}\r\u{1b}\u{7f}", true), "\"bell\\a\\r\\033\\177\"");
+        // Per UTF-8 byte, unless core.quotePath is off
+        assert_eq!(quote("café", true), "\"caf\\303\\251\"");
+        assert_eq!(quote("café", false), "café");
+        assert_eq!(quote("café\n", false), "\"café\\n\"");
+    }
+}
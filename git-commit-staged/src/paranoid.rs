@@ -0,0 +1,183 @@
+//! Postcondition checks (`--paranoid`).
+//!
+//! A path-scoped commit must leave everything else alone: main index
+//! entries for paths not being committed, and the working tree. This
+//! fingerprints both before the operation and compares afterwards, failing
+//! with [`Error::UnexpectedMutation`] if anything changed.
+//!
+//! The working tree is fingerprinted by hashing every tracked file as a blob
+//! (no clean filters), which reads the whole checkout: this is opt-in.
+
+use anyhow::{Context, Result};
+use git2::{ObjectType, Oid, Repository};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::path::Path;
+
+use crate::error::{Area, Error, Mutation};
+use crate::StagedEntry;
+
+const GITLINK_MODE: u32 = 0o160_000;
+
+/// Fingerprints of the main index and working tree.
+#[derive(Debug)]
+pub struct Snapshot {
+    /// Main index entries (blob, mode) by (path, stage)
+    index: BTreeMap<(String, u16), (Oid, u32)>,
+    /// Working tree content of tracked paths (`None`: missing)
+    work_tree: BTreeMap<String, Option<Oid>>,
+}
+
+impl Snapshot {
+    /// Fingerprint the main index and the tracked files in the working tree.
+    ///
+    /// # Errors
+    /// Returns an error if the index or a tracked file cannot be read.
+    pub fn take(repo: &Repository) -> Result<Self> {
+        let index = read_index(repo)?;
+        let tracked: BTreeSet<&str> = index
+            .iter()
+            .filter(|(_, (_, mode))| *mode != GITLINK_MODE)
+            .map(|((path, _), _)| path.as_str())
+            .collect();
+        let work_tree = match repo.workdir() {
+            Some(workdir) => tracked
+                .into_iter()
+                .map(|path| Ok((path.to_owned(), hash_work_tree_file(&workdir.join(path))?)))
+                .collect::<Result<_>>()?,
+            None => BTreeMap::new(),
+        };
+        Ok(Self { index, work_tree })
+    }
+
+    /// Check nothing changed but the main index entries of `committed`.
+    ///
+    /// `commit` is the commit made, if any, for the error message.
+    ///
+    /// # Errors
+    /// Returns [`Error::UnexpectedMutation`] listing every change, or an
+    /// error if the index or a tracked file cannot be read.
+    pub fn check(
+        &self,
+        repo: &Repository,
+        committed: &[StagedEntry],
+        commit: Option<Oid>,
+    ) -> Result<()> {
+        let committed: HashSet<&str> = committed.iter().map(|(path, _)| path.as_str()).collect();
+        let index = read_index(repo)?;
+
+        let keys: BTreeSet<&(String, u16)> = self.index.keys().chain(index.keys()).collect();
+        let mut mutations: Vec<Mutation> = keys
+            .into_iter()
+            .filter(|(path, _)| !committed.contains(path.as_str()))
+            .filter_map(|key| {
+                let (old, new) = (self.index.get(key), index.get(key));
+                (old != new).then(|| Mutation {
+                    path: key.0.clone(),
+                    area: Area::Index,
+                    old: old.map(|&(oid, _)| oid),
+                    new: new.map(|&(oid, _)| oid),
+                })
+            })
+            .collect();
+
+        if let Some(workdir) = repo.workdir() {
+            for (path, &old) in &self.work_tree {
+                let new = hash_work_tree_file(&workdir.join(path))?;
+                if old != new {
+                    mutations.push(Mutation {
+                        path: path.clone(),
+                        area: Area::WorkTree,
+                        old,
+                        new,
+                    });
+                }
+            }
+        }
+
+        if mutations.is_empty() {
+            return Ok(());
+        }
+        mutations.sort_by(|a, b| (&a.path, a.area).cmp(&(&b.path, b.area)));
+        Err(Error::UnexpectedMutation { commit, mutations }.into())
+    }
+}
+
+/// Main index entries by (path, stage), re-read from disk.
+fn read_index(repo: &Repository) -> Result<BTreeMap<(String, u16), (Oid, u32)>> {
+    let mut index = repo.index().context("failed to open index")?;
+    index.read(true).context("failed to read index")?;
+    Ok(index
+        .iter()
+        .map(|entry| {
+            let path = String::from_utf8_lossy(&entry.path).into_owned();
+            let stage = (entry.flags >> 12) & 0b11;
+            ((path, stage), (entry.id, entry.mode))
+        })
+        .collect())
+}
+
+/// Blob ID of a working tree file (a symlink's target for symlinks), or
+/// `None` if it is missing or not a file.
+fn hash_work_tree_file(path: &Path) -> Result<Option<Oid>> {
+    let Ok(metadata) = std::fs::symlink_metadata(path) else {
+        return Ok(None);
+    };
+    let oid = if metadata.is_symlink() {
+        let target = std::fs::read_link(path)
+            .with_context(|| format!("failed to read symlink {}", path.display()))?;
+        Oid::hash_object(ObjectType::Blob, target.as_os_str().as_encoded_bytes())
+    } else if metadata.is_file() {
+        Oid::hash_file(ObjectType::Blob, path)
+    } else {
+        return Ok(None);
+    };
+    oid.map(Some)
+        .with_context(|| format!("failed to hash {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn repo_with(files: &[(&str, &str)]) -> (TempDir, Repository) {
+        let dir = TempDir::new().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let mut index = repo.index().unwrap();
+        for (path, content) in files {
+            fs::write(dir.path().join(path), content).unwrap();
+            index.add_path(Path::new(path)).unwrap();
+        }
+        index.write().unwrap();
+        (dir, repo)
+    }
+
+    #[test]
+    fn reports_changes_outside_committed_paths() {
+        let (dir, repo) = repo_with(&[("a", "a\n"), ("b", "b\n"), ("c", "c\n")]);
+        let snapshot = Snapshot::take(&repo).unwrap();
+
+        fs::write(dir.path().join("b"), "changed\n").unwrap();
+        let mut index = repo.index().unwrap();
+        fs::write(dir.path().join("a"), "staged\n").unwrap();
+        index.add_path(Path::new("a")).unwrap();
+        fs::write(dir.path().join("a"), "a\n").unwrap();
+        index.remove_path(Path::new("c")).unwrap();
+        index.write().unwrap();
+
+        let err = snapshot
+            .check(&repo, &[("a".to_owned(), None)], None)
+            .unwrap_err();
+        let Some(Error::UnexpectedMutation { mutations, .. }) = Error::find(&err) else {
+            panic!("expected mutation: {err:#}");
+        };
+        let found: Vec<_> = mutations.iter().map(|m| (m.path.as_str(), m.area)).collect();
+        assert_eq!(found, [("b", Area::WorkTree), ("c", Area::Index)]);
+        assert!(mutations[1].new.is_none());
+
+        let all = [("a".to_owned(), None), ("c".to_owned(), None)];
+        fs::write(dir.path().join("b"), "b\n").unwrap();
+        snapshot.check(&repo, &all, None).unwrap();
+    }
+}
@@ -0,0 +1,221 @@
+//! Commit plans: a dry run's exact result, committed later as reviewed.
+//!
+//! `git commit-staged -n --plan-out FILE` records the base HEAD and every
+//! entry to commit (path, blob, mode, or deletion). `--plan FILE` commits
+//! exactly those blobs on top of HEAD, whatever the index holds by then.
+//!
+//! The plan file is JSON:
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "base": "<commit oid>",
+//!   "entries": [
+//!     { "path": "src/lib.rs", "oid": "<blob oid>", "mode": "100644" },
+//!     { "path": "old.rs", "oid": null, "mode": null }
+//!   ]
+//! }
+//! ```
+//!
+//! A plan is refused if HEAD moved off its base, unless rebasing was asked
+//! for; even then, paths that also changed between the base and HEAD are
+//! refused rather than silently overwritten.
+
+use anyhow::{bail, Context, Result};
+use git2::{Oid, Repository};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::error::Error;
+use crate::expect::parse_oid;
+use crate::index::tree_entry;
+use crate::{head_commit, StagedEntry};
+
+/// Plan file format version.
+pub const PLAN_VERSION: u32 = 1;
+
+/// Entries to commit on top of a base commit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Plan {
+    /// HEAD when the plan was made
+    pub base: Oid,
+    /// What to commit
+    pub entries: Vec<StagedEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PlanFile {
+    version: u32,
+    base: String,
+    entries: Vec<PlanFileEntry>,
+}
+
+/// A staged entry as written to plan and journal files.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct PlanFileEntry {
+    path: String,
+    oid: Option<String>,
+    mode: Option<String>,
+}
+
+impl PlanFileEntry {
+    pub(crate) fn new((path, entry): &StagedEntry) -> Self {
+        Self {
+            path: path.clone(),
+            oid: entry.map(|(oid, _)| oid.to_string()),
+            mode: entry.map(|(_, mode)| format!("{mode:06o}")),
+        }
+    }
+
+    /// Parse back into a staged entry.
+    pub(crate) fn parse(self) -> Result<StagedEntry> {
+        let data = match (self.oid, self.mode) {
+            (None, None) => None,
+            (Some(oid), Some(mode)) => {
+                let oid = parse_oid(&oid)?;
+                let mode = u32::from_str_radix(&mode, 8)
+                    .with_context(|| format!("invalid mode {mode:?} for {}", self.path))?;
+                Some((oid, mode))
+            }
+            _ => bail!("{}: oid and mode must both be set or both null", self.path),
+        };
+        Ok((self.path, data))
+    }
+}
+
+impl Plan {
+    /// Plan to commit `entries` on top of the current HEAD.
+    ///
+    /// # Errors
+    /// Returns an error if HEAD cannot be read.
+    pub fn new(repo: &Repository, entries: &[StagedEntry]) -> Result<Self> {
+        Ok(Self {
+            base: head_commit(repo)?.id(),
+            entries: entries.to_vec(),
+        })
+    }
+
+    /// Write the plan file.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be written.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let file = PlanFile {
+            version: PLAN_VERSION,
+            base: self.base.to_string(),
+            entries: self.entries.iter().map(PlanFileEntry::new).collect(),
+        };
+        let json = serde_json::to_string_pretty(&file).context("failed to serialize plan")?;
+        std::fs::write(path, json + "\n")
+            .with_context(|| format!("failed to write plan {}", path.display()))
+    }
+
+    /// Read a plan file, checking that its blobs exist in `repo`.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be read or parsed, has an unknown
+    /// version, or names a blob `repo` doesn't have.
+    pub fn read(repo: &Repository, path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read plan {}", path.display()))?;
+        let file: PlanFile = serde_json::from_str(&text)
+            .with_context(|| format!("failed to parse plan {}", path.display()))?;
+        if file.version != PLAN_VERSION {
+            bail!("unsupported plan version {} in {}", file.version, path.display());
+        }
+
+        let entries = file
+            .entries
+            .into_iter()
+            .map(|entry| {
+                let (path, data) = entry.parse()?;
+                if let Some((oid, _)) = data {
+                    repo.find_blob(oid)
+                        .with_context(|| format!("blob {oid} for {path} not found"))?;
+                }
+                Ok((path, data))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            base: parse_oid(&file.base)?,
+            entries,
+        })
+    }
+
+    /// Check the plan still applies to HEAD.
+    ///
+    /// With `rebase`, a plan whose base is no longer HEAD is accepted as
+    /// long as none of its paths changed between the base and HEAD (or
+    /// changed to exactly what the plan commits).
+    ///
+    /// # Errors
+    /// Returns [`Error::HeadMoved`] if HEAD is not the base and `rebase` is
+    /// false, [`Error::Conflict`] if planned paths changed since the base,
+    /// or an error if either commit cannot be read.
+    pub fn check_base(&self, repo: &Repository, rebase: bool) -> Result<()> {
+        let head = head_commit(repo)?;
+        if head.id() == self.base {
+            return Ok(());
+        }
+        if !rebase {
+            return Err(Error::HeadMoved {
+                expected: self.base,
+                actual: head.id(),
+            }
+            .into());
+        }
+
+        let base_tree = repo
+            .find_commit(self.base)
+            .and_then(|commit| commit.tree())
+            .with_context(|| format!("failed to read plan base {}", self.base))?;
+        let head_tree = head.tree().context("failed to get HEAD tree")?;
+
+        let paths: Vec<String> = self
+            .entries
+            .iter()
+            .filter(|(path, planned)| {
+                let now = tree_entry(&head_tree, path);
+                now != tree_entry(&base_tree, path) && now != *planned
+            })
+            .map(|(path, _)| path.clone())
+            .collect();
+        if paths.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Conflict { paths }.into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn round_trips_through_file() {
+        let dir = TempDir::new().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let blob = repo.blob(b"content\n").unwrap();
+        let plan = Plan {
+            base: Oid::from_str(&"1".repeat(40)).unwrap(),
+            entries: vec![
+                ("exec.sh".to_owned(), Some((blob, 0o100_755))),
+                ("gone.txt".to_owned(), None),
+            ],
+        };
+
+        let path = dir.path().join("plan.json");
+        plan.write(&path).unwrap();
+        assert_eq!(Plan::read(&repo, &path).unwrap(), plan);
+
+        let missing = Plan {
+            entries: vec![("x".to_owned(), Some((plan.base, 0o100_644)))],
+            ..plan
+        };
+        missing.write(&path).unwrap();
+        assert!(Plan::read(&repo, &path).is_err());
+    }
+}
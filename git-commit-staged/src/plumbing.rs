@@ -0,0 +1,671 @@
+//! Commit without spawning `git commit` (`--plumbing`).
+//!
+//! The tree is written from the temp index and the commit created with
+//! git2; no git binary or editor is involved. The branch (or detached HEAD)
+//! is updated with a reflog entry, compare-and-swap against the HEAD read
+//! beforehand. The `pre-commit`, `commit-msg` and `post-commit` hooks are
+//! run as `git commit` would run them.
+//!
+//! Only a subset of `git commit`'s options is understood; anything else
+//! after `--` is an error rather than silently ignored:
+//!
+//! - `-m`/`--message` (repeatable: paragraphs), `-F`/`--file` (`-`: stdin).
+//!   Without either, the message is read from stdin, or reused by `--amend`.
+//! - `--amend`: replace HEAD, keeping its parents and author
+//! - `-n`/`--no-verify`: skip `pre-commit` and `commit-msg`
+//! - `-S[KEY]`/`--gpg-sign[=KEY]`, `--no-gpg-sign`: sign the commit
+//!   (default: `commit.gpgSign`) with the program for `gpg.format`
+//! - `-q`/`--quiet`: accepted; nothing is printed anyway
+//!
+//! Author and committer come from `GIT_{AUTHOR,COMMITTER}_{NAME,EMAIL,DATE}`,
+//! then `author.*`/`committer.*`, then `user.*`. Dates must be in git's
+//! internal format, `[@]<seconds> <+zone>`.
+
+use anyhow::{bail, Context, Result};
+use git2::{Commit, Config, Index, Oid, Repository, Signature, Time};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+use crate::cleanup;
+use crate::commit::{commit_output, hooks_dir, is_executable, read_head, CommitOutput};
+use crate::error::Error;
+use crate::session::Session;
+
+/// Whether and how to sign the commit.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Signing {
+    /// As `commit.gpgSign` says
+    #[default]
+    Config,
+    /// Sign, with this key or `user.signingkey`
+    Key(Option<String>),
+    /// Don't sign (`--no-gpg-sign`)
+    Off,
+}
+
+/// The `git commit` options understood in plumbing mode.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CommitOptions {
+    /// `-m` paragraphs
+    pub messages: Vec<String>,
+    /// `-F` file (`-`: stdin)
+    pub message_file: Option<PathBuf>,
+    /// `--amend`
+    pub amend: bool,
+    /// `--no-verify`
+    pub no_verify: bool,
+    /// `-S` / `--no-gpg-sign`
+    pub signing: Signing,
+    /// `--trailer` lines (`Key: value`)
+    pub trailers: Vec<String>,
+}
+
+impl CommitOptions {
+    /// Parse passthrough args.
+    ///
+    /// # Errors
+    /// Returns an error for options plumbing mode doesn't support, or a
+    /// missing option value.
+    pub fn parse(args: &[String]) -> Result<Self> {
+        let mut options = Self::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| {
+                args.next()
+                    .cloned()
+                    .with_context(|| format!("{name} needs a value"))
+            };
+            match arg.as_str() {
+                "-m" | "--message" => options.messages.push(value(arg)?),
+                "-F" | "--file" => options.message_file = Some(value(arg)?.into()),
+                "--trailer" => options.trailers.push(value(arg)?),
+                "--amend" => options.amend = true,
+                "-n" | "--no-verify" => options.no_verify = true,
+                "-S" | "--gpg-sign" => options.signing = Signing::Key(None),
+                "--no-gpg-sign" => options.signing = Signing::Off,
+                "-q" | "--quiet" => {}
+                _ => {
+                    if let Some(message) = arg
+                        .strip_prefix("--message=")
+                        .or_else(|| arg.strip_prefix("-m"))
+                    {
+                        options.messages.push(message.to_owned());
+                    } else if let Some(file) =
+                        arg.strip_prefix("--file=").or_else(|| arg.strip_prefix("-F"))
+                    {
+                        options.message_file = Some(file.into());
+                    } else if let Some(trailer) = arg.strip_prefix("--trailer=") {
+                        options.trailers.push(trailer.to_owned());
+                    } else if let Some(key) =
+                        arg.strip_prefix("--gpg-sign=").or_else(|| arg.strip_prefix("-S"))
+                    {
+                        options.signing = Signing::Key(Some(key.to_owned()));
+                    } else {
+                        bail!("{arg} is not supported with --plumbing");
+                    }
+                }
+            }
+        }
+        if !options.messages.is_empty() && options.message_file.is_some() {
+            bail!("-m and -F cannot be combined");
+        }
+        Ok(options)
+    }
+}
+
+/// Commit the temp index with git2 while caller holds index.lock.
+///
+/// The counterpart of [`crate::commit::do_commit`] for `--plumbing`; the
+/// temp index is removed regardless of outcome.
+///
+/// **Caller must hold index.lock** for the entire operation.
+///
+/// # Errors
+/// - Unsupported passthrough args, or no message
+/// - Repository, HEAD, identity or config cannot be read
+/// - A hook rejects the commit: [`Error::HookRejected`]
+/// - Signing fails
+/// - HEAD moved meanwhile: [`Error::HeadMoved`]
+pub fn commit(temp_index_path: &Path, passthrough_args: &[String]) -> Result<CommitOutput> {
+    commit_checked(temp_index_path, passthrough_args, |_| Ok(()))
+}
+
+/// [`commit`], with `check` run on the tree to commit, once the hooks have
+/// run, before the branch moves.
+///
+/// # Errors
+/// As [`commit`], or the error `check` returns.
+pub fn commit_checked(
+    temp_index_path: &Path,
+    passthrough_args: &[String],
+    check: impl FnOnce(Oid) -> Result<()>,
+) -> Result<CommitOutput> {
+    let result = commit_index(temp_index_path, passthrough_args, check);
+    let _ = cleanup::remove(temp_index_path);
+    result
+}
+
+fn commit_index(
+    temp_index_path: &Path,
+    passthrough_args: &[String],
+    check: impl FnOnce(Oid) -> Result<()>,
+) -> Result<CommitOutput> {
+    let options = CommitOptions::parse(passthrough_args)?;
+    let repo = Repository::open_from_env().context("failed to open repository")?;
+    let (reference, old_head) = read_head(&repo)?;
+    let old_commit = old_head
+        .map(|oid| repo.find_commit(oid))
+        .transpose()
+        .context("failed to read HEAD commit")?;
+    if options.amend && old_commit.is_none() {
+        bail!("nothing to amend: HEAD has no commits yet");
+    }
+
+    let oid = create_commit(&repo, temp_index_path, &options, old_commit.as_ref())?;
+    let commit = repo.find_commit(oid).context("failed to read new commit")?;
+    check(commit.tree_id())?;
+
+    let kind = if options.amend {
+        " (amend)"
+    } else if commit.parent_count() == 0 {
+        " (initial)"
+    } else {
+        ""
+    };
+    let subject = commit.message().unwrap_or_default().lines().next().unwrap_or_default();
+    update_ref(&repo, &reference, oid, old_head, &format!("commit{kind}: {subject}"))?;
+    run_post_commit(&repo, temp_index_path);
+
+    commit_output(&repo, reference, old_head)
+}
+
+/// Create a commit of the temp index on top of `old_commit` (replacing it
+/// with `--amend`), running `pre-commit` and `commit-msg`. No ref is updated.
+///
+/// # Errors
+/// Returns [`Error::HookRejected`] if a hook rejects the commit, or an error
+/// if there is no message, or the identity, config or tree cannot be read,
+/// or signing or writing the commit fails.
+pub(crate) fn create_commit(
+    repo: &Repository,
+    temp_index_path: &Path,
+    options: &CommitOptions,
+    old_commit: Option<&Commit>,
+) -> Result<Oid> {
+    let config = repo.config().context("failed to read git config")?;
+    let session = Session::from_env(repo)?;
+    let hooks = Hooks::new(repo, temp_index_path)?;
+
+    if !options.no_verify {
+        hooks.run("pre-commit", &[])?;
+    }
+
+    let mut message = clean_message(&read_message(options, old_commit)?)?;
+    if let Some(session) = &session {
+        message = add_trailers(&message, &session.all_trailers())?;
+    }
+    if !options.trailers.is_empty() {
+        message = add_trailers(&message, &options.trailers)?;
+    }
+    if !options.no_verify {
+        let path = repo.path().join("COMMIT_EDITMSG");
+        std::fs::write(&path, &message)
+            .with_context(|| format!("failed to write {}", path.display()))?;
+        hooks.run("commit-msg", &[&path])?;
+        let edited = std::fs::read_to_string(&path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        message = clean_message(&edited)?;
+    }
+
+    let tree = Index::open(temp_index_path)
+        .and_then(|mut index| index.write_tree_to(repo))
+        .and_then(|oid| repo.find_tree(oid))
+        .context("failed to write tree from temp index")?;
+
+    let committer = identity(&config, "committer", None)?;
+    let author = match old_commit {
+        Some(old) if options.amend => old.author().to_owned(),
+        _ => identity(&config, "author", session.as_ref())?,
+    };
+    let parents: Vec<Commit> = match old_commit {
+        Some(old) if options.amend => old.parents().collect(),
+        old => old.into_iter().cloned().collect(),
+    };
+    let parents: Vec<&Commit> = parents.iter().collect();
+
+    match signing_key(&config, &options.signing, &committer)? {
+        Some(key) => {
+            let buffer = repo
+                .commit_create_buffer(&author, &committer, &message, &tree, &parents)
+                .context("failed to build commit")?;
+            let buffer = buffer.as_str().context("commit is not valid UTF-8")?;
+            let signature = sign(repo, &config, &key, buffer)?;
+            repo.commit_signed(buffer, &signature, None)
+                .context("failed to write signed commit")
+        }
+        None => repo
+            .commit(None, &author, &committer, &message, &tree, &parents)
+            .context("failed to write commit"),
+    }
+}
+
+/// Run `post-commit`. Like git, a failing hook doesn't undo the commit.
+pub(crate) fn run_post_commit(repo: &Repository, temp_index_path: &Path) {
+    let hooks = Hooks::new(repo, temp_index_path);
+    if let Err(err) = hooks.and_then(|hooks| hooks.run("post-commit", &[])) {
+        eprintln!("warning: {err:#}");
+    }
+}
+
+/// Hooks run against the temp index, from the top of the working tree.
+struct Hooks {
+    dir: PathBuf,
+    workdir: PathBuf,
+    index: PathBuf,
+}
+
+impl Hooks {
+    fn new(repo: &Repository, index: &Path) -> Result<Self> {
+        Ok(Self {
+            dir: hooks_dir(repo)?,
+            workdir: repo.workdir().unwrap_or_else(|| repo.path()).to_path_buf(),
+            index: index.to_path_buf(),
+        })
+    }
+
+    /// Run hook `name` if it exists. Its output goes to stderr, as with
+    /// `git commit`; on failure it is returned in [`Error::HookRejected`].
+    fn run(&self, name: &str, args: &[&Path]) -> Result<()> {
+        let path = self.dir.join(name);
+        if !is_executable(&path) {
+            return Ok(());
+        }
+
+        let child = Command::new(&path)
+            .args(args)
+            .current_dir(&self.workdir)
+            .env("GIT_INDEX_FILE", &self.index)
+            .env("GIT_EDITOR", ":")
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("failed to run {name} hook"))?;
+        let tracked = cleanup::track_child(child.id());
+        let output = child.wait_with_output();
+        drop(tracked);
+        let output = output.with_context(|| format!("failed to wait for {name} hook"))?;
+
+        let text = hook_output(&output);
+        if output.status.success() {
+            eprint!("{text}");
+            Ok(())
+        } else {
+            Err(Error::HookRejected {
+                hooks: vec![name.to_owned()],
+                stderr: text,
+            }
+            .into())
+        }
+    }
+}
+
+fn hook_output(output: &Output) -> String {
+    let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+    text.push_str(&String::from_utf8_lossy(&output.stderr));
+    text
+}
+
+/// The message from `-m`, `-F`, the amended commit, or stdin.
+fn read_message(options: &CommitOptions, old: Option<&Commit>) -> Result<String> {
+    if !options.messages.is_empty() {
+        return Ok(options.messages.join("\n\n"));
+    }
+    match (&options.message_file, old) {
+        (Some(file), _) if file != Path::new("-") => std::fs::read_to_string(file)
+            .with_context(|| format!("failed to read message from {}", file.display())),
+        (None, Some(old)) if options.amend => old
+            .message()
+            .map(str::to_owned)
+            .context("amended commit's message is not valid UTF-8"),
+        _ => {
+            let mut message = String::new();
+            std::io::stdin()
+                .read_to_string(&mut message)
+                .context("failed to read message from stdin")?;
+            Ok(message)
+        }
+    }
+}
+
+/// Strip surplus whitespace, as `git commit --cleanup=whitespace` does.
+///
+/// # Errors
+/// Returns [`Error::CommitFailed`] if nothing is left.
+fn clean_message(message: &str) -> Result<String> {
+    let message = git2::message_prettify(message, None).context("failed to clean up message")?;
+    if message.is_empty() {
+        return Err(Error::CommitFailed {
+            stderr: "Aborting commit due to empty commit message.".to_owned(),
+        }
+        .into());
+    }
+    Ok(message)
+}
+
+/// Append `trailers` not already in the message's trailer block.
+pub(crate) fn add_trailers(message: &str, trailers: &[String]) -> Result<String> {
+    let existing = git2::message_trailers_strs(message).context("failed to parse trailers")?;
+    let existing: Vec<String> = existing
+        .iter()
+        .map(|(key, value)| format!("{key}: {value}"))
+        .collect();
+    let new: Vec<&String> = trailers.iter().filter(|t| !existing.contains(t)).collect();
+    if new.is_empty() {
+        return Ok(message.to_owned());
+    }
+
+    let mut message = message.trim_end().to_owned();
+    // Join an existing trailer block; otherwise start one
+    message.push_str(if existing.is_empty() { "\n\n" } else { "\n" });
+    for trailer in new {
+        message.push_str(trailer);
+        message.push('\n');
+    }
+    Ok(message)
+}
+
+/// Author or committer identity: session, environment, then config.
+///
+/// The session wins over `GIT_AUTHOR_*`, as it does for `git commit`, where
+/// it is passed in those variables.
+pub(crate) fn identity(
+    config: &Config,
+    role: &str,
+    session: Option<&Session>,
+) -> Result<Signature<'static>> {
+    let upper = role.to_uppercase();
+    let lookup = |field: &str, from_session: Option<&String>| {
+        from_session
+            .cloned()
+            .or_else(|| std::env::var(format!("GIT_{upper}_{}", field.to_uppercase())).ok())
+            .or_else(|| config.get_string(&format!("{role}.{field}")).ok())
+            .or_else(|| config.get_string(&format!("user.{field}")).ok())
+            .with_context(|| format!("no {role} {field}: set user.{field}"))
+    };
+    let name = lookup("name", session.and_then(|s| s.author_name.as_ref()))?;
+    let email = lookup("email", session.and_then(|s| s.author_email.as_ref()))?;
+
+    let signature = match std::env::var(format!("GIT_{upper}_DATE")) {
+        Ok(date) => Signature::new(&name, &email, &parse_date(&date)?),
+        Err(_) => Signature::now(&name, &email),
+    };
+    signature.with_context(|| format!("invalid {role} identity {name} <{email}>"))
+}
+
+/// Parse git's internal date format: `[@]<seconds> <+hhmm>`.
+fn parse_date(date: &str) -> Result<Time> {
+    let parse = || {
+        let (seconds, zone) = date.trim().trim_start_matches('@').split_once(' ')?;
+        let seconds: i64 = seconds.parse().ok()?;
+        let sign = match zone.as_bytes().first()? {
+            b'+' => 1,
+            b'-' => -1,
+            _ => return None,
+        };
+        let zone: i32 = zone.get(1..)?.parse().ok()?;
+        Some(Time::new(seconds, sign * (zone / 100 * 60 + zone % 100)))
+    };
+    parse().with_context(|| format!("unsupported date {date:?}: use \"@<seconds> <+hhmm>\""))
+}
+
+/// The key to sign with, if signing.
+fn signing_key(
+    config: &Config,
+    signing: &Signing,
+    committer: &Signature,
+) -> Result<Option<String>> {
+    let key = match signing {
+        Signing::Off => return Ok(None),
+        Signing::Config if !config.get_bool("commit.gpgSign").unwrap_or(false) => return Ok(None),
+        Signing::Key(Some(key)) => return Ok(Some(key.clone())),
+        Signing::Config | Signing::Key(None) => config.get_string("user.signingKey").ok(),
+    };
+    match (key, format(config).as_str()) {
+        (Some(key), _) => Ok(Some(key)),
+        (None, "ssh") => bail!("ssh signing needs user.signingKey"),
+        // gpg picks a key by the committer identity
+        (None, _) => Ok(Some(format!(
+            "{} <{}>",
+            committer.name().unwrap_or_default(),
+            committer.email().unwrap_or_default()
+        ))),
+    }
+}
+
+fn format(config: &Config) -> String {
+    config
+        .get_string("gpg.format")
+        .unwrap_or_else(|_| "openpgp".to_owned())
+}
+
+/// Sign a commit buffer with the program for `gpg.format`.
+fn sign(repo: &Repository, config: &Config, key: &str, buffer: &str) -> Result<String> {
+    let format = format(config);
+    let program = |default: &str| {
+        config
+            .get_string(&format!("gpg.{format}.program"))
+            .or_else(|_| match format.as_str() {
+                "openpgp" => config.get_string("gpg.program"),
+                _ => Err(git2::Error::from_str("unset")),
+            })
+            .unwrap_or_else(|_| default.to_owned())
+    };
+
+    let output = match format.as_str() {
+        "openpgp" | "x509" => {
+            let default = if format == "x509" { "gpgsm" } else { "gpg" };
+            run_signer(
+                Command::new(program(default)).args(["--status-fd=2", "-bsau", key]),
+                buffer,
+            )?
+        }
+        "ssh" => return sign_ssh(repo, &program("ssh-keygen"), key, buffer),
+        other => bail!("unsupported gpg.format {other:?}"),
+    };
+    String::from_utf8(output.stdout).context("signature is not valid UTF-8")
+}
+
+/// Sign with `ssh-keygen -Y sign`, which reads and writes files.
+fn sign_ssh(repo: &Repository, program: &str, key: &str, buffer: &str) -> Result<String> {
+    let pid = std::process::id();
+    let scratch = |name: &str| {
+        let path = repo.path().join(format!("{name}.{pid}"));
+        cleanup::register(&path);
+        path
+    };
+
+    let mut command = Command::new(program);
+    command.args(["-Y", "sign", "-n", "git", "-f"]);
+    // A literal public key signs through ssh-agent
+    let literal = key
+        .strip_prefix("key::")
+        .or_else(|| key.starts_with("ssh-").then_some(key));
+    let key_file = if let Some(literal) = literal {
+        let key_file = scratch("commit-staged-signing-key");
+        std::fs::write(&key_file, literal).context("failed to write signing key")?;
+        command.arg(&key_file).arg("-U");
+        Some(key_file)
+    } else {
+        let home = std::env::var("HOME").unwrap_or_default();
+        command.arg(key.strip_prefix("~/").map_or_else(
+            || PathBuf::from(key),
+            |rest| Path::new(&home).join(rest),
+        ));
+        None
+    };
+
+    let buffer_file = scratch("commit-staged-signing-buffer");
+    let mut signature_file = buffer_file.clone().into_os_string();
+    signature_file.push(".sig");
+    let signature_file = PathBuf::from(signature_file);
+    cleanup::register(&signature_file);
+    let result = std::fs::write(&buffer_file, buffer)
+        .context("failed to write commit for signing")
+        .and_then(|()| run_signer(command.arg(&buffer_file), ""))
+        .and_then(|_| {
+            std::fs::read_to_string(&signature_file).context("failed to read ssh signature")
+        });
+
+    for file in key_file.iter().chain([&buffer_file, &signature_file]) {
+        let _ = cleanup::remove(file);
+    }
+    result
+}
+
+/// Run a signing program with `input` on stdin.
+fn run_signer(command: &mut Command, input: &str) -> Result<Output> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("failed to run {program}"))?;
+    // Write from another thread: the signer may not read all input before
+    // writing output
+    let mut stdin = child.stdin.take().context("signer has no stdin")?;
+    let input = input.to_owned();
+    let writer = std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let output = child
+        .wait_with_output()
+        .with_context(|| format!("failed to wait for {program}"))?;
+    let _ = writer.join();
+
+    if !output.status.success() {
+        bail!(
+            "{program} failed to sign the commit: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    Ok(output)
+}
+
+/// Point `reference` at `new` if it still points at `old`, with a reflog entry.
+pub(crate) fn update_ref(
+    repo: &Repository,
+    reference: &str,
+    new: Oid,
+    old: Option<Oid>,
+    log_message: &str,
+) -> Result<()> {
+    let result = old.map_or_else(
+        // Creating the branch fails if someone else already did
+        || repo.reference(reference, new, false, log_message),
+        |old| repo.reference_matching(reference, new, true, old, log_message),
+    );
+    if let Err(err) = result {
+        let current = repo.refname_to_id(reference).ok();
+        if current != old {
+            return Err(Error::HeadMoved {
+                expected: old.unwrap_or_else(Oid::zero),
+                actual: current.unwrap_or_else(Oid::zero),
+            }
+            .into());
+        }
+        return Err(err).with_context(|| format!("failed to update {reference}"));
+    }
+    Ok(())
+}
+
+/// Point `reference` back from `commit` at `old`, the commit before it, if
+/// it still points at `commit`, with a reflog entry.
+///
+/// If `commit` was a root commit (`old` is `None`), the branch is deleted,
+/// making it unborn again; a detached HEAD can't be.
+///
+/// # Errors
+/// Returns [`Error::HeadMoved`] if `reference` no longer points at
+/// `commit`, or an error if it cannot be updated or deleted.
+pub(crate) fn rewind_ref(
+    repo: &Repository,
+    reference: &str,
+    commit: Oid,
+    old: Option<Oid>,
+    log_message: &str,
+) -> Result<()> {
+    if let Some(old) = old {
+        return update_ref(repo, reference, old, Some(commit), log_message);
+    }
+    if reference == "HEAD" {
+        bail!("cannot take back root commit {commit} on a detached HEAD");
+    }
+    let mut branch = repo
+        .find_reference(reference)
+        .with_context(|| format!("failed to read {reference}"))?;
+    let current = branch.target();
+    if current != Some(commit) {
+        return Err(Error::HeadMoved {
+            expected: commit,
+            actual: current.unwrap_or_else(Oid::zero),
+        }
+        .into());
+    }
+    // Fails if the branch moved since it was read
+    branch
+        .delete()
+        .with_context(|| format!("failed to delete {reference}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|&arg| arg.to_owned()).collect()
+    }
+
+    #[test]
+    fn parses_supported_options() {
+        let options = CommitOptions::parse(&args(&[
+            "-m", "Subject", "-mBody", "--amend", "-n", "-SKEY", "--trailer", "Owners: @a",
+        ]))
+        .unwrap();
+        assert_eq!(
+            options,
+            CommitOptions {
+                messages: args(&["Subject", "Body"]),
+                message_file: None,
+                amend: true,
+                no_verify: true,
+                signing: Signing::Key(Some("KEY".to_owned())),
+                trailers: args(&["Owners: @a"]),
+            }
+        );
+
+        assert!(CommitOptions::parse(&args(&["--fixup", "HEAD"])).is_err());
+        assert!(CommitOptions::parse(&args(&["-m"])).is_err());
+    }
+
+    #[test]
+    fn trailers_join_existing_block() {
+        let trailers = args(&["Agent-Session: s1", "Reviewed-by: A <a@b.c>"]);
+        assert_eq!(
+            add_trailers("Subject\n", &trailers).unwrap(),
+            "Subject\n\nAgent-Session: s1\nReviewed-by: A <a@b.c>\n"
+        );
+        assert_eq!(
+            add_trailers("Subject\n\nAgent-Session: s1\n", &trailers).unwrap(),
+            "Subject\n\nAgent-Session: s1\nReviewed-by: A <a@b.c>\n"
+        );
+    }
+
+    #[test]
+    fn parses_internal_dates() {
+        let time = parse_date("@1700000000 -0130").unwrap();
+        assert_eq!(time.seconds(), 1_700_000_000);
+        assert_eq!(time.offset_minutes(), -90);
+        assert!(parse_date("yesterday").is_err());
+    }
+}
@@ -1,12 +1,25 @@
 //! Prepare staged changes at specific paths for commit.
 
-use anyhow::{bail, Context, Result};
-use git2::Repository;
+use anyhow::{Context, Result};
+use git2::{Index, Repository};
 use std::borrow::Cow;
 use std::path::{Path, PathBuf};
 
+use crate::error::Error;
 use crate::index::write_temp_index;
-use crate::{PrepareResult, StagedEntry};
+use crate::{head_tree, PrepareResult, StagedEntry};
+
+/// Where the content to commit is read from.
+#[derive(Debug, Clone, Default)]
+pub enum Source {
+    /// The repository's index (`.git/index`, or `$GIT_INDEX_FILE`)
+    #[default]
+    Index,
+    /// Another index file, e.g. another agent's or a staging area's
+    IndexFile(PathBuf),
+    /// A tree-ish (commit, tag, stash, tree): paths exactly as they exist there
+    Tree(String),
+}
 
 /// Prepare staged changes at specific paths for commit.
 ///
@@ -34,6 +47,24 @@ pub fn prepare_staged_commit<P: AsRef<Path>>(
     paths: &[P],
     directory: &Path,
     dry_run: bool,
+) -> Result<PrepareResult> {
+    prepare_staged_commit_from(paths, directory, dry_run, &Source::Index)
+}
+
+/// Prepare changes at specific paths from `source` for commit.
+///
+/// Like [`prepare_staged_commit`], but the content layered onto HEAD is read
+/// from `source` instead of the repository's index.
+///
+/// # Errors
+/// As for [`prepare_staged_commit`], plus:
+/// - The source index file cannot be read
+/// - The source tree-ish cannot be resolved
+pub fn prepare_staged_commit_from<P: AsRef<Path>>(
+    paths: &[P],
+    directory: &Path,
+    dry_run: bool,
+    source: &Source,
 ) -> Result<PrepareResult> {
     // Canonicalize scope root (the -C directory) - this resolves symlinks
     let scope_root = std::fs::canonicalize(directory)
@@ -51,10 +82,10 @@ pub fn prepare_staged_commit<P: AsRef<Path>>(
     // Resolve user paths to repo-relative paths
     let resolved_paths = resolve_paths(paths, &scope_root, &repo_root)?;
 
-    let staged_entries = find_staged_entries(&repo, &resolved_paths)?;
+    let staged_entries = find_staged_entries(&repo, &resolved_paths, source)?;
 
     if staged_entries.is_empty() {
-        bail!("no staged changes at specified paths");
+        return Err(Error::NoChanges("no staged changes at specified paths".to_owned()).into());
     }
 
     if dry_run {
@@ -93,48 +124,60 @@ fn resolve_paths<P: AsRef<Path>>(
                 .context("path normalization failed")?;
 
             // Check path stays within scope
+            let escapes = |scope: &Path| Error::PathEscapesScope {
+                path: user_path.as_ref().to_path_buf(),
+                scope: scope.to_path_buf(),
+            };
             if !normalized.starts_with(scope_root) {
-                bail!(
-                    "{} escapes scope {}",
-                    user_path.as_ref().display(),
-                    scope_root.display()
-                );
+                return Err(escapes(scope_root).into());
             }
 
             // Strip repo_root to get repo-relative path
             normalized
                 .strip_prefix(repo_root)
                 .map(Path::to_path_buf)
-                .with_context(|| {
-                    format!(
-                        "{} is outside repository {}",
-                        user_path.as_ref().display(),
-                        repo_root.display()
-                    )
-                })
+                .map_err(|_| escapes(repo_root).into())
         })
         .collect()
 }
 
-/// Find entries in the index that differ from HEAD at the given paths
-fn find_staged_entries(repo: &Repository, paths: &[PathBuf]) -> Result<Vec<StagedEntry>> {
-    let index = repo.index().context("failed to read index")?;
-    let head_tree = repo
-        .head()
-        .context("failed to get HEAD")?
-        .peel_to_tree()
-        .context("failed to peel HEAD to tree")?;
-
-    let diff = repo
-        .diff_tree_to_index(Some(&head_tree), Some(&index), None)
-        .context("failed to diff HEAD to index")?;
+/// Find entries in `source` that differ from HEAD at the given paths
+fn find_staged_entries(
+    repo: &Repository,
+    paths: &[PathBuf],
+    source: &Source,
+) -> Result<Vec<StagedEntry>> {
+    let head_tree = head_tree(repo)?;
+
+    let diff = match source {
+        Source::Index => {
+            let index = repo.index().context("failed to read index")?;
+            repo.diff_tree_to_index(Some(&head_tree), Some(&index), None)
+                .context("failed to diff HEAD to index")?
+        }
+        Source::IndexFile(path) => {
+            let index = Index::open(path)
+                .with_context(|| format!("failed to read index file {}", path.display()))?;
+            repo.diff_tree_to_index(Some(&head_tree), Some(&index), None)
+                .with_context(|| format!("failed to diff HEAD to {}", path.display()))?
+        }
+        Source::Tree(rev) => {
+            let tree = repo
+                .revparse_single(rev)
+                .and_then(|object| object.peel_to_tree())
+                .with_context(|| format!("failed to resolve {rev} to a tree"))?;
+            repo.diff_tree_to_tree(Some(&head_tree), Some(&tree), None)
+                .with_context(|| format!("failed to diff HEAD to {rev}"))?
+        }
+    };
 
     let mut staged = Vec::new();
 
     for delta in diff.deltas() {
         // Note: new_file().path() returns the path for all delta types from
         // diff_tree_to_index, including deletions. The old_file fallback was
-        // dead code - git2 always populates new_file.path() for this diff type.
+        // dead code - git2 always populates new_file.path() for this diff type
+        // (and likewise for diff_tree_to_tree).
         let path = delta.new_file().path().context("diff delta has no path")?;
 
         if !path_matches(path, paths) {
@@ -0,0 +1,222 @@
+//! Re-applying path-scoped changes when HEAD moved off their base.
+//!
+//! Staged entries are absolute: the blob (or deletion) each path should
+//! have. Made on top of a base commit that is no longer HEAD, committing
+//! them as-is would revert whatever HEAD gained at those paths since.
+//! Instead, the changes relative to the base are merged into HEAD's tree:
+//! paths only one side touched take that side, and paths both sides touched
+//! get a three-way content merge. Renames aren't followed, so changing a
+//! path HEAD renamed or deleted is a conflict. Real conflicts abort with
+//! [`Error::Conflict`] before anything is written.
+
+use anyhow::{Context, Result};
+use git2::{Commit, Index, IndexConflict, MergeOptions, Oid, Repository};
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use crate::error::Error;
+use crate::index::{apply_entries, tree_entry};
+use crate::{head_commit, StagedEntry};
+
+/// Drop entries that don't change their path relative to `base`.
+///
+/// # Errors
+/// Returns an error if `base` cannot be read.
+pub fn changes_since(
+    repo: &Repository,
+    base: Oid,
+    entries: &[StagedEntry],
+) -> Result<Vec<StagedEntry>> {
+    let base_tree = repo
+        .find_commit(base)
+        .and_then(|commit| commit.tree())
+        .with_context(|| format!("failed to read base commit {base}"))?;
+    Ok(entries
+        .iter()
+        .filter(|(path, data)| tree_entry(&base_tree, path) != *data)
+        .cloned()
+        .collect())
+}
+
+/// Re-apply `entries`, made on top of `base`, onto the current HEAD.
+///
+/// Returns the entries to layer onto HEAD: the merged content of each path
+/// that still differs from HEAD.
+///
+/// # Errors
+/// Returns [`Error::Conflict`] if the changes conflict with HEAD's, or an
+/// error if the commits cannot be read or merged.
+pub fn rebase_entries(
+    repo: &Repository,
+    base: Oid,
+    entries: &[StagedEntry],
+) -> Result<Vec<StagedEntry>> {
+    rebase_entries_onto(repo, base, &head_commit(repo)?, entries)
+}
+
+/// Re-apply `entries`, made on top of `base`, onto `onto`, as
+/// [`rebase_entries`] does for HEAD.
+///
+/// # Errors
+/// Returns [`Error::Conflict`] if the changes conflict with `onto`'s, or an
+/// error if the commits cannot be read or merged.
+pub fn rebase_entries_onto(
+    repo: &Repository,
+    base: Oid,
+    onto: &Commit,
+    entries: &[StagedEntry],
+) -> Result<Vec<StagedEntry>> {
+    if onto.id() == base {
+        return Ok(entries.to_vec());
+    }
+
+    let base_tree = repo
+        .find_commit(base)
+        .and_then(|commit| commit.tree())
+        .with_context(|| format!("failed to read base commit {base}"))?;
+    let head_tree = onto.tree().context("failed to get tree to rebase onto")?;
+
+    // Our side: the base with our entries applied
+    let mut ours = Index::new().context("failed to create index")?;
+    ours.read_tree(&base_tree)
+        .context("failed to read base tree into index")?;
+    apply_entries(&mut ours, entries)?;
+    let ours_tree = ours
+        .write_tree_to(repo)
+        .and_then(|oid| repo.find_tree(oid))
+        .context("failed to write tree for our changes")?;
+
+    // Without rename detection: a path HEAD renamed would otherwise take our
+    // change along to its new name, which isn't one of our entries, and the
+    // change would be silently lost. Changing a path HEAD moved conflicts.
+    let mut options = MergeOptions::new();
+    options.find_renames(false);
+    let merged = repo
+        .merge_trees(&base_tree, &ours_tree, &head_tree, Some(&options))
+        .context("failed to merge our changes into HEAD")?;
+
+    if merged.has_conflicts() {
+        return Err(Error::Conflict {
+            paths: conflict_paths(&merged)?,
+        }
+        .into());
+    }
+
+    Ok(entries
+        .iter()
+        .filter_map(|(path, _)| {
+            let data = merged
+                .get_path(Path::new(path), 0)
+                .map(|entry| (entry.id, entry.mode));
+            (data != tree_entry(&head_tree, path)).then(|| (path.clone(), data))
+        })
+        .collect())
+}
+
+/// Paths with conflicts in a merged index, sorted.
+///
+/// # Errors
+/// Returns an error if the conflicts cannot be read.
+pub(crate) fn conflict_paths(merged: &Index) -> Result<Vec<String>> {
+    let paths: BTreeSet<String> = merged
+        .conflicts()
+        .context("failed to read merge conflicts")?
+        .filter_map(|conflict| conflict.ok().and_then(conflict_path))
+        .collect();
+    Ok(paths.into_iter().collect())
+}
+
+fn conflict_path(conflict: IndexConflict) -> Option<String> {
+    [conflict.our, conflict.their, conflict.ancestor]
+        .into_iter()
+        .flatten()
+        .next()
+        .map(|entry| String::from_utf8_lossy(&entry.path).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::unstatted_entry;
+    use tempfile::TempDir;
+
+    /// Commit `files` on top of HEAD (if any), returning the new commit.
+    fn commit(repo: &Repository, files: &[(&str, &str)]) -> Oid {
+        let mut index = Index::new().unwrap();
+        let parent = repo.head().ok().map(|head| head.peel_to_commit().unwrap());
+        if let Some(parent) = &parent {
+            index.read_tree(&parent.tree().unwrap()).unwrap();
+        }
+        for (path, content) in files {
+            let blob = repo.blob(content.as_bytes()).unwrap();
+            index.add(&unstatted_entry(path, blob, 0o100_644)).unwrap();
+        }
+        let tree = repo.find_tree(index.write_tree_to(repo).unwrap()).unwrap();
+        let sig = git2::Signature::now("Test", "test@test.com").unwrap();
+        let parents: Vec<_> = parent.iter().collect();
+        repo.commit(Some("HEAD"), &sig, &sig, "commit", &tree, &parents)
+            .unwrap()
+    }
+
+    fn entry(repo: &Repository, path: &str, content: &str) -> StagedEntry {
+        let blob = repo.blob(content.as_bytes()).unwrap();
+        (path.to_owned(), Some((blob, 0o100_644)))
+    }
+
+    #[test]
+    fn merges_paths_changed_on_both_sides() {
+        let dir = TempDir::new().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let base = commit(&repo, &[("a", "1\n2\n3\n4\n5\n"), ("b", "b\n")]);
+        commit(&repo, &[("a", "1\n2\n3\n4\nfive\n"), ("b", "theirs\n")]);
+
+        let ours = [entry(&repo, "a", "one\n2\n3\n4\n5\n")];
+        let rebased = rebase_entries(&repo, base, &ours).unwrap();
+        assert_eq!(rebased, [entry(&repo, "a", "one\n2\n3\n4\nfive\n")]);
+
+        let conflicting = [entry(&repo, "b", "ours\n")];
+        let err = rebase_entries(&repo, base, &conflicting).unwrap_err();
+        let Some(Error::Conflict { paths }) = Error::find(&err) else {
+            panic!("expected conflict: {err:#}");
+        };
+        assert_eq!(paths, &["b"]);
+    }
+
+    #[test]
+    fn changing_a_path_head_renamed_conflicts() {
+        let dir = TempDir::new().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let base = commit(&repo, &[("a", "1\n2\n3\n4\n5\n")]);
+
+        // HEAD renames a to c
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        let mut index = Index::new().unwrap();
+        index.read_tree(&head.tree().unwrap()).unwrap();
+        let moved = index.get_path(Path::new("a"), 0).unwrap().id;
+        index.remove_path(Path::new("a")).unwrap();
+        index.add(&unstatted_entry("c", moved, 0o100_644)).unwrap();
+        let tree = repo.find_tree(index.write_tree_to(&repo).unwrap()).unwrap();
+        let sig = git2::Signature::now("Test", "test@test.com").unwrap();
+        repo.commit(Some("HEAD"), &sig, &sig, "rename", &tree, &[&head])
+            .unwrap();
+
+        let ours = [entry(&repo, "a", "one\n2\n3\n4\n5\n")];
+        let err = rebase_entries(&repo, base, &ours).unwrap_err();
+        let Some(Error::Conflict { paths }) = Error::find(&err) else {
+            panic!("expected conflict: {err:#}");
+        };
+        assert_eq!(paths, &["a"]);
+    }
+
+    #[test]
+    fn unchanged_paths_are_dropped() {
+        let dir = TempDir::new().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let base = commit(&repo, &[("a", "a\n")]);
+        commit(&repo, &[("a", "theirs\n")]);
+
+        // The index still has the base's content, which HEAD changed since
+        let stale = [entry(&repo, "a", "a\n")];
+        assert!(changes_since(&repo, base, &stale).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,378 @@
+//! Committing several groups of changes as one atomic series.
+//!
+//! Each commit of a series is built from its own temp index on top of the
+//! previous one, with git2 as in `--plumbing` (`pre-commit` and
+//! `commit-msg` run for each). The branch is only updated once, after the
+//! last commit is made: if any step fails, nothing is committed. The main
+//! index is never written.
+//!
+//! `git commit-staged --series FILE` reads the groups from a TOML file;
+//! `--each` makes one group per file or directory, and `--split-by-owners`
+//! one per set of CODEOWNERS owners, with the message templated from `-m`.
+//! A series file's paths are relative to the current directory, like paths
+//! on the command line, so the file can live anywhere (even in `.git`).
+//!
+//! ```toml
+//! [[commit]]
+//! paths = ["src/parser.rs", "tests/parser.rs"]  # relative to the cwd
+//! message = "Add parser"
+//!
+//! [[commit]]
+//! paths = ["docs/"]
+//! message_file = "docs-message.txt"  # relative to the series file
+//! ```
+
+use anyhow::{bail, Context, Result};
+use git2::Repository;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use crate::cleanup;
+use crate::codeowners::CodeOwners;
+use crate::commit::{read_head, CommitOutput};
+use crate::error::Error;
+use crate::index::{tree_entry, write_index};
+use crate::plumbing::{create_commit, run_post_commit, update_ref, CommitOptions};
+use crate::{head_commit, StagedEntry};
+
+mod each;
+pub use each::Each;
+
+/// A commit message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    /// The message itself
+    Text(String),
+    /// A file holding it
+    File(PathBuf),
+}
+
+/// A group of paths from a series file, and its message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathGroup {
+    /// Paths to commit, as given on the command line
+    pub paths: Vec<PathBuf>,
+    /// Commit message
+    pub message: Message,
+}
+
+/// One commit of a series.
+#[derive(Debug, Clone)]
+pub struct SeriesCommit {
+    /// Names the commit in errors and dry runs
+    pub label: String,
+    /// What to commit
+    pub entries: Vec<StagedEntry>,
+    /// Commit message
+    pub message: Message,
+    /// Trailers to add to the message (`Key: value`)
+    pub trailers: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SeriesFile {
+    commit: Vec<SeriesFileCommit>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SeriesFileCommit {
+    paths: Vec<PathBuf>,
+    message: Option<String>,
+    message_file: Option<PathBuf>,
+}
+
+/// One commit per group of `entries`, with `{path}` in `template` replaced
+/// by the group's path. Groups are committed in path order.
+#[must_use]
+pub fn split_each(entries: &[StagedEntry], each: Each, template: &str) -> Vec<SeriesCommit> {
+    let mut groups: BTreeMap<&str, Vec<StagedEntry>> = BTreeMap::new();
+    for entry in entries {
+        groups.entry(each.group(&entry.0)).or_default().push(entry.clone());
+    }
+    groups
+        .into_iter()
+        .map(|(group, entries)| SeriesCommit {
+            label: group.to_owned(),
+            entries,
+            message: Message::Text(template.replace("{path}", group)),
+            trailers: Vec::new(),
+        })
+        .collect()
+}
+
+/// One commit per set of owners of `entries`, with `{owners}` in
+/// `template` replaced by the owners and an `Owners:` trailer. Unowned
+/// entries are committed first, as `unowned`, without a trailer.
+#[must_use]
+pub fn split_by_owners(
+    entries: &[StagedEntry],
+    owners: &CodeOwners,
+    template: &str,
+) -> Vec<SeriesCommit> {
+    let mut groups: BTreeMap<Vec<String>, Vec<StagedEntry>> = BTreeMap::new();
+    for entry in entries {
+        groups.entry(owners.owners(&entry.0)).or_default().push(entry.clone());
+    }
+    groups
+        .into_iter()
+        .map(|(group, entries)| {
+            let (label, trailers) = if group.is_empty() {
+                ("unowned".to_owned(), Vec::new())
+            } else {
+                let group = group.join(" ");
+                (group.clone(), vec![format!("Owners: {group}")])
+            };
+            SeriesCommit {
+                message: Message::Text(template.replace("{owners}", &label)),
+                label,
+                entries,
+                trailers,
+            }
+        })
+        .collect()
+}
+
+/// Take the `-m`/`-F` message out of `options`, to use as a template.
+///
+/// # Errors
+/// Returns an error if there is no message, or the file cannot be read.
+pub fn take_message_template(options: &mut CommitOptions) -> Result<String> {
+    if !options.messages.is_empty() {
+        return Ok(std::mem::take(&mut options.messages).join("\n\n"));
+    }
+    let file = options
+        .message_file
+        .take()
+        .context("a message template is needed: -m or -F after --")?;
+    if file == Path::new("-") {
+        let mut message = String::new();
+        std::io::stdin()
+            .read_to_string(&mut message)
+            .context("failed to read message from stdin")?;
+        return Ok(message);
+    }
+    std::fs::read_to_string(&file)
+        .with_context(|| format!("failed to read message from {}", file.display()))
+}
+
+/// Read the path groups of a series file.
+///
+/// Paths are returned as written, to be resolved against the current
+/// directory; a `message_file` is resolved against the series file's.
+///
+/// # Errors
+/// Returns an error if the file cannot be read or parsed, or a group has no
+/// paths, or not exactly one of `message` and `message_file`.
+pub fn read_series_file(path: &Path) -> Result<Vec<PathGroup>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read series {}", path.display()))?;
+    let file: SeriesFile = toml::from_str(&text)
+        .with_context(|| format!("failed to parse series {}", path.display()))?;
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+
+    file.commit
+        .into_iter()
+        .enumerate()
+        .map(|(i, commit)| {
+            let number = i + 1;
+            if commit.paths.is_empty() {
+                bail!("commit {number} in {} has no paths", path.display());
+            }
+            let message = match (commit.message, commit.message_file) {
+                (Some(text), None) => Message::Text(text),
+                (None, Some(file)) => Message::File(dir.join(file)),
+                _ => bail!(
+                    "commit {number} in {} needs either message or message_file",
+                    path.display()
+                ),
+            };
+            Ok(PathGroup {
+                paths: commit.paths,
+                message,
+            })
+        })
+        .collect()
+}
+
+/// Commit `commits` in order on top of HEAD, then update the ref once.
+///
+/// `options` are the `git commit` options as for `--plumbing`, except that
+/// messages come from the series and `--amend` is refused. `post-commit`
+/// runs once per commit, after the ref update. The temp index is removed
+/// regardless of outcome.
+///
+/// **Caller must hold index.lock** for the entire operation.
+///
+/// # Errors
+/// Returns [`Error::NoChanges`] if a commit has nothing to commit on top of
+/// the previous one, [`Error::HookRejected`] if a hook rejects one,
+/// [`Error::HeadMoved`] if HEAD moved meanwhile, or an error if any commit
+/// cannot be made. Nothing is committed in any of these cases.
+pub fn commit_series(
+    repo: &Repository,
+    commits: &[SeriesCommit],
+    options: &CommitOptions,
+) -> Result<Vec<CommitOutput>> {
+    let temp_index_path = repo
+        .path()
+        .join(format!("index.commit-staged.{}", std::process::id()));
+    cleanup::register(&temp_index_path);
+    let result = build_series(repo, commits, options, &temp_index_path);
+    let _ = cleanup::remove(&temp_index_path);
+    result
+}
+
+fn build_series(
+    repo: &Repository,
+    commits: &[SeriesCommit],
+    options: &CommitOptions,
+    temp_index_path: &Path,
+) -> Result<Vec<CommitOutput>> {
+    if !options.messages.is_empty() || options.message_file.is_some() || options.amend {
+        bail!("-m, -F and --amend can't be used with a series: each commit has its message");
+    }
+    if commits.is_empty() {
+        return Err(Error::NoChanges("the series has no commits".to_owned()).into());
+    }
+
+    let (reference, old_head) = read_head(repo)?;
+    let branch = reference.strip_prefix("refs/heads/").map(str::to_owned);
+    let mut parent = head_commit(repo)?;
+    let mut outputs = Vec::new();
+
+    for commit in commits {
+        let parent_tree = parent.tree().context("failed to read parent tree")?;
+        let entries: Vec<StagedEntry> = commit
+            .entries
+            .iter()
+            .filter(|(path, data)| tree_entry(&parent_tree, path) != *data)
+            .cloned()
+            .collect();
+        if entries.is_empty() {
+            let message = format!("nothing to commit for {}", commit.label);
+            return Err(Error::NoChanges(message).into());
+        }
+
+        write_index(temp_index_path, &parent_tree, &entries)?;
+        let mut options = match &commit.message {
+            Message::Text(text) => CommitOptions {
+                messages: vec![text.clone()],
+                ..options.clone()
+            },
+            Message::File(file) => CommitOptions {
+                message_file: Some(file.clone()),
+                ..options.clone()
+            },
+        };
+        options.trailers.extend(commit.trailers.iter().cloned());
+        let oid = create_commit(repo, temp_index_path, &options, Some(&parent))
+            .with_context(|| format!("failed to commit {}", commit.label))?;
+        let new = repo.find_commit(oid).context("failed to read new commit")?;
+
+        outputs.push(CommitOutput {
+            commit: oid,
+            tree: new.tree_id(),
+            old_head: Some(parent.id()),
+            reference: reference.clone(),
+            branch: branch.clone(),
+            amend: false,
+        });
+        parent = new;
+    }
+
+    let message = format!("commit (series): {} commits", outputs.len());
+    update_ref(repo, &reference, parent.id(), old_head, &message)?;
+    // Once per commit, as `git commit` would, with that commit's tree in the
+    // index; the branch is already at the last one
+    for output in &outputs {
+        let written = repo
+            .find_tree(output.tree)
+            .context("failed to read commit tree")
+            .and_then(|tree| write_index(temp_index_path, &tree, &[]));
+        match written {
+            Ok(()) => run_post_commit(repo, temp_index_path),
+            Err(err) => eprintln!("warning: post-commit not run for {}: {err:#}", output.commit),
+        }
+    }
+    Ok(outputs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn splits_entries_by_file_dir_and_top_dir() {
+        let entries: Vec<StagedEntry> = ["a", "src/b", "src/c", "src/lib/d"]
+            .iter()
+            .map(|path| ((*path).to_owned(), None))
+            .collect();
+        let labels = |each| -> Vec<String> {
+            split_each(&entries, each, "").into_iter().map(|c| c.label).collect()
+        };
+        assert_eq!(labels(Each::File), ["a", "src/b", "src/c", "src/lib/d"]);
+        assert_eq!(labels(Each::Dir), [".", "src", "src/lib"]);
+        assert_eq!(labels(Each::TopDir), [".", "src"]);
+
+        let commits = split_each(&entries, Each::TopDir, "fmt: {path}");
+        assert_eq!(commits[1].entries.len(), 3);
+        assert_eq!(commits[1].message, Message::Text("fmt: src".to_owned()));
+    }
+
+    #[test]
+    fn splits_entries_by_owners() {
+        let codeowners = CodeOwners::parse("*.rs @rust\n/web/ @web @rust\n");
+        let entries: Vec<StagedEntry> = ["README", "src/a.rs", "web/b.rs", "web/c.js"]
+            .iter()
+            .map(|path| ((*path).to_owned(), None))
+            .collect();
+
+        let commits = split_by_owners(&entries, &codeowners, "Update for {owners}");
+        let found: Vec<(&str, usize)> = commits
+            .iter()
+            .map(|c| (c.label.as_str(), c.entries.len()))
+            .collect();
+        assert_eq!(found, [("unowned", 1), ("@rust", 1), ("@rust @web", 2)]);
+        assert!(commits[0].trailers.is_empty());
+        assert_eq!(commits[2].trailers, ["Owners: @rust @web"]);
+        assert_eq!(
+            commits[2].message,
+            Message::Text("Update for @rust @web".to_owned())
+        );
+    }
+
+    #[test]
+    fn reads_groups_with_messages() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("series.toml");
+        std::fs::write(
+            &path,
+            "[[commit]]\npaths = [\"a\", \"b/\"]\nmessage = \"Add a\"\n\n\
+             [[commit]]\npaths = [\"c\"]\nmessage_file = \"c.txt\"\n",
+        )
+        .unwrap();
+
+        let groups = read_series_file(&path).unwrap();
+        assert_eq!(
+            groups,
+            [
+                PathGroup {
+                    paths: vec!["a".into(), "b/".into()],
+                    message: Message::Text("Add a".to_owned()),
+                },
+                PathGroup {
+                    paths: vec!["c".into()],
+                    message: Message::File(dir.path().join("c.txt")),
+                },
+            ]
+        );
+
+        std::fs::write(&path, "[[commit]]\npaths = [\"a\"]\n").unwrap();
+        assert!(read_series_file(&path).is_err(), "no message");
+    }
+}
@@ -0,0 +1,31 @@
+//! How `--each` groups entries.
+//!
+//! Kept free of the rest of the crate: the CLI uses [`Each`] as a clap value,
+//! and `build.rs` includes this file to render the man pages.
+
+use clap::ValueEnum;
+
+/// How `--each` groups entries into commits.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Each {
+    /// One commit per file
+    File,
+    /// One per directory, not including subdirectories
+    Dir,
+    /// One per top-level directory (and one for files at the top)
+    TopDir,
+}
+
+impl Each {
+    /// The group of `path`: the path itself, its directory or its top-level
+    /// directory (`.` for the top of the repository).
+    #[must_use]
+    pub fn group(self, path: &str) -> &str {
+        let group = match self {
+            Self::File => Some(path),
+            Self::Dir => path.rsplit_once('/').map(|(dir, _)| dir),
+            Self::TopDir => path.split_once('/').map(|(top, _)| top),
+        };
+        group.unwrap_or(".")
+    }
+}
@@ -0,0 +1,132 @@
+//! Per-session commit identity.
+//!
+//! An agent session is named by `$GIT_PARTIAL_SESSION` and keeps its state in
+//! `.git/partial.d/<id>/`. The optional `config` file there (git-config format)
+//! carries the identity applied to every commit made in that session:
+//!
+//! ```text
+//! [user]
+//!     name = Agent Smith
+//!     email = smith@agents.example
+//! [session]
+//!     trailer = Co-authored-by: Jane Doe <jane@example.com>
+//!     trailer = Generated-by: some-model
+//! ```
+//!
+//! Every session commit also gets an `Agent-Session: <id>` trailer.
+
+use anyhow::{bail, Context, Result};
+use git2::{Config, Repository};
+use std::path::PathBuf;
+use std::process::Command;
+
+/// Environment variable naming the current session.
+pub const SESSION_ENV: &str = "GIT_PARTIAL_SESSION";
+
+/// Trailer key recording which session made a commit.
+pub const SESSION_TRAILER: &str = "Agent-Session";
+
+/// Identity configuration of an agent session.
+#[derive(Debug, Clone, Default)]
+pub struct Session {
+    /// Session ID (from `$GIT_PARTIAL_SESSION`)
+    pub id: String,
+    /// Author name override (`user.name`)
+    pub author_name: Option<String>,
+    /// Author email override (`user.email`)
+    pub author_email: Option<String>,
+    /// Extra trailers, as `Key: value` (`session.trailer`)
+    pub trailers: Vec<String>,
+}
+
+impl Session {
+    /// Load the current session, if `$GIT_PARTIAL_SESSION` is set.
+    ///
+    /// # Errors
+    /// Returns an error if the session ID is malformed or its config cannot be read.
+    pub fn from_env(repo: &Repository) -> Result<Option<Self>> {
+        match std::env::var(SESSION_ENV) {
+            Ok(id) if !id.is_empty() => Self::load(repo, &id).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    /// Load a session by ID. A session without a config file has no overrides.
+    ///
+    /// # Errors
+    /// Returns an error if the session ID is malformed or its config cannot be read.
+    pub fn load(repo: &Repository, id: &str) -> Result<Self> {
+        if id.contains('/') || id.starts_with('.') {
+            bail!("invalid session id {id:?}");
+        }
+
+        let mut session = Self {
+            id: id.to_owned(),
+            ..Self::default()
+        };
+
+        let config_path = session_dir(repo, id).join("config");
+        if !config_path.exists() {
+            return Ok(session);
+        }
+
+        let config = Config::open(&config_path)
+            .with_context(|| format!("failed to read {}", config_path.display()))?;
+        session.author_name = config.get_string("user.name").ok();
+        session.author_email = config.get_string("user.email").ok();
+
+        let mut trailers = config
+            .multivar("session.trailer", None)
+            .context("failed to read session trailers")?;
+        while let Some(entry) = trailers.next() {
+            let entry = entry.context("failed to read session trailer")?;
+            if let Some(value) = entry.value() {
+                session.trailers.push(value.to_owned());
+            }
+        }
+
+        Ok(session)
+    }
+
+    /// All trailers for this session's commits, `Agent-Session` first.
+    #[must_use]
+    pub fn all_trailers(&self) -> Vec<String> {
+        std::iter::once(format!("{SESSION_TRAILER}: {}", self.id))
+            .chain(self.trailers.iter().cloned())
+            .collect()
+    }
+}
+
+/// Directory holding a session's state: `.git/partial.d/<id>/`.
+#[must_use]
+pub fn session_dir(repo: &Repository, id: &str) -> PathBuf {
+    repo.path().join("partial.d").join(id)
+}
+
+/// Build a `git commit` command carrying the session identity, if any.
+///
+/// Trailers are added with `--trailer`, so they go through
+/// `git interpret-trailers`. `trailer.ifexists=addIfDifferent` keeps
+/// `--amend` from duplicating trailers already in the message.
+/// Caller appends the passthrough args.
+#[must_use]
+pub fn git_commit_command(session: Option<&Session>) -> Command {
+    let mut cmd = Command::new("git");
+
+    let Some(session) = session else {
+        cmd.arg("commit");
+        return cmd;
+    };
+
+    cmd.args(["-c", "trailer.ifexists=addIfDifferent", "commit"]);
+    for trailer in session.all_trailers() {
+        cmd.arg("--trailer").arg(trailer);
+    }
+    if let Some(name) = &session.author_name {
+        cmd.env("GIT_AUTHOR_NAME", name);
+    }
+    if let Some(email) = &session.author_email {
+        cmd.env("GIT_AUTHOR_EMAIL", email);
+    }
+    cmd
+}
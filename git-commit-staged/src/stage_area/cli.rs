@@ -0,0 +1,64 @@
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+#[derive(Parser, Debug)]
+#[command(name = "git-stage-area")]
+#[command(version = env!("CARGO_PKG_VERSION"))]
+#[command(about = "Named persistent staging areas")]
+#[command(
+    long_about = "Maintains named alternative staging areas, stored as index files under\n\
+                  .git/staging/<name>. Each agent can stage into its own area instead of\n\
+                  sharing .git/index.\n\n\
+                  Committing from an area re-bases all other areas onto the new HEAD.\n\n\
+                  Examples:\n\
+                  \x20 git stage-area add agent-1 src/foo.rs\n\
+                  \x20 git stage-area diff agent-1 -- --stat\n\
+                  \x20 git stage-area commit agent-1 -- -m \"Add foo\"\n\
+                  \x20 git stage-area drop agent-1"
+)]
+pub struct Args {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Stage working tree content at paths into an area (creating it if needed)
+    Add {
+        /// Staging area name
+        name: String,
+
+        /// Paths to stage
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+    },
+
+    /// Show changes staged in an area, relative to HEAD
+    Diff {
+        /// Staging area name
+        name: String,
+
+        /// Arguments to pass through to git diff
+        #[arg(last = true)]
+        passthrough_args: Vec<String>,
+    },
+
+    /// Commit everything staged in an area
+    Commit {
+        /// Staging area name
+        name: String,
+
+        /// Arguments to pass through to git commit
+        #[arg(last = true)]
+        passthrough_args: Vec<String>,
+    },
+
+    /// Delete an area and everything staged in it
+    Drop {
+        /// Staging area name
+        name: String,
+    },
+
+    /// List staging areas
+    List,
+}
@@ -0,0 +1,104 @@
+use anyhow::{bail, Context, Result};
+use clap::{CommandFactory, FromArgMatches};
+use git2::Repository;
+use std::os::unix::process::CommandExt;
+use std::process::ExitCode;
+
+mod cli;
+use cli::{Args, Command};
+use git_commit_staged::cleanup;
+use git_commit_staged::commit::do_commit;
+use git_commit_staged::index::write_temp_index;
+use git_commit_staged::journal;
+use git_commit_staged::exec::{print_commit, print_warnings, report_error};
+use git_commit_staged::lock::{IndexLock, WaitPolicy};
+use git_commit_staged::staging::{refresh_all, sync_main_index, StageArea};
+use git_commit_staged::unglobbed_path::UnglobbedPath;
+use git_commit_staged::Error;
+
+const VERSION: &str = concat!(env!("CARGO_PKG_VERSION"), " (", env!("GIT_HASH"), ")");
+
+fn main() -> ExitCode {
+    let code = cleanup::install_signal_handlers()
+        .and_then(|()| run())
+        .map_or_else(|err| report_error(&err), |()| ExitCode::SUCCESS);
+    // Anything a failed run left behind
+    cleanup::remove_all();
+    code
+}
+
+fn run() -> Result<()> {
+    let args = Args::from_arg_matches(&Args::command().version(VERSION).get_matches())?;
+    let repo = Repository::open_from_env().context("failed to open repository")?;
+
+    match args.command {
+        Command::Add { name, paths } => {
+            // Expand directories to files
+            let files = UnglobbedPath::from_paths(&paths);
+            if files.is_empty() {
+                bail!("no files found at specified paths");
+            }
+            let _lock = IndexLock::acquire_for_repo_with(&repo, &WaitPolicy::from_config(&repo)?)?;
+            StageArea::open(&repo, &name)?.add(&repo, &files)
+        }
+        Command::Diff {
+            name,
+            passthrough_args,
+        } => {
+            let area = StageArea::open_existing(&repo, &name)?;
+            let lock = IndexLock::acquire_for_repo_with(&repo, &WaitPolicy::from_config(&repo)?)?;
+            area.ensure_current(&repo)?;
+            // exec() skips destructors
+            drop(lock);
+
+            // exec() replaces this process with git diff
+            let err = std::process::Command::new("git")
+                .args(["diff", "--cached"])
+                .args(&passthrough_args)
+                .env("GIT_INDEX_FILE", &area.index_path)
+                .exec();
+            Err(err).context("failed to exec git diff")
+        }
+        Command::Commit {
+            name,
+            passthrough_args,
+        } => commit_area(&repo, &name, &passthrough_args),
+        Command::Drop { name } => StageArea::open_existing(&repo, &name)?.remove(),
+        Command::List => {
+            for area in StageArea::list(&repo)? {
+                let count = area.staged_entries(&repo)?.len();
+                println!("{}\t{count} staged", area.name);
+            }
+            Ok(())
+        }
+    }
+}
+
+fn commit_area(repo: &Repository, name: &str, passthrough_args: &[String]) -> Result<()> {
+    let _lock = IndexLock::acquire_for_repo_with(repo, &WaitPolicy::from_config(repo)?)?;
+
+    let area = StageArea::open_existing(repo, name)?;
+    area.ensure_current(repo)?;
+
+    let staged_entries = area.staged_entries(repo)?;
+    if staged_entries.is_empty() {
+        return Err(Error::NoChanges(format!("nothing staged in staging area {name}")).into());
+    }
+
+    let old_tree = repo.head()?.peel_to_tree()?;
+    let index_before = journal::index_entries(repo, &staged_entries)?;
+    let temp_index_path = write_temp_index(repo, &staged_entries)?;
+
+    // Lock held throughout - do_commit expects caller to hold it
+    let output = do_commit(&temp_index_path, passthrough_args)?;
+
+    sync_main_index(repo, &old_tree, &staged_entries)?;
+    let mut warnings = refresh_all(repo)?;
+    if let Err(err) = journal::record(repo, "stage-area", &output, &staged_entries, &index_before) {
+        warnings.push(format!("failed to journal the commit: {err:#}"));
+    }
+
+    print_warnings(&warnings);
+    print_commit("stage-area", &output);
+    Ok(())
+}
@@ -1,6 +1,8 @@
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
 use std::path::PathBuf;
 
+use crate::Each;
+
 #[derive(Parser, Debug)]
 #[command(name = "git-commit-staged")]
 #[command(version = env!("CARGO_PKG_VERSION"))]
@@ -8,23 +10,214 @@ use std::path::PathBuf;
 #[command(
     long_about = "Unlike `git commit -- paths`, this commits from the index, not the working copy.\n\n\
                   Arguments after -- are passed through to git commit,\n\
-                  enabling -m, --amend, --fixup, -C, GPG signing, hooks, etc.\n\n\
+                  enabling -m, --amend, --fixup, -C, GPG signing, hooks, etc.\n\
+                  Write a path named like a subcommand as ./status, ./undo, etc.\n\n\
                   Examples:\n\
                   \x20 git commit-staged src/ -- -m \"Add feature\"\n\
                   \x20 git commit-staged src/ tests/ -- --amend\n\
-                  \x20 git commit-staged . -- --fixup HEAD~1"
+                  \x20 git commit-staged . -- --fixup HEAD~1\n\
+                  \x20 git commit-staged --from-tree feature src/foo.rs -- -m \"Take foo\""
 )]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// Paths to commit (only staged changes at these paths)
-    #[arg(required = true)]
+    #[arg(required_unless_present_any = ["plan", "series"])]
     pub paths: Vec<PathBuf>,
 
     /// Show what would be committed without committing
     #[arg(short = 'n', long)]
     pub dry_run: bool,
 
+    /// With --dry-run, write the exact entries to commit and the base HEAD
+    /// to FILE, for --plan
+    #[arg(long, value_name = "FILE", requires = "dry_run")]
+    pub plan_out: Option<PathBuf>,
+
+    /// Commit exactly the entries in this plan file on top of HEAD,
+    /// whatever the index holds
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["paths", "plan_out", "from_index", "from_tree", "expect_plan"]
+    )]
+    pub plan: Option<PathBuf>,
+
+    /// With --plan, commit on top of HEAD even if it moved off the plan's
+    /// base, as long as the planned paths did not change since
+    #[arg(long, requires = "plan")]
+    pub rebase_plan: bool,
+
+    /// Commit each group of paths in this TOML file as its own commit, with
+    /// its own message; the branch is only updated once all are made (and
+    /// post-commit then runs for each). Paths are relative to the current
+    /// directory, message files to FILE's
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = [
+            "paths", "plan", "plan_out", "from_index", "from_tree", "auto_rebase", "each",
+            "split_by_owners",
+            "fold_hook_changes", "verify_cmd",
+        ]
+    )]
+    pub series: Option<PathBuf>,
+
+    /// Make one commit per file, directory or top-level directory of the
+    /// changes to commit; `{path}` in the -m message is replaced by each
+    /// one's path. The branch is only updated once all are made
+    #[arg(long, value_name = "GROUP", conflicts_with_all = ["fold_hook_changes", "verify_cmd"])]
+    pub each: Option<Each>,
+
+    /// Make one commit per set of CODEOWNERS owners of the changes to
+    /// commit, with an `Owners:` trailer; `{owners}` in the -m message is
+    /// replaced by them. The branch is only updated once all are made
+    #[arg(long, conflicts_with_all = ["each", "fold_hook_changes", "verify_cmd"])]
+    pub split_by_owners: bool,
+
+    /// CODEOWNERS file for --split-by-owners [default: .github/CODEOWNERS,
+    /// CODEOWNERS, docs/CODEOWNERS or .gitlab/CODEOWNERS]
+    #[arg(long, value_name = "FILE", requires = "split_by_owners")]
+    pub codeowners: Option<PathBuf>,
+
+    /// When HEAD moved off the expected base (--expect-head, or the plan's),
+    /// re-apply the changes on top of HEAD, merging paths changed on both
+    /// sides; abort on conflicts
+    #[arg(long, conflicts_with = "rebase_plan")]
+    pub auto_rebase: bool,
+
+    /// Commit content from this index file instead of the repository's index
+    #[arg(long, value_name = "FILE", conflicts_with = "from_tree")]
+    pub from_index: Option<PathBuf>,
+
+    /// Commit paths exactly as they exist in this commit, tag, stash or tree
+    #[arg(long, value_name = "REV")]
+    pub from_tree: Option<String>,
+
+    /// Wait for a held index lock: indefinitely, or up to SECONDS
+    /// [default: config commitStaged.wait]
+    #[arg(long, value_name = "SECONDS", num_args = 0..=1, require_equals = true)]
+    #[allow(clippy::option_option)]
+    pub wait: Option<Option<u64>>,
+
+    /// While waiting, queue for the index lock in FIFO order
+    /// [default: config commitStaged.queue]
+    #[arg(long)]
+    pub queue: bool,
+
+    /// Commit with libgit2 instead of running `git commit`. Supports -m, -F,
+    /// --amend, --no-verify, --trailer and -S after --; runs the pre-commit,
+    /// commit-msg and post-commit hooks
+    #[arg(long)]
+    pub plumbing: bool,
+
+    /// Fold index changes made by commit hooks (e.g. `git add` from an
+    /// autofixer) to the committed paths into the main index. Changes to
+    /// other paths abort with --plumbing, and are warned about otherwise
+    #[arg(long)]
+    pub fold_hook_changes: bool,
+
+    /// Check out the exact tree to commit, as the commit hooks leave it, into
+    /// a scratch directory and run CMD there (with `sh -c`); commit only if
+    /// it exits 0. Requires --plumbing, which can stop between the hooks and
+    /// the branch update
+    #[arg(long, value_name = "CMD", requires = "plumbing")]
+    pub verify_cmd: Option<String>,
+
+    /// Keep the --verify-cmd scratch directory, for debugging
+    #[arg(long, requires = "verify_cmd")]
+    pub keep_scratch: bool,
+
+    /// Fingerprint the main index and working tree first, and fail if
+    /// anything but the committed paths' index entries changed
+    #[arg(long)]
+    pub paranoid: bool,
+
+    /// Abort unless HEAD is this commit (full OID)
+    #[arg(long, value_name = "OID")]
+    pub expect_head: Option<String>,
+
+    /// Abort unless the content to commit at PATH (repo-relative) is this
+    /// blob, with this octal mode if given; the all-zeros OID expects a
+    /// deletion. Repeatable
+    #[arg(long, value_name = "PATH=[MODE,]OID")]
+    pub expect_blob: Vec<String>,
+
+    /// Abort unless exactly the entries in this `--json` dry-run report
+    /// would be committed
+    #[arg(long, value_name = "FILE")]
+    pub expect_plan: Option<PathBuf>,
+
+    /// Print results as JSON
+    #[arg(long, conflicts_with = "porcelain")]
+    pub json: bool,
+
+    /// Print results in a stable, line-oriented format
+    #[arg(
+        long,
+        value_name = "VERSION",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "v1"
+    )]
+    pub porcelain: Option<PorcelainVersion>,
+
+    /// With --porcelain, terminate records with NUL instead of newline
+    #[arg(short = 'z', requires = "porcelain")]
+    pub nul: bool,
+
     /// Arguments to pass through to git commit
     #[arg(last = true)]
     #[allow(clippy::struct_field_names)]
     pub passthrough_args: Vec<String>,
 }
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Show staged changes grouped by the staging area that claims them
+    Status {
+        /// Limit to these paths
+        paths: Vec<PathBuf>,
+
+        /// Output as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Remove an index.lock (and temp indexes) left behind by a killed git-commit-staged
+    Unlock {
+        /// Only remove the lock if it is ours and its owner is provably dead
+        #[arg(long, required = true)]
+        stale: bool,
+    },
+
+    /// Take back the last N journaled commits: move the branch back (or
+    /// commit a revert if it moved on) and restore the committed paths'
+    /// index entries
+    Undo {
+        /// How many commits to undo
+        #[arg(default_value_t = 1)]
+        count: usize,
+    },
+
+    /// Find files left behind by killed runs, and repository settings this
+    /// tool can't handle safely (in all worktrees)
+    Doctor {
+        /// Remove leftover files whose owner is provably gone
+        #[arg(long)]
+        fix: bool,
+
+        /// Output as JSON
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+/// Versions of the `--porcelain` format.
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum PorcelainVersion {
+    V1,
+}
@@ -1,40 +1,391 @@
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 use clap::{CommandFactory, FromArgMatches};
-use std::path::Path;
+use git2::Repository;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 
 mod cli;
-use cli::Args;
-use git_commit_staged::commit::do_commit;
-use git_commit_staged::exec::print_dry_run;
+use cli::{Args, Command};
+use git_commit_staged::cleanup;
+use git_commit_staged::codeowners::CodeOwners;
+use git_commit_staged::commit::{commit_temp_index, TreeChecks};
+use git_commit_staged::doctor;
+use git_commit_staged::exec::{
+    print_commit, print_doctor, print_dry_run, print_series_dry_run, print_status, print_undone,
+    print_warnings, report_error, resolve_cwd_paths, unstaged_warnings,
+};
+use git_commit_staged::expect::Expectations;
+use git_commit_staged::hook_changes;
+use git_commit_staged::index::write_temp_index;
+use git_commit_staged::journal;
 use git_commit_staged::lock::IndexLock;
-use git_commit_staged::prepare_staged_commit;
+use git_commit_staged::lock_owner::remove_stale_lock;
+use git_commit_staged::output::{self, Format, Report};
+use git_commit_staged::paranoid::Snapshot;
+use git_commit_staged::plan::Plan;
+use git_commit_staged::plumbing::CommitOptions;
+use git_commit_staged::rebase;
+use git_commit_staged::series::{self, Each, SeriesCommit};
+use git_commit_staged::status;
+use git_commit_staged::{prepare_staged_commit_from, Error, Source, StagedEntry};
 
 const VERSION: &str = concat!(env!("CARGO_PKG_VERSION"), " (", env!("GIT_HASH"), ")");
 
-fn main() -> Result<()> {
-    let args = Args::from_arg_matches(&Args::command().version(VERSION).get_matches())?;
+fn main() -> ExitCode {
+    let matches = Args::command().version(VERSION).get_matches();
+    reject_subcommand_passthrough(&matches);
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    let format = Format::from_flags(args.json, args.porcelain.is_some(), args.nul);
+    let dry_run = args.dry_run;
+
+    let code = cleanup::install_signal_handlers()
+        .and_then(|()| run(args, format))
+        .map_or_else(
+            |err| {
+                Report::failure(dry_run, &err).print(format);
+                report_error(&err)
+            },
+            |()| ExitCode::SUCCESS,
+        );
+    // Anything a failed run left behind
+    cleanup::remove_all();
+    code
+}
+
+/// Exit with a usage error if a subcommand name is followed by `--`.
+///
+/// `status -- -m msg` almost certainly meant to commit a path named
+/// `status`, but parses as the `status` subcommand and would commit nothing.
+fn reject_subcommand_passthrough(matches: &clap::ArgMatches) {
+    let Some(name) = matches.subcommand_name() else {
+        return;
+    };
+    if std::env::args_os().skip(1).any(|arg| arg == "--") {
+        Args::command()
+            .error(
+                clap::error::ErrorKind::ArgumentConflict,
+                format!(
+                    "`{name}` is a subcommand, which takes no `--` arguments; \
+                     to commit a path named {name}, write it as ./{name}"
+                ),
+            )
+            .exit();
+    }
+}
+
+fn run(args: Args, format: Format) -> Result<()> {
+    match args.command {
+        Some(Command::Status { paths, json }) => return status(&paths, json),
+        Some(Command::Unlock { stale: _ }) => return unlock(),
+        Some(Command::Doctor { fix, json }) => return doctor(fix, json),
+        Some(Command::Undo { count }) => return undo(count),
+        None => {}
+    }
+
+    let expectations = Expectations::from_args(
+        args.expect_head.as_deref(),
+        &args.expect_blob,
+        args.expect_plan.as_deref(),
+    )?;
 
     // Acquire lock before reading any state (skip for dry-run)
     let _lock = if args.dry_run {
         None
     } else {
-        Some(IndexLock::acquire()?)
+        Some(IndexLock::acquire_waiting(args.wait, args.queue)?)
     };
 
-    let result = prepare_staged_commit(&args.paths, Path::new("."), args.dry_run)?;
+    let repo = Repository::open_from_env().context("failed to open repository")?;
+    let snapshot = args.paranoid.then(|| Snapshot::take(&repo)).transpose()?;
+
+    let snapshot = snapshot.as_ref();
+    if let Some(series) = &args.series {
+        let commits = series_commits(series)?;
+        expectations.check(&repo, &series_entries(&commits))?;
+        let options = CommitOptions::parse(&args.passthrough_args)?;
+        return commit_series(&args, format, &repo, &commits, &options, true, snapshot);
+    }
+
+    let (staged_entries, from_main_index) = entries_to_commit(&args, &repo, expectations)?;
+
+    if args.each.is_some() || args.split_by_owners {
+        let mut options = CommitOptions::parse(&args.passthrough_args)?;
+        let template = series::take_message_template(&mut options)?;
+        let commits = if let Some(each) = args.each {
+            series::split_each(&staged_entries, each, &template)
+        } else {
+            let owners = match &args.codeowners {
+                Some(path) => CodeOwners::read(path)?,
+                None => CodeOwners::find(&repo)?.1,
+            };
+            series::split_by_owners(&staged_entries, &owners, &template)
+        };
+        return commit_series(&args, format, &repo, &commits, &options, from_main_index, snapshot);
+    }
+
+    let mut report = Report::new(args.dry_run);
+    report.entries = output::entries(&repo, &staged_entries)?;
+    // Reported to scripts only; people see them in `git status`
+    if from_main_index && format != Format::Human {
+        report.warnings = unstaged_warnings(&repo, &staged_entries)?;
+    }
 
     if args.dry_run {
-        print_dry_run(&result.staged_entries);
+        if let Some(plan_out) = &args.plan_out {
+            Plan::new(&repo, &staged_entries)?.write(plan_out)?;
+        }
+        if format == Format::Human {
+            print_warnings(&report.warnings);
+            print_dry_run(&staged_entries);
+        }
+        if let Some(snapshot) = snapshot {
+            snapshot.check(&repo, &[], None)?;
+        }
+        report.print(format);
         return Ok(());
     }
 
-    let temp_index_path = result
-        .temp_index_path
-        .expect("non-dry-run should have temp index");
+    let index_before = journal::index_entries(&repo, &staged_entries)?;
+    let temp_index_path = write_temp_index(&repo, &staged_entries)?;
+    let intended = hook_changes::intended_tree(&repo, &temp_index_path)?;
 
     // Lock held throughout - do_commit expects caller to hold it
-    let output = do_commit(&temp_index_path, &args.passthrough_args)?;
-    println!("[commit-staged {}]", &output.commit_sha[..7]);
+    let checks = TreeChecks {
+        scope: args.fold_hook_changes.then_some((intended, staged_entries.as_slice())),
+        verify: args.verify_cmd.as_deref().map(|command| (command, args.keep_scratch)),
+    };
+    let passthrough = &args.passthrough_args;
+    let output = commit_temp_index(&repo, &temp_index_path, passthrough, args.plumbing, checks)?;
+
+    if args.fold_hook_changes {
+        let warnings = hook_changes::fold(&repo, intended, output.tree, &staged_entries)?;
+        report.warnings.extend(warnings);
+    }
+    let entries = &staged_entries;
+    if let Err(err) = journal::record(&repo, "commit-staged", &output, entries, &index_before) {
+        report.warnings.push(format!("failed to journal the commit: {err:#}"));
+    }
+
+    if let Some(snapshot) = snapshot {
+        snapshot.check(&repo, &staged_entries, Some(output.commit))?;
+    }
+
+    if format == Format::Human {
+        print_warnings(&report.warnings);
+        print_commit("commit-staged", &output);
+    }
+    report.commit = Some((&output).into());
+    report.print(format);
 
     Ok(())
 }
+
+/// The commits of a series file, from the staged changes at each group's
+/// paths.
+fn series_commits(path: &Path) -> Result<Vec<SeriesCommit>> {
+    series::read_series_file(path)?
+        .into_iter()
+        .enumerate()
+        .map(|(i, group)| {
+            // Relative to the cwd, like paths on the command line
+            let result =
+                prepare_staged_commit_from(&group.paths, Path::new("."), true, &Source::Index)?;
+            let paths: Vec<String> = group.paths.iter().map(|p| p.display().to_string()).collect();
+            Ok(SeriesCommit {
+                label: format!("commit {} ({})", i + 1, paths.join(" ")),
+                entries: result.staged_entries,
+                message: group.message,
+                trailers: Vec::new(),
+            })
+        })
+        .collect()
+}
+
+/// Every entry of a series.
+fn series_entries(commits: &[SeriesCommit]) -> Vec<StagedEntry> {
+    commits
+        .iter()
+        .flat_map(|commit| commit.entries.iter().cloned())
+        .collect()
+}
+
+/// Commit a series (`--series`, `--each`, `--split-by-owners`) and report it.
+fn commit_series(
+    args: &Args,
+    format: Format,
+    repo: &Repository,
+    commits: &[SeriesCommit],
+    options: &CommitOptions,
+    from_main_index: bool,
+    snapshot: Option<&Snapshot>,
+) -> Result<()> {
+    let all_entries = series_entries(commits);
+    let mut report = Report::new(args.dry_run);
+    report.entries = output::entries(repo, &all_entries)?;
+    // Reported to scripts only; people see them in `git status`
+    if from_main_index && format != Format::Human {
+        report.warnings = unstaged_warnings(repo, &all_entries)?;
+    }
+
+    if args.dry_run {
+        if let Some(plan_out) = &args.plan_out {
+            Plan::new(repo, &all_entries)?.write(plan_out)?;
+        }
+        if format == Format::Human {
+            print_warnings(&report.warnings);
+            print_series_dry_run(commits);
+        }
+        if let Some(snapshot) = snapshot {
+            snapshot.check(repo, &[], None)?;
+        }
+        report.print(format);
+        return Ok(());
+    }
+
+    let index_before = commits
+        .iter()
+        .map(|commit| journal::index_entries(repo, &commit.entries))
+        .collect::<Result<Vec<_>>>()?;
+    let outputs = series::commit_series(repo, commits, options)?;
+
+    for ((output, commit), before) in outputs.iter().zip(commits).zip(&index_before) {
+        if let Err(err) = journal::record(repo, "commit-staged", output, &commit.entries, before) {
+            report.warnings.push(format!("failed to journal the commit: {err:#}"));
+        }
+    }
+
+    let last = outputs.last().context("series made no commits")?;
+    if let Some(snapshot) = snapshot {
+        snapshot.check(repo, &all_entries, Some(last.commit))?;
+    }
+
+    if format == Format::Human {
+        print_warnings(&report.warnings);
+        for output in &outputs {
+            print_commit("commit-staged", output);
+        }
+    }
+    report.commit = Some(last.into());
+    report.series = outputs.iter().map(Into::into).collect();
+    report.print(format);
+
+    Ok(())
+}
+
+/// Entries to commit, and whether they come from the main index.
+///
+/// Reads the plan or staged changes, checks expectations, and rebases onto
+/// HEAD if asked to.
+fn entries_to_commit(
+    args: &Args,
+    repo: &Repository,
+    mut expectations: Expectations,
+) -> Result<(Vec<StagedEntry>, bool)> {
+    // With --auto-rebase, the expected HEAD is the base to rebase from
+    let mut base = if args.auto_rebase {
+        expectations.head.take()
+    } else {
+        None
+    };
+
+    let (mut staged_entries, from_main_index) = if let Some(plan) = &args.plan {
+        let plan = Plan::read(repo, plan)?;
+        if args.auto_rebase {
+            base = Some(plan.base);
+        } else {
+            plan.check_base(repo, args.rebase_plan)?;
+        }
+        if plan.entries.is_empty() {
+            return Err(Error::NoChanges("plan has no entries".to_owned()).into());
+        }
+        (plan.entries, false)
+    } else {
+        let source = match (args.from_index.clone(), args.from_tree.clone()) {
+            (Some(path), _) => Source::IndexFile(path),
+            (None, Some(rev)) => Source::Tree(rev),
+            (None, None) => Source::Index,
+        };
+        // The temp index is written only once expectations are checked
+        let result = prepare_staged_commit_from(&args.paths, Path::new("."), true, &source)?;
+        (result.staged_entries, matches!(source, Source::Index))
+    };
+
+    if args.auto_rebase && base.is_none() {
+        bail!("--auto-rebase needs a base: --expect-head or --plan");
+    }
+
+    if let Some(base) = base {
+        staged_entries = rebase::changes_since(repo, base, &staged_entries)?;
+        expectations.check(repo, &staged_entries)?;
+        staged_entries = rebase::rebase_entries(repo, base, &staged_entries)?;
+        if staged_entries.is_empty() {
+            let message = "no changes left after rebasing onto HEAD".to_owned();
+            return Err(Error::NoChanges(message).into());
+        }
+    } else {
+        expectations.check(repo, &staged_entries)?;
+    }
+
+    Ok((staged_entries, from_main_index))
+}
+
+fn status(paths: &[PathBuf], json: bool) -> Result<()> {
+    let repo = Repository::open_from_env().context("failed to open repository")?;
+
+    let paths = if paths.is_empty() {
+        vec![PathBuf::new()]
+    } else {
+        resolve_cwd_paths(&repo, paths)?
+    };
+    let status = status::collect(&repo, &paths)?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&status)?);
+    } else {
+        print_status(&status);
+    }
+    Ok(())
+}
+
+fn unlock() -> Result<()> {
+    let repo = Repository::open_from_env().context("failed to open repository")?;
+    let lock_path = repo.path().join("index.lock");
+
+    let (owner, reason) = remove_stale_lock(&lock_path)?;
+    println!("Removed stale {} ({reason})", lock_path.display());
+    println!("  was held by {owner}");
+    // Otherwise nothing could ever prove them orphaned (see `doctor`)
+    for path in doctor::remove_temp_indexes(repo.path(), owner.pid)? {
+        println!("Removed its temp index {}", path.display());
+    }
+    Ok(())
+}
+
+fn undo(count: usize) -> Result<()> {
+    let _lock = IndexLock::acquire_waiting(None, false)?;
+    let repo = Repository::open_from_env().context("failed to open repository")?;
+
+    // Checked up front, so a short journal or a commit that can't be undone
+    // undoes nothing
+    let records = journal::undoable(&repo, count)?;
+    journal::check_undo(&repo, &records)?;
+    for record in &records {
+        print_undone(&journal::undo(&repo, record)?);
+    }
+    Ok(())
+}
+
+fn doctor(fix: bool, json: bool) -> Result<()> {
+    let repo = Repository::open_from_env().context("failed to open repository")?;
+
+    let mut report = doctor::diagnose(&repo)?;
+    // Report what was removed even if some orphans couldn't be
+    let fixed = if fix { doctor::fix(&mut report) } else { Ok(()) };
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print_doctor(&report, fix);
+    }
+    fixed
+}
@@ -0,0 +1,279 @@
+//! Named persistent staging areas.
+//!
+//! A staging area is an alternative index file at `.git/staging/<name>`,
+//! holding its base commit's tree plus whatever was added to it. Each agent
+//! can `git add` into its own area instead of sharing `.git/index`.
+//!
+//! The base commit is recorded in `.git/staging/.bases/<name>`, so an area
+//! can be re-based (its changes merged into the new HEAD) when HEAD moves.
+//! Areas are only read and written with `.git/index.lock` held, as commits
+//! re-base them.
+
+use anyhow::{bail, Context, Result};
+use git2::{Index, IndexAddOption, Oid, Repository, Tree};
+use std::path::{Path, PathBuf};
+
+use crate::error::Error;
+use crate::exec::{find_staged_in_index, resolve_cwd_paths};
+use crate::index::{replace_main_index, unstatted_entry, write_index};
+use crate::rebase::rebase_entries_onto;
+use crate::{head_commit, StagedEntry};
+
+/// A named staging area (may not exist yet).
+#[derive(Debug, Clone)]
+pub struct StageArea {
+    /// Area name
+    pub name: String,
+    /// Path to the area's index file
+    pub index_path: PathBuf,
+    base_path: PathBuf,
+}
+
+impl StageArea {
+    /// Refer to the area `name` in `repo`. Does not create it.
+    ///
+    /// # Errors
+    /// Returns an error if the name is empty, hidden, or contains a path separator.
+    pub fn open(repo: &Repository, name: &str) -> Result<Self> {
+        if name.is_empty() || name.starts_with('.') || name.contains('/') {
+            bail!("invalid staging area name {name:?}");
+        }
+
+        let dir = staging_dir(repo);
+        Ok(Self {
+            name: name.to_owned(),
+            index_path: dir.join(name),
+            base_path: dir.join(".bases").join(name),
+        })
+    }
+
+    /// Refer to an existing area.
+    ///
+    /// # Errors
+    /// Returns an error if the name is invalid or the area does not exist.
+    pub fn open_existing(repo: &Repository, name: &str) -> Result<Self> {
+        let area = Self::open(repo, name)?;
+        if !area.exists() {
+            bail!("no staging area named {name:?}");
+        }
+        Ok(area)
+    }
+
+    /// All existing areas, sorted by name.
+    ///
+    /// # Errors
+    /// Returns an error if the staging directory cannot be read.
+    pub fn list(repo: &Repository) -> Result<Vec<Self>> {
+        let dir = staging_dir(repo);
+        if !dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut areas = Vec::new();
+        for entry in std::fs::read_dir(&dir).context("failed to read staging directory")? {
+            let entry = entry.context("failed to read staging directory entry")?;
+            let name = entry.file_name();
+            let Some(name) = name.to_str() else { continue };
+            let is_lock = Path::new(name).extension().is_some_and(|ext| ext == "lock");
+            if !entry.file_type()?.is_file() || name.starts_with('.') || is_lock {
+                continue;
+            }
+            areas.push(Self::open(repo, name)?);
+        }
+        areas.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(areas)
+    }
+
+    /// Whether the area's index file exists.
+    #[must_use]
+    pub fn exists(&self) -> bool {
+        self.index_path.exists()
+    }
+
+    /// The commit this area's changes are relative to.
+    ///
+    /// # Errors
+    /// Returns an error if the base file cannot be read or parsed.
+    pub fn base(&self) -> Result<Oid> {
+        let text = std::fs::read_to_string(&self.base_path)
+            .with_context(|| format!("failed to read base of staging area {}", self.name))?;
+        Oid::from_str(text.trim())
+            .with_context(|| format!("corrupt base of staging area {}", self.name))
+    }
+
+    /// Create the area if needed, and re-base it if HEAD has moved.
+    ///
+    /// **Caller must hold index.lock.**
+    ///
+    /// # Errors
+    /// Returns [`Error::Conflict`] if the area's changes conflict with HEAD's,
+    /// or an error if HEAD cannot be resolved or the area cannot be written.
+    pub fn ensure_current(&self, repo: &Repository) -> Result<()> {
+        let head = head_commit(repo)?.id();
+
+        if !self.exists() {
+            let tree = repo.find_commit(head)?.tree()?;
+            std::fs::create_dir_all(staging_dir(repo).join(".bases"))
+                .context("failed to create staging directory")?;
+            write_index(&self.index_path, &tree, &[])?;
+            return self.set_base(head);
+        }
+
+        if self.base()? != head {
+            self.rebase(repo, head)?;
+        }
+        Ok(())
+    }
+
+    /// Stage working tree content at `paths` into this area.
+    ///
+    /// Paths are relative to the current directory, as for `git add`.
+    ///
+    /// **Caller must hold index.lock.**
+    ///
+    /// # Errors
+    /// Returns an error if paths are outside the repository or staging fails.
+    pub fn add<P: AsRef<Path>>(&self, repo: &Repository, paths: &[P]) -> Result<()> {
+        self.ensure_current(repo)?;
+
+        let repo_relative_paths = resolve_cwd_paths(repo, paths)?;
+
+        let mut index = self.read_index()?;
+        // Attach to repo so add_all/update_all can see the working tree
+        repo.set_index(&mut index)
+            .context("failed to attach staging area to repository")?;
+
+        index
+            .update_all(&repo_relative_paths, None)
+            .context("failed to update staging area from working tree")?;
+        index
+            .add_all(&repo_relative_paths, IndexAddOption::DEFAULT, None)
+            .context("failed to add paths to staging area")?;
+        index.write().context("failed to write staging area")
+    }
+
+    /// Entries in this area that differ from its base.
+    ///
+    /// # Errors
+    /// Returns an error if the area or its base cannot be read.
+    pub fn staged_entries(&self, repo: &Repository) -> Result<Vec<StagedEntry>> {
+        let base_tree = repo.find_commit(self.base()?)?.tree()?;
+        let index = self.read_index()?;
+        find_staged_in_index(repo, &index, &base_tree, &[PathBuf::new()])
+    }
+
+    /// Re-apply this area's changes on top of `onto`.
+    ///
+    /// The area's changes are merged into `onto` as for `--auto-rebase`:
+    /// paths `onto` also changed get a three-way merge, and the area is left
+    /// as it was if they conflict.
+    ///
+    /// **Caller must hold index.lock.**
+    ///
+    /// # Errors
+    /// Returns [`Error::Conflict`] if the changes conflict, or an error if the
+    /// commits cannot be read or the area cannot be written.
+    pub fn rebase(&self, repo: &Repository, onto: Oid) -> Result<()> {
+        let entries = self.staged_entries(repo)?;
+        let onto = repo.find_commit(onto)?;
+        let entries = rebase_entries_onto(repo, self.base()?, &onto, &entries)
+            .with_context(|| format!("failed to re-base staging area {}", self.name))?;
+        write_index(&self.index_path, &onto.tree()?, &entries)?;
+        self.set_base(onto.id())
+    }
+
+    /// Delete the area.
+    ///
+    /// # Errors
+    /// Returns an error if the area files exist but cannot be removed.
+    pub fn remove(&self) -> Result<()> {
+        for path in [&self.index_path, &self.base_path] {
+            if path.exists() {
+                std::fs::remove_file(path)
+                    .with_context(|| format!("failed to remove {}", path.display()))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn read_index(&self) -> Result<Index> {
+        Index::open(&self.index_path)
+            .with_context(|| format!("failed to read staging area {}", self.name))
+    }
+
+    fn set_base(&self, oid: Oid) -> Result<()> {
+        std::fs::write(&self.base_path, format!("{oid}\n"))
+            .with_context(|| format!("failed to record base of staging area {}", self.name))
+    }
+}
+
+/// Re-base every area whose base is not the current HEAD.
+///
+/// Areas whose changes conflict with HEAD's are left on their old base, and
+/// returned as warnings; using them reports the conflict again.
+///
+/// **Caller must hold index.lock.**
+///
+/// # Errors
+/// Returns an error if any area cannot be re-based for another reason.
+pub fn refresh_all(repo: &Repository) -> Result<Vec<String>> {
+    let mut warnings = Vec::new();
+    for area in StageArea::list(repo)? {
+        match area.ensure_current(repo) {
+            Ok(()) => {}
+            Err(err) if matches!(Error::find(&err), Some(Error::Conflict { .. })) => {
+                warnings.push(format!("{err:#}; it stays on its old base"));
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(warnings)
+}
+
+/// Bring the main index up to date with a commit made from a staging area
+/// (or with changes commit hooks made).
+///
+/// For each committed path whose main index entry still matches `old_tree`
+/// (nothing staged there), the entry is replaced with the committed content.
+/// Paths with other staged changes in the main index are left alone.
+///
+/// **Caller must hold index.lock.**
+///
+/// # Errors
+/// Returns an error if the main index cannot be read or replaced.
+pub fn sync_main_index(repo: &Repository, old_tree: &Tree, entries: &[StagedEntry]) -> Result<()> {
+    let mut index = repo.index().context("failed to read index")?;
+    // The repository may have loaded it before the commit
+    index.read(false).context("failed to reload index")?;
+    let mut changed = false;
+
+    for (path, data) in entries {
+        let current = index.get_path(Path::new(path), 0);
+        let old = old_tree.get_path(Path::new(path)).ok();
+
+        let untouched = match (&current, &old) {
+            (Some(cur), Some(old)) => cur.id == old.id() && cur.mode == old.filemode().cast_unsigned(),
+            (None, None) => true,
+            _ => false,
+        };
+        if !untouched {
+            continue;
+        }
+
+        match data {
+            Some((oid, mode)) => index.add(&unstatted_entry(path, *oid, *mode))?,
+            None => index.remove(Path::new(path), 0)?,
+        }
+        changed = true;
+    }
+
+    if !changed {
+        return Ok(());
+    }
+
+    replace_main_index(repo, &index)
+}
+
+fn staging_dir(repo: &Repository) -> PathBuf {
+    repo.path().join("staging")
+}
@@ -0,0 +1,220 @@
+//! Staged/unstaged status of the main index, grouped by claimant.
+//!
+//! Agents stage privately into named staging areas (see [`crate::staging`]).
+//! A path staged in the main index is attributed to every area that holds
+//! the same content for it; staged paths no area accounts for are
+//! unattributed.
+
+use anyhow::{Context, Result};
+use git2::{Delta, Diff, Index, Oid, Repository};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+use crate::head_tree;
+use crate::staging::StageArea;
+
+/// Status of the main index relative to HEAD and the working tree.
+#[derive(Debug, Default, Serialize)]
+pub struct Status {
+    /// Staged changes, keyed by the staging area that claims them
+    pub claimed: BTreeMap<String, Vec<Change>>,
+    /// Staged changes no staging area accounts for
+    pub unattributed: Vec<Change>,
+    /// Paths where the working tree differs from the index
+    pub unstaged: Vec<Change>,
+    /// Staged paths that also differ in the working tree
+    /// (`git commit-files` would overwrite the staged version)
+    pub staged_and_unstaged: Vec<String>,
+    /// Paths staged with different content by more than one claimant
+    pub conflicts: Vec<Conflict>,
+}
+
+/// A changed path.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Change {
+    /// Repo-relative path
+    pub path: String,
+    /// Kind of change
+    pub status: ChangeStatus,
+}
+
+/// Kind of change at a path, as in `git status --short`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeStatus {
+    Added,
+    Modified,
+    Deleted,
+    Typechange,
+}
+
+impl ChangeStatus {
+    /// One-letter code, as in `git status --short`.
+    #[must_use]
+    pub const fn letter(self) -> char {
+        match self {
+            Self::Added => 'A',
+            Self::Modified => 'M',
+            Self::Deleted => 'D',
+            Self::Typechange => 'T',
+        }
+    }
+
+    const fn from_delta(delta: Delta) -> Self {
+        match delta {
+            Delta::Added | Delta::Copied => Self::Added,
+            Delta::Deleted => Self::Deleted,
+            Delta::Typechange => Self::Typechange,
+            _ => Self::Modified,
+        }
+    }
+}
+
+/// A path staged with different content by several claimants.
+#[derive(Debug, Clone, Serialize)]
+pub struct Conflict {
+    /// Repo-relative path
+    pub path: String,
+    /// Staging areas (and `:index` for the main index) holding differing content
+    pub claimants: Vec<String>,
+}
+
+/// Pseudo-claimant naming the main index in [`Conflict::claimants`].
+pub const MAIN_INDEX: &str = ":index";
+
+/// Collect status for paths under any of `paths` (repo-relative prefixes;
+/// an empty path matches everything).
+///
+/// # Errors
+/// Returns an error if HEAD, the index or a staging area cannot be read.
+pub fn collect<P: AsRef<Path>>(repo: &Repository, paths: &[P]) -> Result<Status> {
+    let matches = |p: &Path| paths.iter().any(|t| p.starts_with(t.as_ref()));
+
+    let index = repo.index().context("failed to get index")?;
+    let (staged, unstaged) = index_changes(repo, &index, &matches)?;
+    let staged_and_unstaged = intersect(&staged, &unstaged);
+
+    // path -> claimant -> staged content (None = deletion)
+    let mut claims: BTreeMap<String, BTreeMap<String, Option<Oid>>> = BTreeMap::new();
+    for area in StageArea::list(repo)? {
+        for (path, data) in area.staged_entries(repo)? {
+            if matches(Path::new(&path)) {
+                let oid = data.map(|(oid, _)| oid);
+                claims.entry(path).or_default().insert(area.name.clone(), oid);
+            }
+        }
+    }
+
+    let mut status = Status {
+        unstaged,
+        staged_and_unstaged,
+        ..Status::default()
+    };
+
+    for change in staged {
+        let index_oid = staged_oid(&index, &change.path);
+        let claimants: Vec<String> = claims
+            .get(&change.path)
+            .into_iter()
+            .flatten()
+            .filter(|(_, oid)| **oid == index_oid)
+            .map(|(area, _)| area.clone())
+            .collect();
+
+        if claimants.is_empty() {
+            status.unattributed.push(change.clone());
+        }
+        for area in claimants {
+            status.claimed.entry(area).or_default().push(change.clone());
+        }
+
+        claims
+            .entry(change.path)
+            .or_default()
+            .insert(MAIN_INDEX.to_owned(), index_oid);
+    }
+
+    for (path, by_claimant) in claims {
+        let distinct: BTreeSet<_> = by_claimant.values().collect();
+        if distinct.len() > 1 {
+            status.conflicts.push(Conflict {
+                path,
+                claimants: by_claimant.into_keys().collect(),
+            });
+        }
+    }
+
+    Ok(status)
+}
+
+/// Staged paths that also differ in the working tree, under `paths`.
+///
+/// # Errors
+/// Returns an error if HEAD or the index cannot be read.
+pub fn staged_and_unstaged<P: AsRef<Path>>(
+    repo: &Repository,
+    paths: &[P],
+) -> Result<Vec<String>> {
+    let matches = |p: &Path| paths.iter().any(|t| p.starts_with(t.as_ref()));
+
+    let index = repo.index().context("failed to get index")?;
+    let (staged, unstaged) = index_changes(repo, &index, &matches)?;
+    Ok(intersect(&staged, &unstaged))
+}
+
+/// Changes HEAD -> index (staged) and index -> working tree (unstaged).
+fn index_changes(
+    repo: &Repository,
+    index: &Index,
+    matches: &impl Fn(&Path) -> bool,
+) -> Result<(Vec<Change>, Vec<Change>)> {
+    let head = head_tree(repo)?;
+
+    let staged = changes(
+        &repo
+            .diff_tree_to_index(Some(&head), Some(index), None)
+            .context("failed to diff HEAD to index")?,
+        matches,
+    )?;
+    let unstaged = changes(
+        &repo
+            .diff_index_to_workdir(Some(index), None)
+            .context("failed to diff index to working tree")?,
+        matches,
+    )?;
+    Ok((staged, unstaged))
+}
+
+fn intersect(staged: &[Change], unstaged: &[Change]) -> Vec<String> {
+    let unstaged: BTreeSet<&str> = unstaged.iter().map(|c| c.path.as_str()).collect();
+    staged
+        .iter()
+        .filter(|c| unstaged.contains(c.path.as_str()))
+        .map(|c| c.path.clone())
+        .collect()
+}
+
+/// Changes at matching paths. Paths out of scope are never looked at, so
+/// they need not be valid UTF-8.
+fn changes(diff: &Diff, matches: &impl Fn(&Path) -> bool) -> Result<Vec<Change>> {
+    let mut changes = Vec::new();
+    for delta in diff.deltas() {
+        let path = delta.new_file().path().context("diff delta has no path")?;
+        if !matches(path) {
+            continue;
+        }
+        let path = path
+            .to_str()
+            .with_context(|| format!("path is not valid UTF-8: {}", path.display()))?;
+        changes.push(Change {
+            path: path.to_owned(),
+            status: ChangeStatus::from_delta(delta.status()),
+        });
+    }
+    Ok(changes)
+}
+
+fn staged_oid(index: &Index, path: &str) -> Option<Oid> {
+    index.get_path(Path::new(path), 0).map(|entry| entry.id)
+}
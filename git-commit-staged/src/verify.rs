@@ -0,0 +1,86 @@
+//! Verifying the exact tree to commit (`--verify-cmd`).
+//!
+//! A path-scoped commit's tree never exists on disk: the working copy has
+//! other changes. The tree is exported to a fresh scratch directory and the
+//! command runs there (`sh -c`, output on stderr); only if it exits 0 is the
+//! commit made. The scratch directory is removed afterwards unless kept
+//! for debugging.
+//!
+//! Commit hooks may still change the tree (`git add` from an autofixer), so
+//! it is verified after they have run, right before the branch moves. Only
+//! `--plumbing` can stop there: `git commit` runs its hooks and moves the
+//! branch in one go, so `--verify-cmd` requires `--plumbing`.
+
+use anyhow::{Context, Result};
+use git2::build::CheckoutBuilder;
+use git2::{Oid, Repository};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use crate::cleanup;
+use crate::error::Error;
+
+/// Run `command` on a checkout of `tree`.
+///
+/// With `keep`, the scratch directory is left in place and its path
+/// printed on stderr.
+///
+/// # Errors
+/// Returns [`Error::VerifyFailed`] if the command fails, or an error if the
+/// checkout cannot be written or the command cannot be run.
+pub fn verify(repo: &Repository, tree: Oid, command: &str, keep: bool) -> Result<()> {
+    let scratch = tempfile::Builder::new()
+        .prefix("git-commit-staged-verify.")
+        .tempdir()
+        .context("failed to create scratch directory")?
+        .keep();
+    cleanup::register(&scratch);
+    let result = export(repo, tree, &scratch).and_then(|()| run(command, &scratch));
+
+    if keep {
+        cleanup::forget(&scratch);
+        eprintln!("Scratch checkout kept at {}", scratch.display());
+    } else {
+        let _ = cleanup::remove(&scratch);
+    }
+    result
+}
+
+/// Check out every entry of `tree` into `dir`.
+fn export(repo: &Repository, tree: Oid, dir: &Path) -> Result<()> {
+    let tree = repo.find_tree(tree).context("failed to read the tree to commit")?;
+    let mut checkout = CheckoutBuilder::new();
+    checkout
+        .target_dir(dir)
+        .force()
+        .recreate_missing(true)
+        .update_index(false);
+    repo.checkout_tree(tree.as_object(), Some(&mut checkout))
+        .with_context(|| format!("failed to check out the tree to commit into {}", dir.display()))
+}
+
+fn run(command: &str, dir: &Path) -> Result<()> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .current_dir(dir)
+        .stdin(Stdio::null())
+        // Keep stdout for --json / --porcelain
+        .stdout(std::io::stderr())
+        .spawn()
+        .with_context(|| format!("failed to run verify command: {command}"))?;
+    let tracked = cleanup::track_child(child.id());
+    let status = child.wait();
+    drop(tracked);
+    let status = status.context("failed to wait for verify command")?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(Error::VerifyFailed {
+            command: command.to_owned(),
+            status: status.code(),
+        }
+        .into())
+    }
+}
@@ -14,7 +14,7 @@ use std::process::Command;
 
 use tempfile::TempDir;
 
-use git_commit_staged::git_commit_staged;
+use git_commit_staged::{git_commit_staged, prepare_staged_commit_from, Source};
 
 /// Helper to run git commands in a directory
 fn git(dir: &Path, args: &[&str]) -> String {
@@ -508,3 +508,67 @@ fn committed_files_unstaged() {
         "uncommitted file should still be staged: {status_after}"
     );
 }
+
+// =============================================================================
+// Alternate source tests
+// =============================================================================
+
+#[test]
+fn prepares_from_tree_including_deletions() {
+    let tmp = setup_repo();
+    let dir = tmp.path();
+
+    // Branch that adds one file and deletes README.md
+    git(dir, &["checkout", "-b", "feature"]);
+    fs::write(dir.join("feature.txt"), "feature\n").unwrap();
+    git(dir, &["add", "feature.txt"]);
+    git(dir, &["rm", "-q", "README.md"]);
+    git(dir, &["commit", "-m", "Feature work"]);
+    git(dir, &["checkout", "-q", "main"]);
+
+    let source = Source::Tree("feature".to_string());
+    let result = prepare_staged_commit_from(&[PathBuf::from("")], dir, true, &source)
+        .expect("prepare should succeed");
+
+    let mut paths: Vec<_> = result
+        .staged_entries
+        .iter()
+        .map(|(path, data)| (path.as_str(), data.is_some()))
+        .collect();
+    paths.sort_unstable();
+    assert_eq!(paths, [("README.md", false), ("feature.txt", true)]);
+}
+
+#[test]
+fn prepares_from_other_index_file() {
+    let tmp = setup_repo();
+    let dir = tmp.path();
+
+    // Stage into a private index, leaving the main index untouched
+    let other_index = dir.join(".git/other-index");
+    fs::write(dir.join("a.txt"), "a\n").unwrap();
+    let output = Command::new("git")
+        .args(["read-tree", "HEAD"])
+        .current_dir(dir)
+        .env("GIT_INDEX_FILE", &other_index)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let output = Command::new("git")
+        .args(["add", "a.txt"])
+        .current_dir(dir)
+        .env("GIT_INDEX_FILE", &other_index)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    // Main index has nothing staged
+    let result = prepare_staged_commit_from(&[PathBuf::from("")], dir, true, &Source::Index);
+    assert!(result.is_err(), "main index should have no staged changes");
+
+    let source = Source::IndexFile(other_index);
+    let result = prepare_staged_commit_from(&[PathBuf::from("")], dir, true, &source)
+        .expect("prepare should succeed");
+    assert_eq!(result.staged_entries.len(), 1);
+    assert_eq!(result.staged_entries[0].0, "a.txt");
+}
@@ -155,6 +155,26 @@ fn dry_run_does_not_commit() {
     assert!(status.contains("A  src/main.rs"));
 }
 
+#[test]
+fn path_named_like_subcommand() {
+    let tmp = setup_repo();
+    let dir = tmp.path();
+
+    fs::write(dir.join("status"), "status\n").unwrap();
+    git(dir, &["add", "status"]);
+
+    // Would parse as the status subcommand and commit nothing
+    let output = git_commit_staged(dir, &["status", "--", "-m", "Add status"]);
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("./status"), "{stderr}");
+    assert_eq!(git(dir, &["rev-list", "--count", "HEAD"]).trim(), "1");
+
+    let output = git_commit_staged(dir, &["./status", "--", "-m", "Add status"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(git(dir, &["show", "--name-only", "--format=", "HEAD"]).trim(), "status");
+}
+
 // =============================================================================
 // Passthrough argument tests
 // =============================================================================
@@ -197,6 +217,24 @@ fn amend_modifies_last_commit() {
     assert!(content.contains("println"));
 }
 
+#[test]
+fn git_commit_without_commit_is_an_error() {
+    let tmp = setup_repo();
+    let dir = tmp.path();
+
+    fs::write(dir.join("a.txt"), "a\n").unwrap();
+    git(dir, &["add", "a.txt"]);
+    let head_before = git(dir, &["rev-parse", "HEAD"]);
+
+    // git commit --dry-run exits 0 but commits nothing
+    let output = git_commit_staged(dir, &["a.txt", "--", "--dry-run", "-m", "Add a"]);
+    assert_eq!(output.status.code(), Some(8));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("made no commit"), "stderr: {stderr}");
+    assert_eq!(git(dir, &["rev-parse", "HEAD"]), head_before);
+    assert_eq!(git(dir, &["diff", "--cached", "--name-only"]).trim(), "a.txt");
+}
+
 #[test]
 fn fixup_creates_fixup_commit() {
     let tmp = setup_repo();
@@ -265,6 +303,1285 @@ fn reuse_message_with_c_flag() {
     assert_eq!(log.trim(), "Original message to reuse");
 }
 
+#[test]
+fn from_tree_takes_file_from_other_commit() {
+    let tmp = setup_repo();
+    let dir = tmp.path();
+
+    git(dir, &["checkout", "-q", "-b", "feature"]);
+    fs::write(dir.join("a.txt"), "feature a\n").unwrap();
+    fs::write(dir.join("b.txt"), "feature b\n").unwrap();
+    git(dir, &["add", "a.txt", "b.txt"]);
+    git(dir, &["commit", "-m", "Feature work"]);
+    git(dir, &["checkout", "-q", "main"]);
+
+    let output =
+        git_commit_staged(dir, &["--from-tree", "feature", "a.txt", "--", "-m", "Take a"]);
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let show = git(dir, &["show", "--name-only", "--format="]);
+    assert_eq!(show.trim(), "a.txt");
+    let content = git(dir, &["show", "HEAD:a.txt"]);
+    assert_eq!(content, "feature a\n");
+}
+
+#[test]
+fn from_index_and_from_tree_conflict() {
+    let tmp = setup_repo();
+    let dir = tmp.path();
+
+    let output = git_commit_staged(dir, &["--from-index", "x", "--from-tree", "HEAD", "."]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("cannot be used with"), "stderr: {stderr}");
+}
+
+// =============================================================================
+// Lock wait tests
+// =============================================================================
+
+#[test]
+fn wait_times_out_with_distinct_exit_code() {
+    let tmp = setup_repo();
+    let dir = tmp.path();
+
+    fs::write(dir.join("a.txt"), "a\n").unwrap();
+    git(dir, &["add", "a.txt"]);
+
+    // Simulate another git process holding the lock
+    let lock_path = dir.join(".git/index.lock");
+    fs::write(&lock_path, "").unwrap();
+
+    let output = git_commit_staged(dir, &["--wait=1", "--queue", "a.txt", "--", "-m", "Add a"]);
+    fs::remove_file(&lock_path).unwrap();
+
+    assert_eq!(output.status.code(), Some(75));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("waiting for index lock"), "stderr: {stderr}");
+    assert!(stderr.contains("timed out"), "stderr: {stderr}");
+}
+
+#[test]
+fn wait_default_from_config() {
+    let tmp = setup_repo();
+    let dir = tmp.path();
+
+    fs::write(dir.join("a.txt"), "a\n").unwrap();
+    git(dir, &["add", "a.txt"]);
+    git(dir, &["config", "commitStaged.wait", "1"]);
+
+    let lock_path = dir.join(".git/index.lock");
+    fs::write(&lock_path, "").unwrap();
+
+    let output = git_commit_staged(dir, &["a.txt", "--", "-m", "Add a"]);
+    fs::remove_file(&lock_path).unwrap();
+
+    assert_eq!(output.status.code(), Some(75));
+}
+
+#[test]
+fn unlock_stale_removes_lock_of_killed_process() {
+    let tmp = setup_repo();
+    let dir = tmp.path();
+
+    // A pre-commit hook that keeps the commit in flight
+    let hook = dir.join(".git/hooks/pre-commit");
+    fs::write(&hook, "#!/bin/sh\nsleep 2\n").unwrap();
+    fs::set_permissions(&hook, std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+
+    fs::write(dir.join("a.txt"), "a\n").unwrap();
+    git(dir, &["add", "a.txt"]);
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_git-commit-staged"))
+        .args(["a.txt", "--", "-m", "Add a"])
+        .current_dir(dir)
+        .spawn()
+        .unwrap();
+
+    let owner_path = dir.join(".git/index.lock.owner");
+    let temp_index = dir.join(format!(".git/index.commit-staged.{}", child.id()));
+    while !owner_path.exists() || !temp_index.exists() {
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    // The abandoned lock blocks other commits and names its owner
+    let output = git_commit_staged(dir, &["a.txt", "--", "-m", "Add a"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(&format!("pid {}", child.id())), "stderr: {stderr}");
+
+    let output = git_commit_staged(dir, &["unlock", "--stale"]);
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(!dir.join(".git/index.lock").exists());
+    assert!(!owner_path.exists());
+    // Its temp index too: with the lock gone, doctor couldn't prove it orphaned
+    assert!(!dir.join(format!(".git/index.commit-staged.{}", child.id())).exists());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Removed its temp index"), "{stdout}");
+}
+
+#[test]
+fn sigterm_removes_lock_and_temp_index() {
+    use std::os::unix::process::ExitStatusExt;
+
+    let tmp = setup_repo();
+    let dir = tmp.path();
+
+    // A pre-commit hook that keeps the commit in flight
+    let hook = dir.join(".git/hooks/pre-commit");
+    fs::write(&hook, "#!/bin/sh\nsleep 10\n").unwrap();
+    fs::set_permissions(&hook, std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+
+    fs::write(dir.join("a.txt"), "a\n").unwrap();
+    git(dir, &["add", "a.txt"]);
+    let head_before = git(dir, &["rev-parse", "HEAD"]);
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_git-commit-staged"))
+        .args(["a.txt", "--", "-m", "Add a"])
+        .current_dir(dir)
+        .spawn()
+        .unwrap();
+
+    // Wait until git commit is running on our temp index
+    let temp_index = dir.join(format!(".git/index.commit-staged.{}", child.id()));
+    while !temp_index.exists() {
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    std::thread::sleep(std::time::Duration::from_millis(200));
+
+    let pid = libc::pid_t::try_from(child.id()).unwrap();
+    // SAFETY: signalling our own child process.
+    unsafe { libc::kill(pid, libc::SIGTERM) };
+    let status = child.wait().unwrap();
+
+    // Killed by the re-raised signal, not a normal exit
+    assert_eq!(status.signal(), Some(libc::SIGTERM), "{status:?}");
+
+    let leftovers: Vec<_> = fs::read_dir(dir.join(".git"))
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|name| name.starts_with("index."))
+        .collect();
+    assert!(leftovers.is_empty(), "left behind: {leftovers:?}");
+    assert_eq!(git(dir, &["rev-parse", "HEAD"]), head_before);
+}
+
+#[test]
+fn unlock_stale_refuses_foreign_lock() {
+    let tmp = setup_repo();
+    let dir = tmp.path();
+
+    // Lock taken by something other than us: no owner record
+    let lock_path = dir.join(".git/index.lock");
+    fs::write(&lock_path, "").unwrap();
+
+    let output = git_commit_staged(dir, &["unlock", "--stale"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("no owner record"), "stderr: {stderr}");
+    assert!(lock_path.exists());
+}
+
+#[test]
+fn doctor_removes_orphans_with_fix() {
+    let tmp = setup_repo();
+    let dir = tmp.path();
+
+    // A run killed mid-commit leaves its lock and temp index behind
+    let hook = dir.join(".git/hooks/pre-commit");
+    fs::write(&hook, "#!/bin/sh\nsleep 10\n").unwrap();
+    fs::set_permissions(&hook, std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+    fs::write(dir.join("a.txt"), "a\n").unwrap();
+    git(dir, &["add", "a.txt"]);
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_git-commit-staged"))
+        .args(["a.txt", "--", "-m", "Add a"])
+        .current_dir(dir)
+        .spawn()
+        .unwrap();
+    let orphan = dir.join(format!(".git/index.commit-staged.{}", child.id()));
+    while !orphan.exists() || !dir.join(".git/index.lock.owner").exists() {
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    // Named after a PID not running here, but with nothing to prove it gone;
+    // and after a live one (this test)
+    let unknown = dir.join(format!(".git/index.commit-staged.{}", u32::MAX));
+    let live = dir.join(format!(".git/index.stage.{}", std::process::id()));
+    fs::write(&unknown, "").unwrap();
+    fs::write(&live, "").unwrap();
+
+    let output = git_commit_staged(dir, &["doctor"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("orphaned"), "stdout: {stdout}");
+    assert!(stdout.contains("unknown"), "stdout: {stdout}");
+    assert!(stdout.contains("in use"), "stdout: {stdout}");
+    assert!(orphan.exists(), "doctor without --fix must not delete");
+
+    let output = git_commit_staged(dir, &["doctor", "--fix", "--json"]);
+    assert!(output.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let artifacts = report["artifacts"].as_array().unwrap();
+    assert_eq!(artifacts.len(), 4, "{report}");
+    let state = |kind: &str, path: &Path| {
+        let artifact = artifacts
+            .iter()
+            .find(|a| a["kind"] == kind && a["path"] == path.to_str().unwrap())
+            .unwrap_or_else(|| panic!("no {kind} {}: {report}", path.display()));
+        (artifact["state"].as_str().unwrap(), artifact["removed"] == true)
+    };
+    assert_eq!(state("commit-index", &orphan), ("orphaned", true));
+    assert_eq!(state("lock", &dir.join(".git/index.lock")), ("orphaned", true));
+    assert_eq!(state("commit-index", &unknown), ("unknown", false));
+    assert_eq!(state("stage-index", &live), ("in-use", false));
+    assert!(!orphan.exists());
+    assert!(!dir.join(".git/index.lock").exists());
+    assert!(!dir.join(".git/index.lock.owner").exists());
+    assert!(unknown.exists());
+    assert!(live.exists());
+}
+
+#[test]
+fn doctor_never_removes_index_backups() {
+    let tmp = setup_repo();
+    let dir = tmp.path();
+
+    let backup = dir.join(format!(".git/index.backup.{}", u32::MAX));
+    fs::copy(dir.join(".git/index"), &backup).unwrap();
+
+    let output = git_commit_staged(dir, &["doctor", "--fix", "--json"]);
+    assert!(output.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let artifact = &report["artifacts"][0];
+    assert_eq!(artifact["kind"], "index-backup", "{report}");
+    assert_eq!(artifact["state"], "recoverable", "{report}");
+    assert_eq!(artifact["removed"], false, "{report}");
+    assert!(artifact["reason"].as_str().unwrap().contains("mv "), "{report}");
+    assert!(backup.exists());
+}
+
+#[test]
+fn doctor_flags_unsafe_setups() {
+    let tmp = setup_repo();
+    let dir = tmp.path();
+
+    let hook = dir.join(".git/hooks/pre-commit");
+    fs::write(&hook, "#!/bin/sh\ncargo fmt && git add -u\n").unwrap();
+    git(dir, &["config", "core.splitIndex", "true"]);
+
+    let output = git_commit_staged(dir, &["doctor", "--json"]);
+    assert!(output.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let kinds: Vec<&str> = report["warnings"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|w| w["kind"].as_str().unwrap())
+        .collect();
+    assert_eq!(kinds, ["split-index", "hook-runs-git-add"]);
+}
+
+// =============================================================================
+// Status tests
+// =============================================================================
+
+#[test]
+fn status_groups_staged_and_unstaged() {
+    let tmp = setup_repo();
+    let dir = tmp.path();
+
+    fs::write(dir.join("staged.txt"), "staged\n").unwrap();
+    fs::write(dir.join("both.txt"), "v1\n").unwrap();
+    git(dir, &["add", "staged.txt", "both.txt"]);
+    fs::write(dir.join("both.txt"), "v2\n").unwrap();
+    fs::write(dir.join("README.md"), "changed\n").unwrap();
+
+    let output = git_commit_staged(dir, &["status"]);
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(
+        stdout.trim(),
+        "Staged (unattributed):\n  A both.txt\n  A staged.txt\n\
+         Not staged:\n  M README.md\n  M both.txt\n\
+         Staged and modified since:\n  both.txt"
+    );
+}
+
+#[test]
+fn status_json_attributes_staging_areas() {
+    let tmp = setup_repo();
+    let dir = tmp.path();
+
+    // Area "agent" claims a.txt; the main index stages the same content
+    fs::write(dir.join("a.txt"), "a\n").unwrap();
+    git(dir, &["add", "a.txt"]);
+    let index_path = dir.join(".git/staging/agent");
+    fs::create_dir_all(dir.join(".git/staging/.bases")).unwrap();
+    fs::copy(dir.join(".git/index"), &index_path).unwrap();
+    let head = git(dir, &["rev-parse", "HEAD"]);
+    fs::write(dir.join(".git/staging/.bases/agent"), head).unwrap();
+
+    // ...and the main index stages b.txt, which the area holds differently
+    fs::write(dir.join("b.txt"), "index\n").unwrap();
+    git(dir, &["add", "b.txt"]);
+    fs::write(dir.join("b.txt"), "area\n").unwrap();
+    let output = Command::new("git")
+        .args(["add", "b.txt"])
+        .current_dir(dir)
+        .env("GIT_INDEX_FILE", &index_path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let output = git_commit_staged(dir, &["status", "--json"]);
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let status: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(
+        status["claimed"]["agent"],
+        serde_json::json!([{"path": "a.txt", "status": "added"}])
+    );
+    assert_eq!(
+        status["unattributed"],
+        serde_json::json!([{"path": "b.txt", "status": "added"}])
+    );
+    assert_eq!(
+        status["conflicts"],
+        serde_json::json!([{"path": "b.txt", "claimants": [":index", "agent"]}])
+    );
+}
+
+// =============================================================================
+// Session identity tests
+// =============================================================================
+
+/// Helper to run our binary inside an agent session
+fn git_commit_staged_in_session(
+    dir: &Path,
+    session: &str,
+    args: &[&str],
+) -> std::process::Output {
+    let binary = env!("CARGO_BIN_EXE_git-commit-staged");
+    let bin_dir = Path::new(binary).parent().unwrap();
+    let path = std::env::var("PATH").unwrap_or_default();
+    let new_path = format!("{}:{}", bin_dir.display(), path);
+
+    Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .arg("commit-staged")
+        .args(args)
+        .env("PATH", new_path)
+        .env("GIT_PARTIAL_SESSION", session)
+        .output()
+        .expect("failed to execute git commit-staged")
+}
+
+#[test]
+fn session_identity_applied_to_commit() {
+    let tmp = setup_repo();
+    let dir = tmp.path();
+
+    let session_dir = dir.join(".git/partial.d/abc-123");
+    fs::create_dir_all(&session_dir).unwrap();
+    fs::write(
+        session_dir.join("config"),
+        "[user]\n\tname = Agent One\n\temail = one@agents.test\n\
+         [session]\n\ttrailer = Generated-by: test-agent\n",
+    )
+    .unwrap();
+
+    fs::write(dir.join("file.txt"), "v1\n").unwrap();
+    git(dir, &["add", "file.txt"]);
+
+    let output =
+        git_commit_staged_in_session(dir, "abc-123", &["file.txt", "--", "-m", "Add file"]);
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let author = git(dir, &["log", "-1", "--format=%an <%ae>"]);
+    assert_eq!(author.trim(), "Agent One <one@agents.test>");
+
+    let committer = git(dir, &["log", "-1", "--format=%cn"]);
+    assert_eq!(committer.trim(), "Test User");
+
+    let message = git(dir, &["log", "-1", "--format=%B"]);
+    assert_eq!(
+        message.trim(),
+        "Add file\n\nAgent-Session: abc-123\nGenerated-by: test-agent"
+    );
+}
+
+#[test]
+fn session_identity_wins_over_the_environment() {
+    let tmp = setup_repo();
+    let dir = tmp.path();
+    let session_dir = dir.join(".git/partial.d/abc-123");
+    fs::create_dir_all(&session_dir).unwrap();
+    fs::write(session_dir.join("config"), "[user]\n\tname = Agent One\n\temail = one@agents.test\n")
+        .unwrap();
+
+    let binary = env!("CARGO_BIN_EXE_git-commit-staged");
+    for (file, plumbing) in [("a.txt", false), ("b.txt", true)] {
+        fs::write(dir.join(file), "v1\n").unwrap();
+        git(dir, &["add", file]);
+        let mut args = vec![file, "--", "-m", "Add"];
+        if plumbing {
+            args.insert(0, "--plumbing");
+        }
+        let output = Command::new(binary)
+            .args(&args)
+            .current_dir(dir)
+            .env("GIT_PARTIAL_SESSION", "abc-123")
+            .env("GIT_AUTHOR_NAME", "Env Author")
+            .env("GIT_AUTHOR_EMAIL", "env@example.com")
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        let author = git(dir, &["log", "-1", "--format=%an <%ae>"]);
+        assert_eq!(author, "Agent One <one@agents.test>\n", "plumbing: {plumbing}");
+    }
+}
+
+#[test]
+fn session_trailers_not_duplicated_on_amend() {
+    let tmp = setup_repo();
+    let dir = tmp.path();
+
+    fs::write(dir.join("file.txt"), "v1\n").unwrap();
+    git(dir, &["add", "file.txt"]);
+    let output =
+        git_commit_staged_in_session(dir, "abc-123", &["file.txt", "--", "-m", "Add file"]);
+    assert!(output.status.success());
+
+    fs::write(dir.join("file.txt"), "v2\n").unwrap();
+    git(dir, &["add", "file.txt"]);
+    let output =
+        git_commit_staged_in_session(dir, "abc-123", &["file.txt", "--", "--amend", "--no-edit"]);
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let message = git(dir, &["log", "-1", "--format=%B"]);
+    assert_eq!(message.matches("Agent-Session: abc-123").count(), 1, "{message}");
+}
+
+#[test]
+fn commit_result_read_from_repository() {
+    let tmp = setup_repo();
+    let dir = tmp.path();
+
+    fs::write(dir.join("a.txt"), "a\n").unwrap();
+    git(dir, &["add", "a.txt"]);
+
+    // -q leaves nothing on git's stdout to scrape
+    let output = git_commit_staged(dir, &["a.txt", "--", "-q", "-m", "Add a"]);
+    assert!(output.status.success());
+    let head = git(dir, &["rev-parse", "--short=7", "HEAD"]);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!("[commit-staged main {}]\n", head.trim())
+    );
+
+    fs::write(dir.join("a.txt"), "b\n").unwrap();
+    git(dir, &["add", "a.txt"]);
+    git(dir, &["checkout", "-q", "--detach"]);
+    let output = git_commit_staged(dir, &["a.txt", "--", "--amend", "--no-edit"]);
+    assert!(output.status.success());
+    let head = git(dir, &["rev-parse", "--short=7", "HEAD"]);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!("[commit-staged detached HEAD (amend) {}]\n", head.trim())
+    );
+}
+
+#[test]
+fn json_output_for_dry_run_and_commit() {
+    let tmp = setup_repo();
+    let dir = tmp.path();
+
+    fs::write(dir.join("README.md"), "# Changed\n").unwrap();
+    git(dir, &["add", "README.md"]);
+    fs::write(dir.join("README.md"), "# Changed again\n").unwrap();
+    let old_blob = git(dir, &["rev-parse", "HEAD:README.md"]);
+    let new_blob = git(dir, &["rev-parse", ":README.md"]);
+
+    let output = git_commit_staged(dir, &["--json", "-n", "README.md"]);
+    assert!(output.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["version"], 1);
+    assert_eq!(report["dry_run"], true);
+    let entry = &report["entries"][0];
+    assert_eq!(entry["path"], "README.md");
+    assert_eq!(entry["status"], "modified");
+    assert_eq!(entry["old_mode"], "100644");
+    assert_eq!(entry["old_oid"], old_blob.trim());
+    assert_eq!(entry["new_oid"], new_blob.trim());
+    assert!(report["warnings"][0].as_str().unwrap().contains("unstaged changes"));
+
+    // Only scripts get that warning
+    let output = git_commit_staged(dir, &["-n", "README.md"]);
+    assert!(output.status.success());
+    assert!(output.stderr.is_empty(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let output = git_commit_staged(dir, &["--json", "README.md", "--", "-m", "Change"]);
+    assert!(output.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["commit"]["oid"], git(dir, &["rev-parse", "HEAD"]).trim());
+    assert_eq!(report["commit"]["branch"], "main");
+    assert_eq!(report["commit"]["amend"], false);
+    assert_eq!(report["error"], serde_json::Value::Null);
+}
+
+#[test]
+fn porcelain_output_reports_errors() {
+    let tmp = setup_repo();
+    let dir = tmp.path();
+
+    fs::write(dir.join("a.txt"), "a\n").unwrap();
+    git(dir, &["add", "a.txt"]);
+    git(dir, &["config", "commitStaged.wait", "1"]);
+
+    let lock_path = dir.join(".git/index.lock");
+    fs::write(&lock_path, "").unwrap();
+    let output = git_commit_staged(dir, &["--porcelain", "-z", "a.txt", "--", "-m", "Add a"]);
+    fs::remove_file(&lock_path).unwrap();
+
+    assert_eq!(output.status.code(), Some(75));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let records: Vec<&str> = stdout.split_terminator('\0').collect();
+    assert_eq!(records[..2], ["version 1", "dry-run 0"]);
+    assert!(records[2].starts_with("error lock-timeout "), "{records:?}");
+}
+
+#[test]
+fn exit_codes_distinguish_failures() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let tmp = setup_repo();
+    let dir = tmp.path();
+
+    let output = git_commit_staged(dir, &["a.txt", "--", "-m", "Nothing"]);
+    assert_eq!(output.status.code(), Some(3), "no changes");
+
+    let output = git_commit_staged(dir, &["../outside", "--", "-m", "Escape"]);
+    assert_eq!(output.status.code(), Some(4), "path escapes repository");
+
+    fs::write(dir.join("a.txt"), "a\n").unwrap();
+    git(dir, &["add", "a.txt"]);
+    let hook = dir.join(".git/hooks/pre-commit");
+    // Output that looks like git's own errors doesn't matter
+    fs::write(&hook, "#!/bin/sh\necho 'error: lint rejected' >&2\nexit 1\n").unwrap();
+    fs::set_permissions(&hook, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let output = git_commit_staged(dir, &["--json", "a.txt", "--", "-m", "Add a"]);
+    assert_eq!(output.status.code(), Some(7), "hook rejected");
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["error"]["code"], "hook-rejected");
+    assert!(
+        report["error"]["message"].as_str().unwrap().contains("lint rejected"),
+        "{report}"
+    );
+
+    // With the hook passing, a failure after it is git's own
+    fs::write(&hook, "#!/bin/sh\nexit 0\n").unwrap();
+    let output = git_commit_staged(dir, &["a.txt", "--", "-m", ""]);
+    assert_eq!(output.status.code(), Some(8), "commit failed after pre-commit");
+
+    // Bypassing the hook, an empty message is git's own failure
+    let output = git_commit_staged(dir, &["a.txt", "--", "--no-verify", "-m", ""]);
+    assert_eq!(output.status.code(), Some(8), "commit failed");
+}
+
+#[test]
+fn expectations_guard_reviewed_content() {
+    let tmp = setup_repo();
+    let dir = tmp.path();
+    let head = git(dir, &["rev-parse", "HEAD"]);
+    let head = head.trim();
+
+    fs::write(dir.join("a.txt"), "reviewed\n").unwrap();
+    git(dir, &["add", "a.txt"]);
+    let preview = git_commit_staged(dir, &["--json", "-n", "a.txt"]);
+    assert!(preview.status.success());
+    let plan = dir.join(".git/plan.json");
+    fs::write(&plan, &preview.stdout).unwrap();
+    let plan = plan.to_str().unwrap();
+
+    // Another agent restages the path after review
+    fs::write(dir.join("a.txt"), "unreviewed\n").unwrap();
+    git(dir, &["add", "a.txt"]);
+    let output = git_commit_staged(dir, &["--expect-plan", plan, "a.txt", "--", "-m", "Add a"]);
+    assert_eq!(output.status.code(), Some(10), "blob mismatch");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("a.txt: expected "));
+
+    let wrong_head = "1".repeat(40);
+    let output = git_commit_staged(dir, &["--expect-head", &wrong_head, "a.txt", "--", "-m", "x"]);
+    assert_eq!(output.status.code(), Some(9), "head moved");
+    assert_eq!(git(dir, &["rev-parse", "HEAD"]).trim(), head);
+
+    fs::write(dir.join("a.txt"), "reviewed\n").unwrap();
+    git(dir, &["add", "a.txt"]);
+    let blob = git(dir, &["rev-parse", ":a.txt"]);
+    let expect_blob = format!("a.txt={}", blob.trim());
+    let args = [
+        "--expect-head", head, "--expect-plan", plan, "--expect-blob", &expect_blob, "a.txt",
+        "--", "-m", "Add a",
+    ];
+    let output = git_commit_staged(dir, &args);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+}
+
+#[test]
+fn plan_commits_exactly_what_was_reviewed() {
+    let tmp = setup_repo();
+    let dir = tmp.path();
+
+    fs::write(dir.join("a.txt"), "reviewed\n").unwrap();
+    git(dir, &["add", "a.txt"]);
+    let preview = git_commit_staged(dir, &["-n", "--plan-out", ".git/plan.json", "a.txt"]);
+    assert!(preview.status.success(), "{}", String::from_utf8_lossy(&preview.stderr));
+
+    // The index changes after review; the plan still commits what was reviewed
+    fs::write(dir.join("a.txt"), "later\n").unwrap();
+    git(dir, &["add", "a.txt"]);
+
+    // HEAD moves: refused, unless rebasing onto unrelated changes
+    fs::write(dir.join("b.txt"), "b\n").unwrap();
+    git(dir, &["add", "b.txt"]);
+    git(dir, &["commit", "-q", "-m", "Add b", "--", "b.txt"]);
+    let output = git_commit_staged(dir, &["--plan", ".git/plan.json", "--", "-m", "Add a"]);
+    assert_eq!(output.status.code(), Some(9), "head moved");
+
+    let output = git_commit_staged(
+        dir,
+        &["--plan", ".git/plan.json", "--rebase-plan", "--", "-m", "Add a"],
+    );
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(git(dir, &["show", "HEAD:a.txt"]), "reviewed\n");
+    assert_eq!(git(dir, &["show", "HEAD:b.txt"]), "b\n");
+    assert_eq!(git(dir, &["show", ":a.txt"]), "later\n", "index untouched");
+}
+
+#[test]
+fn rebased_plan_refuses_paths_changed_since_base() {
+    let tmp = setup_repo();
+    let dir = tmp.path();
+
+    fs::write(dir.join("a.txt"), "planned\n").unwrap();
+    git(dir, &["add", "a.txt"]);
+    let preview = git_commit_staged(dir, &["-n", "--plan-out", ".git/plan.json", "a.txt"]);
+    assert!(preview.status.success());
+
+    fs::write(dir.join("a.txt"), "theirs\n").unwrap();
+    git(dir, &["add", "a.txt"]);
+    git(dir, &["commit", "-q", "-m", "Change a"]);
+
+    let output = git_commit_staged(
+        dir,
+        &["--json", "--plan", ".git/plan.json", "--rebase-plan", "--", "-m", "Plan"],
+    );
+    assert_eq!(output.status.code(), Some(11));
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["error"]["code"], "conflict");
+    assert_eq!(git(dir, &["show", "HEAD:a.txt"]), "theirs\n");
+}
+
+#[test]
+fn auto_rebase_merges_onto_moved_head() {
+    let tmp = setup_repo();
+    let dir = tmp.path();
+    fs::write(dir.join("a.txt"), "1\n2\n3\n4\n5\n").unwrap();
+    git(dir, &["add", "a.txt"]);
+    git(dir, &["commit", "-q", "-m", "Add a"]);
+    let base = git(dir, &["rev-parse", "HEAD"]);
+    let base = base.trim();
+
+    // Our change, staged against the base
+    fs::write(dir.join("a.txt"), "one\n2\n3\n4\n5\n").unwrap();
+    git(dir, &["add", "a.txt"]);
+
+    let ours = git(dir, &["rev-parse", ":a.txt"]);
+
+    // Meanwhile HEAD moves (as from another worktree), leaving our change staged
+    fs::write(dir.join("a.txt"), "1\n2\n3\n4\nfive\n").unwrap();
+    git(dir, &["commit", "-q", "-m", "Change a", "--only", "a.txt"]);
+    let moved = git(dir, &["rev-parse", "HEAD"]);
+    let cacheinfo = format!("100644,{},a.txt", ours.trim());
+    git(dir, &["update-index", "--cacheinfo", &cacheinfo]);
+
+    let output = git_commit_staged(dir, &["--expect-head", base, "a.txt", "--", "-m", "x"]);
+    assert_eq!(output.status.code(), Some(9), "head moved");
+
+    let args = ["--expect-head", base, "--auto-rebase", "a.txt", "--", "-m", "Ours"];
+    let output = git_commit_staged(dir, &args);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(git(dir, &["show", "HEAD:a.txt"]), "one\n2\n3\n4\nfive\n");
+    assert_eq!(git(dir, &["rev-parse", "HEAD~"]).trim(), moved.trim());
+}
+
+#[test]
+fn plumbing_commit_runs_hooks_and_writes_reflog() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let tmp = setup_repo();
+    let dir = tmp.path();
+    let hook = |name: &str, script: &str| {
+        let path = dir.join(".git/hooks").join(name);
+        fs::write(&path, script).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    };
+
+    fs::write(dir.join("a.txt"), "a\n").unwrap();
+    git(dir, &["add", "a.txt"]);
+
+    hook("pre-commit", "#!/bin/sh\necho no >&2\nexit 1\n");
+    let output = git_commit_staged(dir, &["--plumbing", "a.txt", "--", "-m", "Add a"]);
+    assert_eq!(output.status.code(), Some(7), "hook rejected");
+
+    hook("pre-commit", "#!/bin/sh\ngit diff --cached --name-only > .git/pre-commit-saw\n");
+    hook("commit-msg", "#!/bin/sh\necho 'Checked-by: hook' >> \"$1\"\n");
+    hook("post-commit", "#!/bin/sh\ngit rev-parse HEAD > .git/post-commit-saw\n");
+    let output = git_commit_staged(dir, &["--plumbing", "a.txt", "--", "-m", "Add a"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let head = git(dir, &["rev-parse", "HEAD"]);
+    assert_eq!(fs::read_to_string(dir.join(".git/pre-commit-saw")).unwrap(), "a.txt\n");
+    assert_eq!(fs::read_to_string(dir.join(".git/post-commit-saw")).unwrap(), head);
+    assert_eq!(git(dir, &["log", "-1", "--format=%B"]), "Add a\nChecked-by: hook\n\n");
+    assert_eq!(git(dir, &["reflog", "-1", "--format=%gs"]), "commit: Add a\n");
+    assert_eq!(git(dir, &["reflog", "-1", "--format=%gs", "main"]), "commit: Add a\n");
+
+    // --amend keeps the parents and reuses the message
+    fs::write(dir.join("b.txt"), "b\n").unwrap();
+    git(dir, &["add", "b.txt"]);
+    let args = ["--plumbing", "b.txt", "--", "--amend", "--no-verify"];
+    let output = git_commit_staged(dir, &args);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(git(dir, &["rev-list", "--count", "HEAD"]), "2\n");
+    assert_eq!(git(dir, &["ls-tree", "--name-only", "HEAD"]), "README.md\na.txt\nb.txt\n");
+    assert_eq!(git(dir, &["reflog", "-1", "--format=%gs"]), "commit (amend): Add a\n");
+
+    fs::write(dir.join("a.txt"), "changed\n").unwrap();
+    git(dir, &["add", "a.txt"]);
+    let output = git_commit_staged(dir, &["--plumbing", "a.txt", "--", "--fixup", "HEAD"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("not supported with --plumbing"));
+}
+
+#[test]
+fn plumbing_commit_signs_with_ssh_key() {
+    let tmp = setup_repo();
+    let dir = tmp.path();
+    let key = dir.join(".git/signing-key");
+    let status = Command::new("ssh-keygen")
+        .args(["-q", "-t", "ed25519", "-N", "", "-C", "test", "-f"])
+        .arg(&key)
+        .status()
+        .expect("failed to run ssh-keygen");
+    assert!(status.success());
+    git(dir, &["config", "gpg.format", "ssh"]);
+    git(dir, &["config", "user.signingKey", key.to_str().unwrap()]);
+    git(dir, &["config", "commit.gpgSign", "true"]);
+
+    fs::write(dir.join("a.txt"), "a\n").unwrap();
+    git(dir, &["add", "a.txt"]);
+    let output = git_commit_staged(dir, &["--plumbing", "a.txt", "--", "-m", "Signed"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let commit = git(dir, &["cat-file", "commit", "HEAD"]);
+    assert!(commit.contains("gpgsig -----BEGIN SSH SIGNATURE-----"), "{commit}");
+    assert!(!fs::read_dir(dir.join(".git"))
+        .unwrap()
+        .any(|entry| entry.unwrap().file_name().to_string_lossy().contains("signing-buffer")));
+}
+
+#[test]
+fn hook_index_changes_fold_into_main_index() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let tmp = setup_repo();
+    let dir = tmp.path();
+    let hook = dir.join(".git/hooks/pre-commit");
+    let script = "#!/bin/sh\n\
+                  printf 'fixed\\n' > a.txt && git add a.txt\n\
+                  printf 'generated\\n' > gen.txt && git add gen.txt\n";
+    fs::write(&hook, script).unwrap();
+    fs::set_permissions(&hook, fs::Permissions::from_mode(0o755)).unwrap();
+
+    fs::write(dir.join("a.txt"), "unfixed\n").unwrap();
+    git(dir, &["add", "a.txt"]);
+    let args = ["--fold-hook-changes", "a.txt", "--", "-m", "Add a"];
+    let output = git_commit_staged(dir, &args);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    assert_eq!(git(dir, &["show", "HEAD:a.txt"]), "fixed\n");
+    // Only the committed path is folded; the other was committed regardless
+    let staged = git(dir, &["diff", "--cached", "--name-status"]);
+    assert_eq!(staged, "D\tgen.txt\n", "a.txt matches HEAD");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("warning: a hook changed gen.txt"), "{stderr}");
+    assert!(!stderr.contains("a.txt,"), "{stderr}");
+}
+
+#[test]
+fn plumbing_refuses_hook_changes_outside_the_commit() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let tmp = setup_repo();
+    let dir = tmp.path();
+    let hook = dir.join(".git/hooks/pre-commit");
+    let script = "#!/bin/sh\nprintf 'generated\\n' > gen.txt && git add gen.txt\n";
+    fs::write(&hook, script).unwrap();
+    fs::set_permissions(&hook, fs::Permissions::from_mode(0o755)).unwrap();
+
+    fs::write(dir.join("a.txt"), "a\n").unwrap();
+    git(dir, &["add", "a.txt"]);
+    let head = git(dir, &["rev-parse", "HEAD"]);
+    let args = ["--plumbing", "--fold-hook-changes", "a.txt", "--", "-m", "Add a"];
+    let output = git_commit_staged(dir, &args);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("outside the ones being committed: gen.txt"), "{stderr}");
+    assert_eq!(git(dir, &["rev-parse", "HEAD"]), head);
+    assert_eq!(git(dir, &["diff", "--cached", "--name-only"]), "a.txt\n");
+}
+
+#[test]
+fn verify_cmd_runs_on_the_tree_to_commit() {
+    let tmp = setup_repo();
+    let dir = tmp.path();
+    fs::write(dir.join("a.txt"), "staged\n").unwrap();
+    git(dir, &["add", "a.txt"]);
+    fs::write(dir.join("a.txt"), "unstaged\n").unwrap();
+    fs::write(dir.join("b.txt"), "untracked\n").unwrap();
+    let head = git(dir, &["rev-parse", "HEAD"]);
+
+    let failing = ["--json", "--plumbing", "--verify-cmd", "exit 3", "a.txt", "--", "-m", "Add a"];
+    let output = git_commit_staged(dir, &failing);
+    assert_eq!(output.status.code(), Some(12), "verify failed");
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["error"]["code"], "verify-failed");
+    assert_eq!(git(dir, &["rev-parse", "HEAD"]), head, "nothing committed");
+
+    let check = "test \"$(cat a.txt)\" = staged && test -e README.md && ! test -e b.txt";
+    let args = ["--plumbing", "--verify-cmd", check, "--keep-scratch", "a.txt", "--", "-m", "Add"];
+    let output = git_commit_staged(dir, &args);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{stderr}");
+    assert_eq!(git(dir, &["show", "HEAD:a.txt"]), "staged\n");
+
+    let kept = stderr
+        .lines()
+        .find_map(|line| line.strip_prefix("Scratch checkout kept at "))
+        .expect("scratch path reported");
+    assert_eq!(fs::read_to_string(Path::new(kept).join("a.txt")).unwrap(), "staged\n");
+    fs::remove_dir_all(kept).unwrap();
+}
+
+#[test]
+fn verify_cmd_refuses_trees_changed_by_hooks() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let tmp = setup_repo();
+    let dir = tmp.path();
+    let hook = dir.join(".git/hooks/pre-commit");
+    fs::write(&hook, "#!/bin/sh\nprintf 'broken\\n' > a.txt && git add a.txt\n").unwrap();
+    fs::set_permissions(&hook, fs::Permissions::from_mode(0o755)).unwrap();
+    fs::write(dir.join("a.txt"), "good\n").unwrap();
+    git(dir, &["add", "a.txt"]);
+    let head = git(dir, &["rev-parse", "HEAD"]);
+    let check = "grep -q good a.txt";
+
+    // git commit can't be stopped after its hooks
+    let args = ["--verify-cmd", check, "a.txt", "--", "-m", "Add a"];
+    let output = git_commit_staged(dir, &args);
+    assert_eq!(output.status.code(), Some(2), "usage error");
+    assert_eq!(git(dir, &["rev-parse", "HEAD"]), head);
+
+    // --plumbing: verified after the hook
+    let args = ["--plumbing", "--verify-cmd", check, "a.txt", "--", "-m", "Add a"];
+    let output = git_commit_staged(dir, &args);
+    assert_eq!(output.status.code(), Some(12), "verify failed");
+    assert_eq!(git(dir, &["rev-parse", "HEAD"]), head);
+    assert_eq!(git(dir, &["show", ":a.txt"]), "good\n");
+}
+
+#[test]
+fn paranoid_reports_changes_outside_the_commit() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let tmp = setup_repo();
+    let dir = tmp.path();
+    fs::write(dir.join("a.txt"), "a\n").unwrap();
+    fs::write(dir.join("b.txt"), "b\n").unwrap();
+    git(dir, &["add", "a.txt", "b.txt"]);
+    fs::write(dir.join("b.txt"), "unstaged\n").unwrap();
+
+    let output = git_commit_staged(dir, &["--paranoid", "a.txt", "--", "-m", "Add a"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let hook = dir.join(".git/hooks/pre-commit");
+    fs::write(&hook, "#!/bin/sh\nprintf 'touched\\n' > README.md\n").unwrap();
+    fs::set_permissions(&hook, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let args = ["--paranoid", "--json", "b.txt", "--", "-m", "Add b"];
+    let output = git_commit_staged(dir, &args);
+    assert_eq!(output.status.code(), Some(13), "unexpected mutation");
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["error"]["code"], "unexpected-mutation");
+    let message = report["error"]["message"].as_str().unwrap();
+    let head = git(dir, &["rev-parse", "HEAD"]);
+    assert!(message.contains(head.trim()), "names the commit: {message}");
+    assert!(message.contains("work tree: README.md: "), "{message}");
+    assert!(!message.contains("b.txt"), "{message}");
+}
+
+#[test]
+fn undo_takes_back_journaled_commits() {
+    let tmp = setup_repo();
+    let dir = tmp.path();
+    let initial = git(dir, &["rev-parse", "HEAD"]);
+    fs::write(dir.join("a.txt"), "a\n").unwrap();
+    fs::write(dir.join("b.txt"), "b\n").unwrap();
+    git(dir, &["add", "a.txt", "b.txt"]);
+
+    let output = git_commit_staged(dir, &["a.txt", "--", "-m", "Add a"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let journal = fs::read_to_string(dir.join(".git/commit-staged/journal.jsonl")).unwrap();
+    let record: serde_json::Value = serde_json::from_str(journal.trim()).unwrap();
+    assert_eq!(record["operation"], "commit-staged");
+    assert_eq!(record["old_head"], initial.trim());
+    assert_eq!(record["entries"][0]["path"], "a.txt");
+
+    // HEAD still at the commit: the branch moves back, a.txt stays staged
+    let output = git_commit_staged(dir, &["undo"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(git(dir, &["rev-parse", "HEAD"]), initial);
+    assert_eq!(git(dir, &["diff", "--cached", "--name-only"]), "a.txt\nb.txt\n");
+
+    // HEAD moved on: the commit is reverted on top
+    let output = git_commit_staged(dir, &["a.txt", "--", "-m", "Add a again"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    git(dir, &["commit", "--only", "-m", "Add b", "b.txt"]);
+    let output = git_commit_staged(dir, &["undo"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("Reverted "));
+    assert_eq!(git(dir, &["log", "-1", "--format=%s"]), "Revert \"Add a again\"\n");
+    assert_eq!(git(dir, &["ls-tree", "--name-only", "HEAD"]), "README.md\nb.txt\n");
+    assert_eq!(git(dir, &["diff", "--cached", "--name-only"]), "a.txt\n");
+
+    let output = git_commit_staged(dir, &["undo"]);
+    assert!(!output.status.success(), "nothing left to undo");
+}
+
+#[test]
+fn undo_checks_every_commit_before_undoing_any() {
+    let tmp = setup_repo();
+    let dir = tmp.path();
+    for file in ["a.txt", "b.txt", "c.txt"] {
+        fs::write(dir.join(file), "v1\n").unwrap();
+        git(dir, &["add", file]);
+        let output = git_commit_staged(dir, &[file, "--", "-m", file]);
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    }
+    // Reverting c.txt would merge; reverting b.txt, the 2nd, conflicts
+    fs::write(dir.join("b.txt"), "v2\n").unwrap();
+    git(dir, &["commit", "-q", "-am", "Change b"]);
+    let head = git(dir, &["rev-parse", "HEAD"]);
+    let index = git(dir, &["ls-files", "--stage"]);
+    let journal = fs::read_to_string(dir.join(".git/commit-staged/journal.jsonl")).unwrap();
+
+    let output = git_commit_staged(dir, &["undo", "3"]);
+    assert_eq!(output.status.code(), Some(11), "conflict");
+    assert_eq!(git(dir, &["rev-parse", "HEAD"]), head);
+    assert_eq!(git(dir, &["ls-files", "--stage"]), index);
+    let after = fs::read_to_string(dir.join(".git/commit-staged/journal.jsonl")).unwrap();
+    assert_eq!(after, journal);
+
+    // Without the change to b.txt, the branch moves back twice
+    git(dir, &["reset", "-q", "--hard", "HEAD~"]);
+    let output = git_commit_staged(dir, &["undo", "2"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(git(dir, &["ls-tree", "--name-only", "HEAD"]), "README.md\na.txt\n");
+}
+
+#[test]
+fn undo_reverts_with_the_session_identity() {
+    let tmp = setup_repo();
+    let dir = tmp.path();
+    let session_dir = dir.join(".git/partial.d/abc-123");
+    fs::create_dir_all(&session_dir).unwrap();
+    fs::write(session_dir.join("config"), "[user]\n\tname = Agent One\n\temail = one@agents.test\n")
+        .unwrap();
+    fs::write(dir.join("a.txt"), "a\n").unwrap();
+    git(dir, &["add", "a.txt"]);
+    let output = git_commit_staged_in_session(dir, "abc-123", &["a.txt", "--", "-m", "Add a"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    git(dir, &["commit", "-q", "--allow-empty", "-m", "Later"]);
+
+    let output = git_commit_staged_in_session(dir, "abc-123", &["undo"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(git(dir, &["log", "-1", "--format=%an <%ae>"]), "Agent One <one@agents.test>\n");
+    assert_eq!(git(dir, &["log", "-1", "--format=%cn"]), "Test User\n");
+    let message = git(dir, &["log", "-1", "--format=%B"]);
+    assert!(message.contains("\n\nAgent-Session: abc-123\n"), "{message}");
+}
+
+#[test]
+fn undo_refuses_commits_head_does_not_contain() {
+    let tmp = setup_repo();
+    let dir = tmp.path();
+    fs::write(dir.join("a.txt"), "a\n").unwrap();
+    git(dir, &["add", "a.txt"]);
+    let output = git_commit_staged(dir, &["a.txt", "--", "-m", "Add a"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    // Another branch: nothing is reverted there
+    git(dir, &["switch", "-q", "-c", "other", "HEAD~"]);
+    let output = git_commit_staged(dir, &["undo"]);
+    assert!(!output.status.success(), "other branch");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("was committed to refs/heads/main"), "{stderr}");
+    assert_eq!(git(dir, &["log", "-1", "--format=%s"]), "Initial commit\n");
+
+    // The branch itself, rewound past the commit
+    git(dir, &["switch", "-q", "main"]);
+    git(dir, &["reset", "-q", "--soft", "HEAD~"]);
+    let output = git_commit_staged(dir, &["undo"]);
+    assert!(!output.status.success(), "rewound branch");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("refs/heads/main no longer contains"), "{stderr}");
+    assert_eq!(git(dir, &["log", "-1", "--format=%s"]), "Initial commit\n");
+}
+
+#[test]
+fn undo_of_a_root_commit_deletes_the_branch() {
+    let tmp = TempDir::new().unwrap();
+    let dir = tmp.path();
+    git(dir, &["init", "-q", "-b", "main"]);
+    git(dir, &["config", "user.email", "test@test.com"]);
+    git(dir, &["config", "user.name", "Test User"]);
+    fs::write(dir.join("a.txt"), "a\n").unwrap();
+    git(dir, &["add", "a.txt"]);
+    git(dir, &["commit", "-q", "-m", "Add a"]);
+
+    // Journaled as a root commit: no old_head
+    let head = git(dir, &["rev-parse", "HEAD"]);
+    let blob = git(dir, &["rev-parse", "HEAD:a.txt"]);
+    let entry = serde_json::json!({"path": "a.txt", "oid": blob.trim(), "mode": "100644"});
+    let record = serde_json::json!({
+        "time": 0,
+        "operation": "commit-staged",
+        "session": null,
+        "command": [],
+        "reference": "refs/heads/main",
+        "old_head": null,
+        "new_head": head.trim(),
+        "entries": [entry],
+        "index_before": [entry],
+    });
+    fs::create_dir(dir.join(".git/commit-staged")).unwrap();
+    fs::write(dir.join(".git/commit-staged/journal.jsonl"), format!("{record}\n")).unwrap();
+
+    let output = git_commit_staged(dir, &["undo"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(git(dir, &["for-each-ref"]), "");
+    assert_eq!(git(dir, &["symbolic-ref", "HEAD"]), "refs/heads/main\n");
+    assert_eq!(git(dir, &["ls-files", "--stage"]).split_whitespace().last(), Some("a.txt"));
+}
+
+#[test]
+fn series_commits_groups_with_one_ref_update() {
+    let tmp = setup_repo();
+    let dir = tmp.path();
+    let initial = git(dir, &["rev-parse", "HEAD"]);
+    fs::write(dir.join("a.txt"), "a\n").unwrap();
+    fs::write(dir.join("b.txt"), "b\n").unwrap();
+    git(dir, &["add", "a.txt", "b.txt"]);
+    fs::write(dir.join("b-message.txt"), "Add b\n").unwrap();
+
+    // A group with nothing left to commit fails the whole series
+    let series = dir.join(".git/series.toml");
+    fs::write(
+        &series,
+        "[[commit]]\npaths = [\"a.txt\"]\nmessage = \"Add a\"\n\n\
+         [[commit]]\npaths = [\"a.txt\"]\nmessage = \"Add a again\"\n",
+    )
+    .unwrap();
+    let output = git_commit_staged(dir, &["--series", series.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(3), "no changes");
+    assert_eq!(git(dir, &["rev-parse", "HEAD"]), initial);
+
+    fs::write(
+        &series,
+        "[[commit]]\npaths = [\"a.txt\"]\nmessage = \"Add a\"\n\n\
+         [[commit]]\npaths = [\"b.txt\"]\nmessage_file = \"../b-message.txt\"\n",
+    )
+    .unwrap();
+    let output = git_commit_staged(dir, &["--series", series.to_str().unwrap(), "--dry-run"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("commit 2 (b.txt):\n    M b.txt"), "{stdout}");
+    assert_eq!(git(dir, &["rev-parse", "HEAD"]), initial);
+
+    let output = git_commit_staged(dir, &["--series", series.to_str().unwrap()]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(git(dir, &["log", "--format=%s", "-2"]), "Add b\nAdd a\n");
+    assert_eq!(git(dir, &["show", "--name-only", "--format=", "HEAD~"]), "a.txt\n");
+    assert_eq!(git(dir, &["reflog", "-1", "--format=%gs", "main"]), "commit (series): 2 commits\n");
+    assert_eq!(git(dir, &["rev-parse", "main@{1}"]), initial);
+    assert_eq!(git(dir, &["diff", "--cached", "--name-only"]), "");
+}
+
+#[test]
+fn series_runs_post_commit_for_each_commit() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let tmp = setup_repo();
+    let dir = tmp.path();
+    let hook = dir.join(".git/hooks/post-commit");
+    fs::write(&hook, "#!/bin/sh\ngit ls-files >> .git/post-commit.log\n").unwrap();
+    fs::set_permissions(&hook, fs::Permissions::from_mode(0o755)).unwrap();
+    fs::write(dir.join("a.txt"), "a\n").unwrap();
+    fs::write(dir.join("b.txt"), "b\n").unwrap();
+    git(dir, &["add", "a.txt", "b.txt"]);
+
+    let series = dir.join(".git/series.toml");
+    fs::write(
+        &series,
+        "[[commit]]\npaths = [\"a.txt\"]\nmessage = \"Add a\"\n\n\
+         [[commit]]\npaths = [\"b.txt\"]\nmessage = \"Add b\"\n",
+    )
+    .unwrap();
+    let output = git_commit_staged(dir, &["--series", series.to_str().unwrap()]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    // Each run sees its own commit's tree in the index
+    let log = fs::read_to_string(dir.join(".git/post-commit.log")).unwrap();
+    assert_eq!(log, "README.md\na.txt\nREADME.md\na.txt\nb.txt\n");
+}
+
+#[test]
+fn series_paths_are_relative_to_the_current_directory() {
+    let tmp = setup_repo();
+    let dir = tmp.path();
+    fs::create_dir(dir.join("sub")).unwrap();
+    fs::write(dir.join("sub/c.txt"), "c\n").unwrap();
+    git(dir, &["add", "sub/c.txt"]);
+
+    // Paths from the cwd (sub), the message file from the series file (.git)
+    let series = dir.join(".git/series.toml");
+    fs::write(&series, "[[commit]]\npaths = [\"c.txt\"]\nmessage_file = \"c-message.txt\"\n")
+        .unwrap();
+    fs::write(dir.join(".git/c-message.txt"), "Add c\n").unwrap();
+    let output = git_commit_staged_in_subdir(dir, "sub", &["--series", series.to_str().unwrap()]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(git(dir, &["log", "-1", "--format=%s"]), "Add c\n");
+    assert_eq!(git(dir, &["show", "--name-only", "--format=", "HEAD"]), "sub/c.txt\n");
+}
+
+#[test]
+fn each_commits_one_group_at_a_time() {
+    let tmp = setup_repo();
+    let dir = tmp.path();
+    fs::create_dir_all(dir.join("src/lib")).unwrap();
+    fs::write(dir.join("src/a.rs"), "a\n").unwrap();
+    fs::write(dir.join("src/lib/b.rs"), "b\n").unwrap();
+    fs::write(dir.join("README.md"), "# Changed\n").unwrap();
+    git(dir, &["add", "."]);
+
+    let output = git_commit_staged(dir, &["--each=top-dir", "."]);
+    assert!(!output.status.success(), "no message template");
+
+    let output = git_commit_staged(dir, &["--each=dir", "-n", "src", "--", "-m", "{path}"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("  src:\n    M src/a.rs\n  src/lib:\n"), "{stdout}");
+
+    let output = git_commit_staged(dir, &["--each=top-dir", ".", "--", "-m", "fmt: {path}"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(git(dir, &["log", "--format=%s", "-2"]), "fmt: src\nfmt: .\n");
+    assert_eq!(git(dir, &["show", "--name-only", "--format=", "HEAD"]), "src/a.rs\nsrc/lib/b.rs\n");
+    assert_eq!(git(dir, &["diff", "--cached", "--name-only"]), "");
+}
+
+#[test]
+fn split_by_owners_commits_each_owner_group() {
+    let tmp = setup_repo();
+    let dir = tmp.path();
+    fs::create_dir_all(dir.join(".github")).unwrap();
+    fs::write(dir.join(".github/CODEOWNERS"), "*.md @docs\n/web/ @web\n").unwrap();
+    git(dir, &["add", ".github"]);
+    git(dir, &["commit", "-m", "Add CODEOWNERS"]);
+    fs::create_dir_all(dir.join("web")).unwrap();
+    fs::write(dir.join("web/app.js"), "app\n").unwrap();
+    fs::write(dir.join("README.md"), "# Changed\n").unwrap();
+    fs::write(dir.join("Makefile"), "all:\n").unwrap();
+    git(dir, &["add", "."]);
+
+    let args = ["--split-by-owners", "-n", ".", "--", "-m", "Update {owners}"];
+    let output = git_commit_staged(dir, &args);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("  unowned:\n    M Makefile\n  @docs:\n    M README.md\n"),
+        "{stdout}"
+    );
+
+    let args = ["--split-by-owners", "web", "README.md", "--", "-m", "Update {owners}"];
+    let output = git_commit_staged(dir, &args);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(git(dir, &["log", "--format=%s", "-2"]), "Update @web\nUpdate @docs\n");
+    assert_eq!(git(dir, &["log", "-1", "--format=%b"]), "Owners: @web\n\n");
+    assert_eq!(git(dir, &["diff", "--cached", "--name-only"]), "Makefile\n");
+}
+
+#[test]
+fn ignores_non_utf8_paths_outside_scope() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let tmp = setup_repo();
+    let dir = tmp.path();
+    let bad = dir.join(OsStr::from_bytes(b"bad\xff"));
+    fs::write(&bad, "bad\n").unwrap();
+    git(dir, &["add", "-A"]);
+    git(dir, &["commit", "-m", "Add a badly named file"]);
+    fs::write(&bad, "changed\n").unwrap();
+
+    fs::write(dir.join("a.txt"), "a\n").unwrap();
+    git(dir, &["add", "a.txt"]);
+    let output = git_commit_staged(dir, &["a.txt", "--", "-m", "Add a"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+}
+
 #[test]
 fn version_includes_git_hash() {
     let output = Command::new(env!("CARGO_BIN_EXE_git-commit-staged"))
@@ -276,7 +1593,7 @@ fn version_includes_git_hash() {
     let stdout = String::from_utf8_lossy(&output.stdout);
     // Should match pattern: "git-commit-staged X.Y.Z (abcdef1)"
     assert!(
-        stdout.contains("git-commit-staged") && stdout.contains("("),
+        stdout.contains("git-commit-staged") && stdout.contains('('),
         "version should include git hash: {stdout}"
     );
 }
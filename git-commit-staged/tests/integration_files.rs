@@ -369,6 +369,125 @@ fn fails_when_lock_is_held() {
     fs::remove_file(&lock_path).unwrap();
 }
 
+#[test]
+fn failed_commit_restores_index() {
+    let tmp = setup_repo();
+    let dir = tmp.path();
+
+    fs::write(dir.join("other.txt"), "staged\n").unwrap();
+    git(dir, &["add", "other.txt"]);
+    fs::write(dir.join("main.rs"), "fn main() {}\n").unwrap();
+
+    let hook = dir.join(".git/hooks/pre-commit");
+    fs::write(&hook, "#!/bin/sh\nexit 1\n").unwrap();
+    fs::set_permissions(&hook, std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+
+    let index_before = git(dir, &["ls-files", "--stage"]);
+    let head_before = git(dir, &["rev-parse", "HEAD"]);
+
+    let output = git_commit_files(dir, &["main.rs", "--", "-m", "Add main"]);
+    assert!(!output.status.success());
+
+    // Exactly as before: main.rs not staged, other.txt still staged
+    assert_eq!(git(dir, &["ls-files", "--stage"]), index_before);
+    assert_eq!(git(dir, &["rev-parse", "HEAD"]), head_before);
+    assert_eq!(git(dir, &["diff", "--cached", "--name-only"]), "other.txt\n");
+}
+
+#[test]
+fn porcelain_output_lists_entries_and_commit() {
+    let tmp = setup_repo();
+    let dir = tmp.path();
+
+    fs::write(dir.join("new file.txt"), "new\n").unwrap();
+
+    let output = git_commit_files(dir, &["--porcelain=v1", "new file.txt", "--", "-m", "Add"]);
+    assert!(output.status.success());
+
+    let blob = git(dir, &["rev-parse", "HEAD:new file.txt"]);
+    let head = git(dir, &["rev-parse", "HEAD"]);
+    let old_head = git(dir, &["rev-parse", "HEAD~"]);
+    let zero = "0".repeat(40);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(
+        lines,
+        [
+            "version 1".to_string(),
+            "dry-run 0".to_string(),
+            format!("entry A 000000 100644 {zero} {} new file.txt", blob.trim()),
+            format!("commit {}", head.trim()),
+            format!("tree {}", git(dir, &["rev-parse", "HEAD^{tree}"]).trim()),
+            format!("old-head {}", old_head.trim()),
+            "ref refs/heads/main".to_string(),
+            "branch main".to_string(),
+            "amend 0".to_string(),
+        ]
+    );
+}
+
+#[test]
+fn paranoid_passes_when_only_committed_paths_change() {
+    let tmp = setup_repo();
+    let dir = tmp.path();
+
+    fs::write(dir.join("staged.txt"), "staged\n").unwrap();
+    git(dir, &["add", "staged.txt"]);
+    fs::write(dir.join("new.txt"), "new\n").unwrap();
+
+    let output = git_commit_files(dir, &["--paranoid", "new.txt", "--", "-m", "Add new"]);
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(git(dir, &["diff", "--cached", "--name-only"]), "staged.txt\n");
+}
+
+#[test]
+fn ignores_non_utf8_paths_outside_scope() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let tmp = setup_repo();
+    let dir = tmp.path();
+    let bad = dir.join(OsStr::from_bytes(b"bad\xff"));
+    fs::write(&bad, "bad\n").unwrap();
+    git(dir, &["add", "-A"]);
+    git(dir, &["commit", "-m", "Add a badly named file"]);
+    fs::write(&bad, "changed\n").unwrap();
+
+    fs::write(dir.join("a.txt"), "a\n").unwrap();
+    let output = git_commit_files(dir, &["a.txt", "--", "-m", "Add a"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+}
+
+#[test]
+fn expect_blob_checks_the_mode() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let tmp = setup_repo();
+    let dir = tmp.path();
+
+    fs::write(dir.join("run.sh"), "#!/bin/sh\n").unwrap();
+    fs::set_permissions(dir.join("run.sh"), fs::Permissions::from_mode(0o755)).unwrap();
+    let blob = git(dir, &["hash-object", "run.sh"]);
+    let head = git(dir, &["rev-parse", "HEAD"]);
+
+    // Reviewed as a regular file, but it is executable now
+    let regular = format!("run.sh=100644,{}", blob.trim());
+    let output = git_commit_files(dir, &["--expect-blob", &regular, "run.sh", "--", "-m", "x"]);
+    assert_eq!(output.status.code(), Some(10), "blob mismatch");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("found 100755"), "stderr: {stderr}");
+    assert_eq!(git(dir, &["rev-parse", "HEAD"]), head);
+    assert!(git(dir, &["status", "--porcelain"]).contains("?? run.sh"));
+
+    let executable = format!("run.sh=100755,{}", blob.trim());
+    let output = git_commit_files(dir, &["--expect-blob", &executable, "run.sh", "--", "-m", "x"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+}
+
 #[test]
 fn version_includes_git_hash() {
     let output = Command::new(env!("CARGO_BIN_EXE_git-commit-files"))
@@ -380,7 +499,7 @@ fn version_includes_git_hash() {
     let stdout = String::from_utf8_lossy(&output.stdout);
     // Should match pattern: "git-commit-files X.Y.Z (abcdef1)"
     assert!(
-        stdout.contains("git-commit-files") && stdout.contains("("),
+        stdout.contains("git-commit-files") && stdout.contains('('),
         "version should include git hash: {stdout}"
     );
 }
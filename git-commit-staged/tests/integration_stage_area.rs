@@ -0,0 +1,207 @@
+//! CLI integration tests for git-stage-area
+//!
+//! These tests run git-stage-area as a subprocess via `git -C <dir> stage-area`.
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use tempfile::TempDir;
+
+/// Helper to run git commands in a directory
+fn git(dir: &Path, args: &[&str]) -> String {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .expect("failed to execute git");
+
+    assert!(
+        output.status.success(),
+        "git {:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8_lossy(&output.stdout).to_string()
+}
+
+/// Helper to run our binary via `git -C <dir> stage-area`, asserting success
+fn git_stage_area(dir: &Path, args: &[&str]) -> String {
+    let binary = env!("CARGO_BIN_EXE_git-stage-area");
+    let bin_dir = Path::new(binary).parent().unwrap();
+    let path = std::env::var("PATH").unwrap_or_default();
+    let new_path = format!("{}:{}", bin_dir.display(), path);
+
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .arg("stage-area")
+        .args(args)
+        .env("PATH", new_path)
+        .output()
+        .expect("failed to execute git stage-area");
+
+    assert!(
+        output.status.success(),
+        "git stage-area {:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8_lossy(&output.stdout).to_string()
+}
+
+/// Create a test repo with an initial commit
+fn setup_repo() -> TempDir {
+    let tmp = TempDir::new().expect("failed to create temp dir");
+    let dir = tmp.path();
+
+    git(dir, &["init", "-b", "main"]);
+    git(dir, &["config", "user.email", "test@test.com"]);
+    git(dir, &["config", "user.name", "Test User"]);
+
+    // Initial commit
+    fs::write(dir.join("README.md"), "# Test Repo\n").unwrap();
+    git(dir, &["add", "README.md"]);
+    git(dir, &["commit", "-m", "Initial commit"]);
+
+    tmp
+}
+
+#[test]
+fn add_does_not_touch_main_index() {
+    let tmp = setup_repo();
+    let dir = tmp.path();
+
+    fs::write(dir.join("a.txt"), "a\n").unwrap();
+    git_stage_area(dir, &["add", "one", "a.txt"]);
+
+    assert!(dir.join(".git/staging/one").exists());
+
+    // Main index still has nothing staged
+    let status = git(dir, &["status", "--porcelain"]);
+    assert_eq!(status.trim(), "?? a.txt");
+
+    let diff = git_stage_area(dir, &["diff", "one", "--", "--name-status"]);
+    assert_eq!(diff.trim(), "A\ta.txt");
+}
+
+#[test]
+fn commit_includes_only_area_content() {
+    let tmp = setup_repo();
+    let dir = tmp.path();
+
+    fs::write(dir.join("a.txt"), "a\n").unwrap();
+    fs::write(dir.join("b.txt"), "b\n").unwrap();
+    git_stage_area(dir, &["add", "one", "a.txt"]);
+    git(dir, &["add", "b.txt"]);
+
+    git_stage_area(dir, &["commit", "one", "--", "-m", "Add a"]);
+
+    let show = git(dir, &["show", "--name-only", "--format="]);
+    assert_eq!(show.trim(), "a.txt");
+
+    // Main index follows the commit for a.txt; b.txt stays staged
+    let status = git(dir, &["status", "--porcelain"]);
+    assert_eq!(status.trim(), "A  b.txt");
+
+    // Committed area is now empty
+    let list = git_stage_area(dir, &["list"]);
+    assert_eq!(list.trim(), "one\t0 staged");
+}
+
+#[test]
+fn commit_is_journaled_for_undo() {
+    let tmp = setup_repo();
+    let dir = tmp.path();
+    let initial = git(dir, &["rev-parse", "HEAD"]);
+
+    fs::write(dir.join("a.txt"), "a\n").unwrap();
+    git_stage_area(dir, &["add", "one", "a.txt"]);
+    git_stage_area(dir, &["commit", "one", "--", "-m", "Add a"]);
+
+    let journal = fs::read_to_string(dir.join(".git/commit-staged/journal.jsonl")).unwrap();
+    let record: serde_json::Value = serde_json::from_str(journal.trim()).unwrap();
+    assert_eq!(record["operation"], "stage-area");
+    assert_eq!(record["entries"][0]["path"], "a.txt");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_git-commit-staged"))
+        .arg("undo")
+        .current_dir(dir)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(git(dir, &["rev-parse", "HEAD"]), initial);
+}
+
+#[test]
+fn commit_rebases_other_areas() {
+    let tmp = setup_repo();
+    let dir = tmp.path();
+
+    fs::write(dir.join("a.txt"), "a\n").unwrap();
+    fs::write(dir.join("b.txt"), "b\n").unwrap();
+    git_stage_area(dir, &["add", "one", "a.txt"]);
+    git_stage_area(dir, &["add", "two", "b.txt"]);
+
+    git_stage_area(dir, &["commit", "one", "--", "-m", "Add a"]);
+
+    // Area two is based on the new HEAD and still only carries b.txt
+    let head = git(dir, &["rev-parse", "HEAD"]);
+    let base = fs::read_to_string(dir.join(".git/staging/.bases/two")).unwrap();
+    assert_eq!(base.trim(), head.trim());
+
+    let diff = git_stage_area(dir, &["diff", "two", "--", "--name-status"]);
+    assert_eq!(diff.trim(), "A\tb.txt");
+
+    git_stage_area(dir, &["commit", "two", "--", "-m", "Add b"]);
+    let tree = git(dir, &["ls-tree", "--name-only", "HEAD"]);
+    assert_eq!(tree.trim(), "README.md\na.txt\nb.txt");
+}
+
+#[test]
+fn rebase_merges_upstream_changes_to_the_same_path() {
+    let tmp = setup_repo();
+    let dir = tmp.path();
+    fs::write(dir.join("a.txt"), "1\n2\n3\n4\n5\n").unwrap();
+    git(dir, &["add", "a.txt"]);
+    git(dir, &["commit", "-m", "Add a"]);
+
+    fs::write(dir.join("a.txt"), "one\n2\n3\n4\n5\n").unwrap();
+    git_stage_area(dir, &["add", "one", "a.txt"]);
+
+    // HEAD changes another line of the same file
+    fs::write(dir.join("a.txt"), "1\n2\n3\n4\nfive\n").unwrap();
+    git(dir, &["commit", "-am", "Change five"]);
+
+    git_stage_area(dir, &["commit", "one", "--", "-m", "Change one"]);
+    assert_eq!(git(dir, &["show", "HEAD:a.txt"]), "one\n2\n3\n4\nfive\n");
+
+    // A conflicting upstream change is reported, not reverted
+    fs::write(dir.join("a.txt"), "ONE\n2\n3\n4\nfive\n").unwrap();
+    git_stage_area(dir, &["add", "two", "a.txt"]);
+    fs::write(dir.join("a.txt"), "uno\n2\n3\n4\nfive\n").unwrap();
+    git(dir, &["commit", "-am", "Change one again"]);
+
+    let binary = env!("CARGO_BIN_EXE_git-stage-area");
+    let output = Command::new(binary)
+        .args(["commit", "two", "--", "-m", "Conflict"])
+        .current_dir(dir)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(11), "conflict");
+    assert_eq!(git(dir, &["show", "HEAD:a.txt"]), "uno\n2\n3\n4\nfive\n");
+}
+
+#[test]
+fn drop_removes_area() {
+    let tmp = setup_repo();
+    let dir = tmp.path();
+
+    fs::write(dir.join("a.txt"), "a\n").unwrap();
+    git_stage_area(dir, &["add", "one", "a.txt"]);
+    git_stage_area(dir, &["drop", "one"]);
+
+    assert!(!dir.join(".git/staging/one").exists());
+    assert!(!dir.join(".git/staging/.bases/one").exists());
+    assert!(git_stage_area(dir, &["list"]).is_empty());
+}